@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use uuid::Uuid;
+use alert_net_server::common::models::area::Area;
+use alert_net_server::common::models::command::CommandKind;
+use alert_net_server::common::models::history::DetectionFilter;
+use alert_net_server::common::models::sensor::normalize_source;
+use alert_net_server::export::ExportFormat;
+
+/// Alert Net server. Settings are read from the environment or a `.env` file.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// Where detections and devices are kept
+    #[arg(long, value_enum, global = true, default_value_t)]
+    pub storage: StorageKind,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug)]
+pub enum StorageKind {
+    /// The database of `DATABASE_URL`
+    #[default]
+    Database,
+    /// In memory, lost on exit. For tests and demos, `DATABASE_URL` is not needed
+    Memory,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the server (default)
+    Serve,
+    /// Export detections, archived ones included, or incidents for a time range and areas
+    Export(ExportArgs),
+    /// Send a command to a connected device through the running server and print its answer
+    #[command(name = "command")]
+    Send(CommandArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value_t)]
+    pub records: ExportRecords,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ExportFormat,
+    /// Area slugs or names, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub areas: Vec<String>,
+    #[arg(long)]
+    pub device: Option<Uuid>,
+    #[arg(long)]
+    pub source: Option<String>,
+    /// Start of the time range (RFC 3339), inclusive
+    #[arg(long)]
+    pub from: Option<DateTime<Utc>>,
+    /// End of the time range (RFC 3339), exclusive
+    #[arg(long)]
+    pub to: Option<DateTime<Utc>>,
+    /// Write to this file instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, Debug)]
+pub enum ExportRecords {
+    /// Every detection the devices reported
+    #[default]
+    Detections,
+    /// The alarms the server raised, with the area that was alerted
+    Incidents,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CommandArgs {
+    /// Uuid of the device
+    pub device: Uuid,
+    #[arg(value_enum)]
+    pub command: CommandKind,
+    /// Seconds to wait for the answer, at most 30
+    #[arg(long, default_value_t = 10)]
+    pub wait: u64,
+    /// HTTP address of the server, defaults to `SERVER_ADDRESS` and `HTTP_PORT`
+    #[arg(long)]
+    pub server: Option<String>,
+}
+
+impl ExportArgs {
+    pub fn filter(&self) -> DetectionFilter {
+        DetectionFilter {
+            areas: self.areas.iter().map(|area| Area::slugify(area)).filter(|slug| !slug.is_empty()).collect(),
+            device: self.device,
+            source: self.source.as_deref().map(normalize_source),
+            from: self.from,
+            to: self.to,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use futures_channel::mpsc::UnboundedSender;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::warn;
+use tungstenite::http::Uri;
+use tungstenite::protocol::Message;
+use crate::common::route::Route;
+use crate::common::topic::{EventType, Topic};
+use crate::common::zone::ZoneTree;
+use crate::error::{Error, Result};
+
+pub type Tx = UnboundedSender<Message>;
+pub type PeerMap = Arc<Mutex<Vec<Client>>>;
+
+#[derive(Clone)]
+pub struct Client {
+    pub socket_addr: SocketAddr,
+    pub tx: Tx,
+    pub uri: Uri,
+    pub route: Route,
+    /// Area the client is located in, the origin of its detections and online/offline events.
+    pub area_id: Option<i64>,
+    pub topics: Vec<Topic>,
+    /// The device on the other end, known once it registered or acknowledged its config.
+    pub device_id: Option<i64>,
+    /// Exceeded the rate limit, its messages over the limit are dropped.
+    pub flooding: bool,
+}
+
+impl Client {
+    pub fn wants(&self, event: EventType, origin: Option<i64>, zones: &ZoneTree) -> bool {
+        self.topics.iter().any(|topic| topic.matches(event, origin, zones))
+    }
+
+    pub fn send(&self, message: Message) -> Result<()> {
+        self.tx.unbounded_send(message).map_err(|_| Error::PeerGone(self.socket_addr))
+    }
+
+    pub fn send_json<T: Serialize>(&self, value: &T) -> Result<()> {
+        self.send(Message::text(serde_json::to_string(value)?))
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct ClientOut {
+    pub socket_addr: String,
+    pub uri: String,
+    pub area_id: Option<i64>,
+    pub topics: Vec<Topic>,
+    pub device_id: Option<i64>,
+    pub flooding: bool,
+}
+
+impl From<&Client> for ClientOut {
+    fn from(client: &Client) -> Self {
+        ClientOut {
+            socket_addr: client.socket_addr.to_string(),
+            uri: client.uri.to_string(),
+            area_id: client.area_id,
+            topics: client.topics.clone(),
+            device_id: client.device_id,
+            flooding: client.flooding,
+        }
+    }
+}
+
+/// Sends a message to every client listening to the event in the area it originates from and returns
+/// how many received it. Clients that disconnected in the meantime are skipped.
+pub fn publish(peers: &[Client], event: EventType, origin: Option<i64>, zones: &ZoneTree, message: &Message) -> usize {
+    let mut delivered = 0;
+
+    for recipient in peers.iter().filter(|c| c.wants(event, origin, zones)) {
+        match recipient.send(message.clone()) {
+            Ok(()) => delivered += 1,
+            Err(err) => warn!(%err, ?event, "Could not publish event"),
+        }
+    }
+
+    delivered
+}
@@ -0,0 +1,92 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use chrono::Utc;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+use crate::common::models::command::{CommandRecord, CommandStatus};
+use crate::message::receive::command::CommandResponseMessage;
+
+/// How many commands are kept for the dashboard and CLI.
+const KEPT_COMMANDS: usize = 200;
+
+/// The recent commands sent to devices, shared by the message handler and the HTTP API.
+/// Kept in memory, commands are only meaningful while the device is connected.
+#[derive(Clone, Default)]
+pub struct CommandLog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Oldest first.
+    records: VecDeque<CommandRecord>,
+    waiting: HashMap<Uuid, Vec<oneshot::Sender<CommandRecord>>>,
+}
+
+impl CommandLog {
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn sent(&self, record: CommandRecord) {
+        let mut inner = self.inner();
+
+        if inner.records.len() == KEPT_COMMANDS {
+            if let Some(dropped) = inner.records.pop_front() {
+                inner.waiting.remove(&dropped.request_id);
+            }
+        }
+        inner.records.push_back(record);
+    }
+
+    /// Stores the answer of the device and hands it to everybody waiting for it.
+    /// `None` for an unknown request id, one that was answered before or one sent to another device.
+    pub fn answer(&self, response: &CommandResponseMessage, device_id: i64) -> Option<CommandRecord> {
+        let mut inner = self.inner();
+
+        let record = inner.records.iter_mut()
+            .find(|record| record.request_id == response.request_id && record.status == CommandStatus::Sent)
+            .filter(|record| record.device_id == device_id)?;
+
+        record.status = if response.ok { CommandStatus::Succeeded } else { CommandStatus::Failed };
+        record.answered_at = Some(Utc::now());
+        record.error = response.error.clone();
+        record.diagnostics = response.diagnostics.clone();
+        let record = record.clone();
+
+        for waiter in inner.waiting.remove(&record.request_id).unwrap_or_default() {
+            let _ = waiter.send(record.clone());
+        }
+
+        Some(record)
+    }
+
+    pub fn get(&self, request_id: Uuid) -> Option<CommandRecord> {
+        self.inner().records.iter().find(|record| record.request_id == request_id).cloned()
+    }
+
+    /// Newest first, of one device or all.
+    pub fn recent(&self, device_uuid: Option<Uuid>) -> Vec<CommandRecord> {
+        self.inner().records.iter()
+            .rev()
+            .filter(|record| device_uuid.is_none_or(|uuid| record.device_uuid == uuid))
+            .cloned()
+            .collect()
+    }
+
+    /// Completes with the answered record, or is dropped if the command falls out of the log.
+    pub fn wait(&self, request_id: Uuid) -> oneshot::Receiver<CommandRecord> {
+        let (tx, rx) = oneshot::channel();
+        let mut inner = self.inner();
+
+        match inner.records.iter().find(|record| record.request_id == request_id) {
+            Some(record) if record.status != CommandStatus::Sent => {
+                let _ = tx.send(record.clone());
+            }
+            Some(_) => inner.waiting.entry(request_id).or_default().push(tx),
+            None => {}
+        }
+
+        rx
+    }
+}
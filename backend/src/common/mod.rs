@@ -1 +1,4 @@
 pub mod models;
+pub mod route;
+pub mod topic;
+pub mod zone;
@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::common::route::normalized_segments;
+use crate::error::{Error, Result};
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type, Debug)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum ZoneKind {
+    Building,
+    Floor,
+    Room,
+}
+
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct Area {
+    pub id: i64,
+    pub slug: String,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    pub armed: bool,
+    pub notify: bool,
+    pub notification_topic: Option<String>,
+    pub notification_priority: i16,
+    pub kind: ZoneKind,
+    pub propagate: bool,
+}
+
+impl Area {
+    /// Normalizes an area name or connection path (`/Laden/`) into its slug (`laden`).
+    pub fn slugify(value: &str) -> String {
+        normalized_segments(value).join("/")
+    }
+
+    pub fn topic(&self) -> String {
+        match &self.notification_topic {
+            Some(topic) => topic.clone(),
+            None => format!("Alert-Net-{}", self.slug),
+        }
+    }
+}
+
+/// An edit of an area or zone. Name, kind and the switches are kept if they are missing,
+/// parent and notification topic are replaced, a missing one is removed.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct AreaChange {
+    /// Name or connection path of the area, it is created if it is unknown.
+    pub area: String,
+    pub name: Option<String>,
+    /// Name or path of the parent zone, which is created if it is unknown.
+    pub parent: Option<String>,
+    pub kind: Option<ZoneKind>,
+    pub propagate: Option<bool>,
+    pub armed: Option<bool>,
+    pub notify: Option<bool>,
+    pub notification_topic: Option<String>,
+    /// 1 = min to 5 = max, as ntfy.
+    pub notification_priority: Option<i16>,
+}
+
+impl AreaChange {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::InvalidConfig(message.to_string()));
+
+        if Area::slugify(&self.area).is_empty() {
+            return invalid("area must not be empty");
+        }
+        if self.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+            return invalid("name must not be empty");
+        }
+        if self.parent.as_deref().is_some_and(|parent| Area::slugify(parent).is_empty()) {
+            return invalid("parent must not be empty");
+        }
+        if self.notification_topic.as_deref().is_some_and(|topic| topic.trim().is_empty()) {
+            return invalid("notification_topic must not be empty");
+        }
+        if self.notification_priority.is_some_and(|priority| !(1..=5).contains(&priority)) {
+            return invalid("notification_priority must be between 1 and 5");
+        }
+
+        Ok(())
+    }
+
+    /// The area with the change applied, `parent_id` is the resolved `parent`.
+    pub fn apply(&self, area: &Area, parent_id: Option<i64>) -> Area {
+        Area {
+            id: area.id,
+            slug: area.slug.clone(),
+            name: self.name.as_deref().map_or_else(|| area.name.clone(), |name| name.trim().to_string()),
+            parent_id,
+            armed: self.armed.unwrap_or(area.armed),
+            notify: self.notify.unwrap_or(area.notify),
+            notification_topic: self.notification_topic.as_deref().map(|topic| topic.trim().to_string()),
+            notification_priority: self.notification_priority.unwrap_or(area.notification_priority),
+            kind: self.kind.unwrap_or(area.kind),
+            propagate: self.propagate.unwrap_or(area.propagate),
+        }
+    }
+}
+
+/// A stored slug that `Area::slugify` would write differently, e.g. `kÜche` from the SQL backfill of the areas.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SlugFix {
+    /// The area gets the normalized slug.
+    Rename { id: i64, slug: String },
+    /// Another area already has the normalized slug, the area is merged into it.
+    Merge { from: i64, into: i64 },
+}
+
+/// The fixes that bring the stored slugs in line with `Area::slugify`, in the order they have to be applied.
+pub fn slug_fixes(areas: &[Area]) -> Vec<SlugFix> {
+    let mut ids: HashMap<String, i64> = areas.iter().map(|area| (area.slug.clone(), area.id)).collect();
+    let mut fixes = Vec::new();
+
+    for area in areas {
+        let slug = Area::slugify(&area.slug);
+        if slug == area.slug {
+            continue;
+        }
+
+        ids.remove(&area.slug);
+        match ids.get(&slug) {
+            Some(&into) => fixes.push(SlugFix::Merge { from: area.id, into }),
+            None => {
+                ids.insert(slug.clone(), area.id);
+                fixes.push(SlugFix::Rename { id: area.id, slug });
+            }
+        }
+    }
+
+    fixes
+}
@@ -0,0 +1,48 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What a device is asked to do.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    Reboot,
+    /// Blink the LED and play the chime, to find the device.
+    Identify,
+    /// Play the alarm sound once.
+    SelfTest,
+    /// Report free heap, RSSI, uptime and firmware version.
+    Diagnostics,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Diagnostics {
+    pub free_heap: u32,
+    /// WiFi signal strength in dBm.
+    pub rssi: i32,
+    pub uptime_s: u64,
+    pub firmware_version: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandStatus {
+    /// Delivered to the connection, no answer yet.
+    Sent,
+    Succeeded,
+    Failed,
+}
+
+/// A command sent to a device and the answer it got.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CommandRecord {
+    pub request_id: Uuid,
+    pub device_id: i64,
+    pub device_uuid: Uuid,
+    pub command: CommandKind,
+    pub status: CommandStatus,
+    pub sent_at: DateTime<Utc>,
+    pub answered_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+    pub diagnostics: Option<Diagnostics>,
+}
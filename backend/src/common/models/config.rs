@@ -0,0 +1,80 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, Result};
+
+/// Highest volume of the DFPlayer.
+pub const MAX_SPEAKER_VOLUME: i16 = 30;
+
+/// Configuration the server pushes to a device, with the version the device acknowledged.
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct DeviceConfig {
+    pub device_id: i64,
+    /// Grows with each edit.
+    pub version: i64,
+    pub description: String,
+    /// Slug of the area.
+    pub area: String,
+    /// Settings that are `None` are left to the device.
+    pub speaker_volume: Option<i16>,
+    pub motion_debounce_ms: Option<i32>,
+    pub heartbeat_interval_s: Option<i32>,
+    /// `host:port` of the server the device connects to.
+    pub server_address: Option<String>,
+    pub updated_at: DateTime<Utc>,
+    /// Last version the device reported as applied.
+    pub applied_version: Option<i64>,
+    pub applied_at: Option<DateTime<Utc>>,
+}
+
+impl DeviceConfig {
+    /// Whether the device runs the current version.
+    pub fn applied(&self) -> bool {
+        self.applied_version == Some(self.version)
+    }
+}
+
+/// An edit of the configuration. Description and area are kept if they are missing,
+/// the settings are replaced, a missing one is left to the device from now on.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ConfigChange {
+    pub description: Option<String>,
+    pub area: Option<String>,
+    pub speaker_volume: Option<i16>,
+    pub motion_debounce_ms: Option<i32>,
+    pub heartbeat_interval_s: Option<i32>,
+    pub server_address: Option<String>,
+}
+
+impl ConfigChange {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::InvalidConfig(message.to_string()));
+
+        if self.description.as_deref().is_some_and(|description| description.trim().is_empty()) {
+            return invalid("description must not be empty");
+        }
+        if self.area.as_deref().is_some_and(|area| area.trim().trim_matches('/').is_empty()) {
+            return invalid("area must not be empty");
+        }
+        if self.speaker_volume.is_some_and(|volume| !(0..=MAX_SPEAKER_VOLUME).contains(&volume)) {
+            return invalid("speaker_volume must be between 0 and 30");
+        }
+        if self.motion_debounce_ms.is_some_and(|debounce| debounce < 0) {
+            return invalid("motion_debounce_ms must not be negative");
+        }
+        if self.heartbeat_interval_s.is_some_and(|interval| interval < 1) {
+            return invalid("heartbeat_interval_s must be at least 1");
+        }
+        if self.server_address.as_deref().is_some_and(|address| !valid_address(address)) {
+            return invalid("server_address must be host:port");
+        }
+
+        Ok(())
+    }
+}
+
+fn valid_address(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok_and(|port| port > 0),
+        None => false,
+    }
+}
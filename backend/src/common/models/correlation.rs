@@ -0,0 +1,131 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::common::models::sensor::normalized_source;
+use crate::error::{Error, Result};
+
+const DEFAULT_PRE_ALARM_LIMIT: i64 = 100;
+const MAX_PRE_ALARM_LIMIT: i64 = 1000;
+/// Longest correlation window, a day.
+const MAX_WINDOW_S: i32 = 24 * 60 * 60;
+
+/// What an area needs within the window before a detection raises the full alarm.
+/// Detections short of it raise a pre-alarm. Either part alone is enough to alarm.
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct CorrelationRule {
+    pub area_id: i64,
+    /// Slug of the area.
+    pub area: String,
+    /// Detections within the window, `None` if only the sequence counts.
+    pub min_detections: Option<i32>,
+    /// Distinct devices among these detections.
+    pub min_devices: i32,
+    pub window_s: i32,
+    /// A detection of `then_source` after one of `first_source` alarms, like a door contact followed by motion.
+    pub first_source: Option<String>,
+    pub then_source: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl CorrelationRule {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_s.max(1) as u64)
+    }
+}
+
+/// The rule of an area, replacing the previous one.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RuleChange {
+    pub area: String,
+    pub min_detections: Option<i32>,
+    #[serde(default = "default_min_devices")]
+    pub min_devices: i32,
+    #[serde(default = "default_window_s")]
+    pub window_s: i32,
+    /// Normalized like stored sources, so `Tür` matches the detections of a door contact.
+    #[serde(default, deserialize_with = "normalized_source")]
+    pub first_source: Option<String>,
+    #[serde(default, deserialize_with = "normalized_source")]
+    pub then_source: Option<String>,
+}
+
+fn default_min_devices() -> i32 {
+    1
+}
+
+fn default_window_s() -> i32 {
+    60
+}
+
+impl RuleChange {
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(Error::InvalidConfig(message.to_string()));
+        let blank = |source: &Option<String>| source.as_deref().is_none_or(|source| source.trim().is_empty());
+
+        if self.area.trim().trim_matches('/').is_empty() {
+            return invalid("area must not be empty");
+        }
+        if self.min_detections.is_some_and(|detections| detections < 1) {
+            return invalid("min_detections must be at least 1");
+        }
+        if self.min_devices < 1 || self.min_detections.is_some_and(|detections| self.min_devices > detections) {
+            return invalid("min_devices must be between 1 and min_detections");
+        }
+        if !(1..=MAX_WINDOW_S).contains(&self.window_s) {
+            return invalid("window_s must be between 1 and 86400");
+        }
+        if blank(&self.first_source) != blank(&self.then_source) {
+            return invalid("first_source and then_source must be set together");
+        }
+        if self.min_detections.is_none() && blank(&self.first_source) {
+            return invalid("min_detections or first_source and then_source must be set");
+        }
+
+        Ok(())
+    }
+}
+
+/// A detection that didn't satisfy the correlation rule of its area, with how far the area got.
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct PreAlarm {
+    pub id: i64,
+    pub area_id: i64,
+    /// Slug of the area.
+    pub area: String,
+    pub device_id: i64,
+    pub source: String,
+    pub timestamp: DateTime<Utc>,
+    /// Detections within the window, this one included.
+    pub detections: i32,
+    /// Distinct devices among them.
+    pub devices: i32,
+}
+
+/// A pre-alarm to be stored, the id is assigned by the storage.
+#[derive(Clone, Debug)]
+pub struct NewPreAlarm {
+    pub area_id: i64,
+    pub device_id: i64,
+    pub source: String,
+    pub timestamp: DateTime<Utc>,
+    pub detections: i32,
+    pub devices: i32,
+}
+
+/// Selects pre-alarms by area and time range, newest first.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct PreAlarmQuery {
+    /// Area slug.
+    pub area: Option<String>,
+    /// Inclusive start of the time range.
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive end of the time range.
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+impl PreAlarmQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PRE_ALARM_LIMIT).clamp(1, MAX_PRE_ALARM_LIMIT)
+    }
+}
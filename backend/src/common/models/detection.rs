@@ -1,11 +1,26 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use crate::common::models::device::Device;
+use crate::common::models::sensor::{ContactState, SensorKind};
 
 #[derive(Deserialize, Serialize, sqlx::FromRow, Debug)]
 pub struct Detection {
     pub id: i64,
     pub device: Device,
+    /// Normalized, see `normalize_source`.
     pub source: String,
+    pub kind: SensorKind,
+    pub state: Option<ContactState>,
+    pub timestamp: DateTime<Utc>,
+    /// Detections of the same device and source within the debounce window, this one included.
+    pub count: i32,
+}
+
+/// A detection to be stored, the id is assigned by the storage.
+#[derive(Clone, Debug)]
+pub struct NewDetection {
+    pub device_id: i64,
+    pub source: String,
+    pub state: Option<ContactState>,
     pub timestamp: DateTime<Utc>,
 }
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -6,5 +7,52 @@ pub struct Device {
     pub id: i64,
     pub uuid: Uuid,
     pub description: String,
+    /// Slug of the area, kept on the wire so devices can keep sending the name they were configured with.
     pub area: String,
+    #[serde(default)]
+    pub area_id: i64,
+}
+
+/// A device to be stored, the id and area id are assigned by the storage.
+#[derive(Clone, Debug)]
+pub struct NewDevice {
+    pub uuid: Uuid,
+    pub description: String,
+    /// Area name or slug, created if it is unknown.
+    pub area: String,
+    /// Identifies the device across registrations, see `RegisterMessage`.
+    pub hardware_id: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// Result of a registration.
+#[derive(Clone, Debug)]
+pub struct Registration {
+    pub device: Device,
+    /// False if the device was already registered with the same hardware or request id.
+    pub created: bool,
+}
+
+/// A device waiting for an admin to approve it, see `DEVICE_APPROVAL`.
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct PendingDevice {
+    pub id: i64,
+    pub description: String,
+    /// The area the device asked for.
+    pub area: String,
+    pub hardware_id: Option<String>,
+    pub request_id: Option<String>,
+    /// Shown on the serial console of the device, the admin can confirm it on approval.
+    #[serde(skip_serializing, default)]
+    pub pairing_code: String,
+    pub requested_at: DateTime<Utc>,
+}
+
+/// Result of a registration while approval is required.
+#[derive(Clone, Debug)]
+pub enum Provisioning {
+    /// The device was approved before.
+    Known(Device),
+    /// The device waits for approval, a repeated registration gets the same entry.
+    Pending(PendingDevice),
 }
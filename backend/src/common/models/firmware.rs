@@ -0,0 +1,120 @@
+use chrono::{DateTime, Utc};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A firmware image, without the image itself.
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct Firmware {
+    pub id: i64,
+    pub version: String,
+    /// Board the image is built for, e.g. `d1_mini`.
+    pub board: String,
+    /// In bytes.
+    pub size: i64,
+    pub sha256: String,
+    /// ESP8266httpUpdate checks the MD5 the server sends in the `x-MD5` header.
+    pub md5: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// A firmware image to be stored, the id is assigned by the storage.
+#[derive(Clone, Debug)]
+pub struct NewFirmware {
+    pub version: String,
+    pub board: String,
+    pub sha256: String,
+    pub md5: String,
+    pub image: Vec<u8>,
+}
+
+impl NewFirmware {
+    pub fn new(version: &str, board: &str, image: Vec<u8>) -> Self {
+        NewFirmware {
+            version: version.trim().to_string(),
+            board: board.trim().to_string(),
+            sha256: format!("{:x}", Sha256::digest(&image)),
+            md5: format!("{:x}", Md5::digest(&image)),
+            image,
+        }
+    }
+
+    pub fn size(&self) -> i64 {
+        self.image.len() as i64
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type, Debug)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum UpdateStatus {
+    /// Belongs to a later stage of the rollout.
+    Waiting,
+    /// The device was told about the update, or is told when it connects.
+    Offered,
+    Downloading,
+    Succeeded,
+    Failed,
+}
+
+/// The update of one device in a rollout.
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct FirmwareUpdate {
+    pub rollout_id: i64,
+    pub device_id: i64,
+    pub stage: i32,
+    pub status: UpdateStatus,
+    pub error: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A firmware offered to a set of devices in stages of `batch_size` devices. The next stage starts when
+/// every device of the current one succeeded, a failed update halts the rollout until it is advanced by hand.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Rollout {
+    pub id: i64,
+    pub firmware: Firmware,
+    pub batch_size: i32,
+    /// The stage that is offered, stages before it are done.
+    pub stage: i32,
+    pub created_at: DateTime<Utc>,
+    pub updates: Vec<FirmwareUpdate>,
+}
+
+impl Rollout {
+    /// True if every device of the current stage succeeded and there are devices left.
+    pub fn stage_done(&self) -> bool {
+        self.updates.iter().any(|update| update.stage > self.stage)
+            && self.updates.iter()
+                .filter(|update| update.stage == self.stage)
+                .all(|update| update.status == UpdateStatus::Succeeded)
+    }
+
+    /// Devices of the current stage that still have to install the update.
+    pub fn offered(&self) -> impl Iterator<Item = &FirmwareUpdate> {
+        self.updates.iter().filter(|update| update.status == UpdateStatus::Offered)
+    }
+}
+
+/// What is needed to start a rollout.
+#[derive(Clone, Debug)]
+pub struct NewRollout {
+    pub firmware_id: i64,
+    /// The devices in the order they are updated.
+    pub device_ids: Vec<i64>,
+    pub batch_size: i32,
+}
+
+impl NewRollout {
+    /// Stage of the device at `index`, starting with 1.
+    pub fn stage_of(&self, index: usize) -> i32 {
+        index as i32 / self.batch_size.max(1) + 1
+    }
+}
+
+/// An update offered to a device, sent again when the device connects until it reports back.
+#[derive(Clone, Debug)]
+pub struct UpdateOffer {
+    pub rollout_id: i64,
+    pub firmware: Firmware,
+}
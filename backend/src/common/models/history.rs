@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+use crate::common::models::area::Area;
+use crate::common::models::detection::Detection;
+use crate::common::models::device::Device;
+use crate::common::models::sensor::{normalized_source, ContactState};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 100;
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Selects detections by area, device, source and time range. Unset fields don't filter.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct DetectionFilter {
+    /// Area slugs, comma separated in query strings (`areas=laden,lager`).
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub areas: Vec<String>,
+    pub device: Option<Uuid>,
+    /// Normalized like stored sources, so `Bewegung` finds `motion`.
+    #[serde(default, deserialize_with = "normalized_source")]
+    pub source: Option<String>,
+    /// Inclusive start of the time range.
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive end of the time range.
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Detections are paged newest first, the cursor is the id of the last detection of the previous page.
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+pub struct PageRequest {
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl PageRequest {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct DetectionPage {
+    pub detections: Vec<Detection>,
+    /// Cursor for the next page, `None` on the last page.
+    pub next_cursor: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl Bucket {
+    /// Field name for `date_trunc`.
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Bucket::Hour => "hour",
+            Bucket::Day => "day",
+        }
+    }
+}
+
+/// Number of detections of an area within one hour or day.
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct AreaCount {
+    pub area: String,
+    pub bucket: DateTime<Utc>,
+    pub count: i64,
+}
+
+/// Device with the number of detections it reported.
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct DeviceCount {
+    #[sqlx(flatten)]
+    pub device: Device,
+    pub count: i64,
+}
+
+/// Flat detection record for exports, with the names people recognize.
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct ExportRecord {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub source: String,
+    pub device_uuid: Uuid,
+    pub device_description: String,
+    pub area: String,
+    pub area_name: String,
+    pub count: i32,
+    pub state: Option<ContactState>,
+}
+
+fn comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+
+    Ok(value.split(',')
+        .map(Area::slugify)
+        .filter(|slug| !slug.is_empty())
+        .collect())
+}
@@ -0,0 +1,48 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::common::models::sensor::ContactState;
+
+/// An alarm the server raised for a detection, kept when the retention removes the detection.
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct Incident {
+    pub id: i64,
+    /// The area of the connection, whose clients were alerted.
+    pub area_id: i64,
+    /// Slug of the area.
+    pub area: String,
+    pub device_id: i64,
+    /// `None` if the detection could not be stored.
+    pub detection_id: Option<i64>,
+    pub source: String,
+    pub state: Option<ContactState>,
+    /// Sirens and clients the alert was sent to.
+    pub recipients: i32,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// An incident to be stored, the id is assigned by the storage.
+#[derive(Clone, Debug)]
+pub struct NewIncident {
+    pub area_id: i64,
+    pub device_id: i64,
+    pub detection_id: Option<i64>,
+    pub source: String,
+    pub state: Option<ContactState>,
+    pub recipients: i32,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Flat incident record for exports, like `ExportRecord`.
+#[derive(Serialize, sqlx::FromRow, Debug)]
+pub struct IncidentRecord {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub source: String,
+    pub device_uuid: Uuid,
+    pub device_description: String,
+    pub area: String,
+    pub area_name: String,
+    pub recipients: i32,
+    pub state: Option<ContactState>,
+}
@@ -1,2 +1,11 @@
+pub mod area;
+pub mod command;
+pub mod config;
+pub mod correlation;
 pub mod device;
+pub mod firmware;
 pub mod detection;
+pub mod history;
+pub mod incident;
+pub mod sensor;
+pub mod telemetry;
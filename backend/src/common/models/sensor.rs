@@ -0,0 +1,153 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// What kind of sensor raised a detection, derived from its source.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorKind {
+    Motion,
+    /// Door or window contact.
+    Contact,
+    GlassBreak,
+    Smoke,
+    WaterLeak,
+    PanicButton,
+    /// The housing of a device was opened or it was torn off.
+    Tamper,
+    /// A source this server doesn't know yet, it is stored and alarms like motion.
+    Unknown,
+}
+
+impl SensorKind {
+    /// The kind of a source as sent by the devices, `Bewegung` and `PIR` are motion.
+    pub fn of(source: &str) -> SensorKind {
+        match normalized(source).as_str() {
+            "motion" | "bewegung" | "pir" | "radar" | "presence" | "präsenz" => SensorKind::Motion,
+            "contact" | "kontakt" | "door" | "window" | "tür" | "tuer" | "fenster" | "reed"
+            | "door_contact" | "window_contact" | "türkontakt" | "fensterkontakt" => SensorKind::Contact,
+            "glass_break" | "glassbreak" | "glass" | "glasbruch" => SensorKind::GlassBreak,
+            "smoke" | "rauch" | "rauchmelder" | "fire" | "feuer" | "brand" => SensorKind::Smoke,
+            "water_leak" | "water" | "leak" | "wasser" | "leck" | "wassermelder" => SensorKind::WaterLeak,
+            "panic_button" | "panic" | "panik" | "sos" | "notruf" | "emergency" => SensorKind::PanicButton,
+            "tamper" | "sabotage" => SensorKind::Tamper,
+            _ => SensorKind::Unknown,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SensorKind::Motion => "motion",
+            SensorKind::Contact => "contact",
+            SensorKind::GlassBreak => "glass_break",
+            SensorKind::Smoke => "smoke",
+            SensorKind::WaterLeak => "water_leak",
+            SensorKind::PanicButton => "panic_button",
+            SensorKind::Tamper => "tamper",
+            SensorKind::Unknown => "unknown",
+        }
+    }
+
+    /// Name in notifications, `None` for unknown sources which are shown as sent.
+    pub fn label(&self) -> Option<&'static str> {
+        match self {
+            SensorKind::Motion => Some("Bewegung"),
+            SensorKind::Contact => Some("Tür/Fenster"),
+            SensorKind::GlassBreak => Some("Glasbruch"),
+            SensorKind::Smoke => Some("Rauch"),
+            SensorKind::WaterLeak => Some("Wasser"),
+            SensorKind::PanicButton => Some("Notruf"),
+            SensorKind::Tamper => Some("Sabotage"),
+            SensorKind::Unknown => None,
+        }
+    }
+
+    /// Kinds that alarm around the clock. They alarm in disarmed areas and skip the correlation rule.
+    pub fn always_armed(&self) -> bool {
+        matches!(self, SensorKind::Smoke | SensorKind::WaterLeak | SensorKind::PanicButton | SensorKind::Tamper)
+    }
+
+    /// Title of the notification in place of the area alone, for the kinds that aren't a burglary.
+    pub fn alarm_title(&self) -> Option<&'static str> {
+        match self {
+            SensorKind::Smoke => Some("Rauchalarm"),
+            SensorKind::WaterLeak => Some("Wasseralarm"),
+            SensorKind::PanicButton => Some("Notruf"),
+            SensorKind::Tamper => Some("Sabotagealarm"),
+            _ => None,
+        }
+    }
+
+    /// The lowest notification priority of the kind, 1 to 5 like the priority of an area which can only raise it.
+    pub fn priority(&self) -> i16 {
+        match self {
+            SensorKind::Smoke | SensorKind::PanicButton => 5,
+            SensorKind::WaterLeak | SensorKind::Tamper => 4,
+            _ => 1,
+        }
+    }
+}
+
+/// Stored form of a source: the kind for known sources, otherwise the source in lower case.
+/// So `Motion`, `Bewegung` and `pir` are all stored as `motion`.
+pub fn normalize_source(source: &str) -> String {
+    match SensorKind::of(source) {
+        SensorKind::Unknown => normalized(source),
+        kind => kind.as_str().to_string(),
+    }
+}
+
+/// A detection as shown in notifications, like `Tür/Fenster geöffnet`.
+pub fn describe(source: &str, state: Option<ContactState>) -> String {
+    let name = SensorKind::of(source).label().map_or_else(|| source.to_string(), str::to_string);
+
+    match state {
+        Some(state) => format!("{} {}", name, state.label()),
+        None => name,
+    }
+}
+
+/// Deserializes an optional source into its stored form, for filters and rules.
+pub fn normalized_source<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)?.map(|source| normalize_source(&source)))
+}
+
+fn normalized(source: &str) -> String {
+    source.trim().to_lowercase().replace([' ', '-'], "_")
+}
+
+/// Reported by contacts along with the detection.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, sqlx::Type, Debug)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "text", rename_all = "lowercase")]
+pub enum ContactState {
+    Open,
+    /// The contact closed again, stored without alarming.
+    Closed,
+}
+
+impl ContactState {
+    /// `None` for states this server doesn't know, the detection is kept without its state.
+    pub fn parse(state: &str) -> Option<ContactState> {
+        match normalized(state).as_str() {
+            "open" | "opened" | "offen" | "geöffnet" => Some(ContactState::Open),
+            "closed" | "geschlossen" => Some(ContactState::Closed),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContactState::Open => "open",
+            ContactState::Closed => "closed",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ContactState::Open => "geöffnet",
+            ContactState::Closed => "geschlossen",
+        }
+    }
+}
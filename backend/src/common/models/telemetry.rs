@@ -0,0 +1,66 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::common::models::device::Device;
+
+pub const DEFAULT_TELEMETRY_LIMIT: i64 = 100;
+pub const MAX_TELEMETRY_LIMIT: i64 = 10_000;
+
+/// What a device reports about itself. Every value is optional, older firmware and other boards send less.
+#[derive(Deserialize, Serialize, Clone, Default, PartialEq, Debug)]
+pub struct TelemetryReport {
+    /// WiFi signal strength in dBm.
+    pub rssi: Option<i32>,
+    /// In bytes.
+    pub free_heap: Option<i64>,
+    pub uptime_s: Option<i64>,
+    /// As the ESP names it, e.g. `Power On` or `Hardware Watchdog`.
+    pub reset_reason: Option<String>,
+    pub firmware_version: Option<String>,
+    /// In volts.
+    pub supply_voltage: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, sqlx::FromRow, Debug)]
+pub struct Telemetry {
+    pub id: i64,
+    pub device_id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub rssi: Option<i32>,
+    pub free_heap: Option<i64>,
+    pub uptime_s: Option<i64>,
+    pub reset_reason: Option<String>,
+    pub firmware_version: Option<String>,
+    pub supply_voltage: Option<f64>,
+}
+
+/// Telemetry to be stored, the id is assigned by the storage.
+#[derive(Clone, Debug)]
+pub struct NewTelemetry {
+    pub device_id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub report: TelemetryReport,
+}
+
+/// A device with the latest telemetry it sent, `None` if it never sent any.
+#[derive(Serialize, Clone, Debug)]
+pub struct DeviceStatus {
+    #[serde(flatten)]
+    pub device: Device,
+    pub telemetry: Option<Telemetry>,
+}
+
+/// Selects the telemetry of a device by time range, newest first.
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+pub struct TelemetryQuery {
+    /// Inclusive start of the time range.
+    pub from: Option<DateTime<Utc>>,
+    /// Exclusive end of the time range.
+    pub to: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+impl TelemetryQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_TELEMETRY_LIMIT).clamp(1, MAX_TELEMETRY_LIMIT)
+    }
+}
@@ -0,0 +1,84 @@
+/// The kind of client, derived from the path it connected with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// `/`, a client without a route.
+    Root,
+    /// `/all`, observes every alert of the zone tree.
+    All,
+    /// `/ui`, the dashboard.
+    Ui,
+    /// `/zone/<slug>`, observes the subtree of a zone.
+    Zone(String),
+    /// `/<slug>`, sirens and sensors of an area.
+    Area(String),
+}
+
+impl Route {
+    /// Parses a connection path. Query strings, empty segments and trailing slashes are ignored,
+    /// percent-encoded characters are decoded and the result is lowercase, so `/Laden/?id=1` is `/laden`.
+    pub fn parse(path: &str) -> Route {
+        let segments = normalized_segments(path);
+
+        match segments.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+            [] => Route::Root,
+            ["all"] => Route::All,
+            ["ui"] => Route::Ui,
+            ["zone", zone @ ..] if !zone.is_empty() => Route::Zone(zone.join("/")),
+            area => Route::Area(area.join("/")),
+        }
+    }
+
+    /// The slug of the area or zone the route points to.
+    pub fn slug(&self) -> Option<&str> {
+        match self {
+            Route::Zone(slug) | Route::Area(slug) => Some(slug),
+            _ => None,
+        }
+    }
+
+    /// Name of the route kind, e.g. for logs and metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Route::Root => "root",
+            Route::All => "all",
+            Route::Ui => "ui",
+            Route::Zone(_) => "zone",
+            Route::Area(_) => "area",
+        }
+    }
+}
+
+/// Splits a path into lowercase, percent-decoded segments, dropping the query string and empty segments.
+pub fn normalized_segments(path: &str) -> Vec<String> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+
+    path.split('/')
+        .map(|segment| percent_decode(segment.trim()).to_lowercase())
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
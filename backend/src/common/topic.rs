@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use crate::common::zone::{Subscription, ZoneTree};
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum EventType {
+    /// Alerts raised by detections.
+    Detection,
+    /// Server status notices.
+    Status,
+    /// Devices coming online or going offline.
+    Device,
+    /// Detections short of the correlation rule of their area.
+    PreAlarm,
+}
+
+impl EventType {
+    pub fn all() -> Vec<EventType> {
+        vec![EventType::Detection, EventType::Status, EventType::Device, EventType::PreAlarm]
+    }
+}
+
+/// An event type in a part of the zone tree a client listens to.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Topic {
+    pub event: EventType,
+    pub subscription: Subscription,
+}
+
+impl Topic {
+    /// Checks if an event raised in the origin area belongs to this topic. Events without an
+    /// area only reach subscriptions of the whole tree, status events reach every subscription.
+    pub fn matches(&self, event: EventType, origin: Option<i64>, zones: &ZoneTree) -> bool {
+        if self.event != event {
+            return false;
+        }
+
+        match (event, origin) {
+            (EventType::Status, _) => true,
+            (_, Some(origin)) => self.subscription.matches(origin, zones),
+            (_, None) => self.subscription == Subscription::Subtree(None),
+        }
+    }
+}
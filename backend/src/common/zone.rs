@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use crate::common::models::area::Area;
+
+/// What a client listens to in the zone tree.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase", tag = "type", content = "area_id")]
+pub enum Subscription {
+    /// Sirens of a zone: alerts of the zone itself and alerts propagated up to it.
+    Node(i64),
+    /// Observers of a zone: every alert raised within its subtree. `None` is the whole tree.
+    Subtree(Option<i64>),
+}
+
+impl Subscription {
+    pub fn area_id(&self) -> Option<i64> {
+        match self {
+            Subscription::Node(id) => Some(*id),
+            Subscription::Subtree(id) => *id,
+        }
+    }
+
+    pub fn matches(&self, origin: i64, zones: &ZoneTree) -> bool {
+        match self {
+            Subscription::Node(id) => zones.alert_targets(origin).contains(id),
+            Subscription::Subtree(None) => true,
+            Subscription::Subtree(Some(id)) => zones.ancestors(origin).contains(id),
+        }
+    }
+}
+
+/// In-memory view of the area table as a tree, used for alert routing.
+#[derive(Default)]
+pub struct ZoneTree {
+    areas: HashMap<i64, Area>,
+}
+
+impl ZoneTree {
+    pub fn new(areas: Vec<Area>) -> Self {
+        ZoneTree {
+            areas: areas.into_iter().map(|area| (area.id, area)).collect(),
+        }
+    }
+
+    pub fn get(&self, id: i64) -> Option<&Area> {
+        self.areas.get(&id)
+    }
+
+    /// The area itself followed by its parents up to the root.
+    pub fn ancestors(&self, id: i64) -> Vec<i64> {
+        let mut ids = vec![id];
+        let mut current = self.areas.get(&id);
+
+        while let Some(parent_id) = current.and_then(|area| area.parent_id) {
+            // A misconfigured parent loop must not hang the routing
+            if ids.contains(&parent_id) {
+                break;
+            }
+
+            ids.push(parent_id);
+            current = self.areas.get(&parent_id);
+        }
+
+        ids
+    }
+
+    /// The zones whose sirens go off for an alert in the given area: the area itself and
+    /// every parent reached while the zones on the way are set to propagate.
+    pub fn alert_targets(&self, id: i64) -> Vec<i64> {
+        let ancestors = self.ancestors(id);
+        let mut targets = vec![id];
+
+        for pair in ancestors.windows(2) {
+            match self.areas.get(&pair[0]) {
+                Some(area) if area.propagate => targets.push(pair[1]),
+                _ => break,
+            }
+        }
+
+        targets
+    }
+}
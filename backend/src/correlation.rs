@@ -0,0 +1,64 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Instant;
+use crate::common::models::correlation::CorrelationRule;
+use crate::common::models::sensor::normalize_source;
+
+/// How a detection in an area with a correlation rule is treated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Verdict {
+    Alarm,
+    /// The rule isn't satisfied yet, with the detections and distinct devices within the window.
+    PreAlarm {
+        detections: usize,
+        devices: usize,
+    },
+}
+
+struct Recent {
+    at: Instant,
+    device_id: i64,
+    source: String,
+}
+
+/// Recent detections by area, to check them against the correlation rule of their area.
+/// Detections that raised the alarm stay counted, so an ongoing intrusion keeps alarming.
+#[derive(Default)]
+pub struct Correlator {
+    recent: HashMap<i64, VecDeque<Recent>>,
+}
+
+impl Correlator {
+    pub fn detect(&mut self, rule: &CorrelationRule, device_id: i64, source: &str, now: Instant) -> Verdict {
+        let window = rule.window();
+        let recent = self.recent.entry(rule.area_id).or_default();
+
+        while recent.front().is_some_and(|detection| now.duration_since(detection.at) >= window) {
+            recent.pop_front();
+        }
+
+        // The sequence needs its first source before this detection
+        let sequence = match (&rule.first_source, &rule.then_source) {
+            (Some(first), Some(then)) => {
+                same_source(source, then) && recent.iter().any(|detection| same_source(&detection.source, first))
+            }
+            _ => false,
+        };
+
+        recent.push_back(Recent { at: now, device_id, source: source.to_string() });
+
+        let detections = recent.len();
+        let devices = recent.iter().map(|detection| detection.device_id).collect::<HashSet<_>>().len();
+        let count = rule.min_detections.is_some_and(|min| detections >= min as usize && devices >= rule.min_devices as usize);
+
+        if sequence || count {
+            Verdict::Alarm
+        } else {
+            Verdict::PreAlarm { detections, devices }
+        }
+    }
+}
+
+/// Compares the sensor kinds, so a rule for `door` matches a detection of `Tür`.
+fn same_source(source: &str, rule: &str) -> bool {
+    normalize_source(source) == normalize_source(rule)
+}
@@ -0,0 +1,120 @@
+use sqlx::{Error, PgConnection};
+use crate::common::models::area::{Area, ZoneKind};
+use crate::database::Repository;
+
+pub struct AreaRepository;
+
+impl Repository for AreaRepository {
+    type Model = Area;
+    /// Settings of the new area, its id is ignored.
+    type New = Area;
+
+    async fn insert(area: &Area, con: &mut PgConnection) -> Result<Area, Error> {
+        sqlx::query_as!(
+            Area,
+            r#"INSERT INTO area (slug, name, parent_id, armed, notify, notification_topic, notification_priority, kind, propagate)
+               VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+               RETURNING id, slug, name, parent_id, armed, notify, notification_topic, notification_priority, kind AS "kind: ZoneKind", propagate"#,
+            area.slug,
+            area.name,
+            area.parent_id,
+            area.armed,
+            area.notify,
+            area.notification_topic,
+            area.notification_priority,
+            area.kind as ZoneKind,
+            area.propagate,
+        )
+            .fetch_one(con).await
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Area>, Error> {
+        sqlx::query_as!(
+            Area,
+            r#"SELECT id, slug, name, parent_id, armed, notify, notification_topic, notification_priority, kind AS "kind: ZoneKind", propagate
+               FROM area WHERE id = $1"#,
+            id,
+        )
+            .fetch_optional(con).await
+    }
+}
+
+impl AreaRepository {
+    pub async fn update(area: &Area, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE area SET slug = $1, name = $2, parent_id = $3, armed = $4, notify = $5, notification_topic = $6, notification_priority = $7, kind = $8, propagate = $9
+             WHERE id = $10",
+            area.slug,
+            area.name,
+            area.parent_id,
+            area.armed,
+            area.notify,
+            area.notification_topic,
+            area.notification_priority,
+            area.kind as ZoneKind,
+            area.propagate,
+            area.id,
+        )
+            .execute(con).await?;
+
+        Ok(())
+    }
+
+    pub async fn get_all(con: &mut PgConnection) -> Result<Vec<Area>, Error> {
+        sqlx::query_as!(
+            Area,
+            r#"SELECT id, slug, name, parent_id, armed, notify, notification_topic, notification_priority, kind AS "kind: ZoneKind", propagate
+               FROM area ORDER BY id"#,
+        )
+            .fetch_all(con).await
+    }
+
+    /// Resolves an area by name or connection path, creating it with default settings if it is unknown.
+    pub async fn get_or_create(name: &str, con: &mut PgConnection) -> Result<Area, Error> {
+        let slug = Area::slugify(name);
+
+        // Insert and select in one statement, so two connections for a new area can't create it twice
+        sqlx::query_as!(
+            Area,
+            r#"WITH inserted AS (INSERT INTO area (slug, name) VALUES ($1, $2) ON CONFLICT (slug) DO NOTHING RETURNING *)
+               SELECT id AS "id!", slug AS "slug!", name AS "name!", parent_id, armed AS "armed!", notify AS "notify!", notification_topic,
+                   notification_priority AS "notification_priority!", kind AS "kind!: ZoneKind", propagate AS "propagate!"
+               FROM (SELECT * FROM inserted UNION ALL SELECT * FROM area WHERE slug = $1) AS area"#,
+            slug,
+            name.trim().trim_matches('/'),
+        )
+            .fetch_one(con).await
+    }
+
+    pub async fn set_slug(id: i64, slug: &str, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!("UPDATE area SET slug = $1 WHERE id = $2", slug, id)
+            .execute(con).await?;
+
+        Ok(())
+    }
+
+    /// Moves the devices, child zones, pre-alarms, incidents and correlation rule of the area `from` to `into` and deletes it.
+    /// The rule of `into` wins if both have one. Run it in a transaction.
+    pub async fn merge(from: i64, into: i64, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!("UPDATE device SET area_id = $2 WHERE area_id = $1", from, into)
+            .execute(&mut *con).await?;
+        sqlx::query!("UPDATE area SET parent_id = CASE WHEN id = $2 THEN NULL ELSE $2 END WHERE parent_id = $1", from, into)
+            .execute(&mut *con).await?;
+        sqlx::query!("UPDATE pre_alarm SET area_id = $2 WHERE area_id = $1", from, into)
+            .execute(&mut *con).await?;
+        sqlx::query!("UPDATE incident SET area_id = $2 WHERE area_id = $1", from, into)
+            .execute(&mut *con).await?;
+        sqlx::query!(
+            "UPDATE correlation_rule SET area_id = $2 WHERE area_id = $1 AND NOT EXISTS (SELECT 1 FROM correlation_rule WHERE area_id = $2)",
+            from,
+            into,
+        )
+            .execute(&mut *con).await?;
+        sqlx::query!("DELETE FROM correlation_rule WHERE area_id = $1", from)
+            .execute(&mut *con).await?;
+        sqlx::query!("DELETE FROM area WHERE id = $1", from)
+            .execute(con).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,76 @@
+use sqlx::{Error, PgConnection};
+use crate::common::models::config::{ConfigChange, DeviceConfig};
+use crate::database::{AreaRepository, DeviceRepository, Repository};
+
+pub struct DeviceConfigRepository;
+
+impl DeviceConfigRepository {
+    /// `None` until the configuration of the device is edited the first time.
+    pub async fn get(device_id: i64, con: &mut PgConnection) -> Result<Option<DeviceConfig>, Error> {
+        sqlx::query_as!(
+            DeviceConfig,
+            "SELECT device_config.device_id, device_config.version, device.description, area.slug AS area, device_config.speaker_volume,
+                 device_config.motion_debounce_ms, device_config.heartbeat_interval_s, device_config.server_address, device_config.updated_at,
+                 device_config.applied_version, device_config.applied_at
+             FROM device_config JOIN device ON device.id = device_config.device_id JOIN area ON area.id = device.area_id
+             WHERE device_config.device_id = $1",
+            device_id,
+        )
+            .fetch_optional(con).await
+    }
+
+    pub async fn get_all(con: &mut PgConnection) -> Result<Vec<DeviceConfig>, Error> {
+        sqlx::query_as!(
+            DeviceConfig,
+            "SELECT device_config.device_id, device_config.version, device.description, area.slug AS area, device_config.speaker_volume,
+                 device_config.motion_debounce_ms, device_config.heartbeat_interval_s, device_config.server_address, device_config.updated_at,
+                 device_config.applied_version, device_config.applied_at
+             FROM device_config JOIN device ON device.id = device_config.device_id JOIN area ON area.id = device.area_id
+             ORDER BY device_config.device_id",
+        )
+            .fetch_all(con).await
+    }
+
+    /// Applies the change and increments the version, `None` if there is no such device.
+    /// Run it in a transaction, so the device and its settings change together.
+    pub async fn update(device_id: i64, change: &ConfigChange, con: &mut PgConnection) -> Result<Option<DeviceConfig>, Error> {
+        let Some(mut device) = DeviceRepository::get_by_id(device_id, con).await? else {
+            return Ok(None);
+        };
+
+        if let Some(description) = &change.description {
+            device.description = description.trim().to_string();
+        }
+        if let Some(area) = &change.area {
+            device.area_id = AreaRepository::get_or_create(area, con).await?.id;
+        }
+        DeviceRepository::update(&device, con).await?;
+
+        sqlx::query!(
+            "INSERT INTO device_config (device_id, speaker_volume, motion_debounce_ms, heartbeat_interval_s, server_address) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (device_id) DO UPDATE SET version = device_config.version + 1, speaker_volume = excluded.speaker_volume,
+                 motion_debounce_ms = excluded.motion_debounce_ms, heartbeat_interval_s = excluded.heartbeat_interval_s,
+                 server_address = excluded.server_address, updated_at = now()",
+            device_id,
+            change.speaker_volume,
+            change.motion_debounce_ms,
+            change.heartbeat_interval_s,
+            change.server_address,
+        )
+            .execute(&mut *con).await?;
+
+        Self::get(device_id, con).await
+    }
+
+    /// Records the version the device applied, unless the device reports a version that was never issued.
+    pub async fn acknowledge(device_id: i64, version: i64, con: &mut PgConnection) -> Result<Option<DeviceConfig>, Error> {
+        sqlx::query!(
+            "UPDATE device_config SET applied_version = $2, applied_at = now() WHERE device_id = $1 AND $2::bigint BETWEEN 1 AND version",
+            device_id,
+            version,
+        )
+            .execute(&mut *con).await?;
+
+        Self::get(device_id, con).await
+    }
+}
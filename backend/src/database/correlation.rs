@@ -0,0 +1,122 @@
+use sqlx::{Error, PgConnection};
+use crate::common::models::area::Area;
+use crate::common::models::correlation::{CorrelationRule, NewPreAlarm, PreAlarm, PreAlarmQuery, RuleChange};
+use crate::database::{AreaRepository, Repository};
+
+pub struct CorrelationRepository;
+
+impl CorrelationRepository {
+    pub async fn get(area_id: i64, con: &mut PgConnection) -> Result<Option<CorrelationRule>, Error> {
+        sqlx::query_as!(
+            CorrelationRule,
+            "SELECT correlation_rule.area_id, area.slug AS area, correlation_rule.min_detections, correlation_rule.min_devices,
+                 correlation_rule.window_s, correlation_rule.first_source, correlation_rule.then_source, correlation_rule.updated_at
+             FROM correlation_rule JOIN area ON area.id = correlation_rule.area_id
+             WHERE correlation_rule.area_id = $1",
+            area_id,
+        )
+            .fetch_optional(con).await
+    }
+
+    pub async fn get_all(con: &mut PgConnection) -> Result<Vec<CorrelationRule>, Error> {
+        sqlx::query_as!(
+            CorrelationRule,
+            "SELECT correlation_rule.area_id, area.slug AS area, correlation_rule.min_detections, correlation_rule.min_devices,
+                 correlation_rule.window_s, correlation_rule.first_source, correlation_rule.then_source, correlation_rule.updated_at
+             FROM correlation_rule JOIN area ON area.id = correlation_rule.area_id
+             ORDER BY area.slug",
+        )
+            .fetch_all(con).await
+    }
+
+    /// Replaces the rule of the area, which is created if it is unknown.
+    /// Run it in a transaction, so a new area isn't left behind without its rule.
+    pub async fn set(change: &RuleChange, con: &mut PgConnection) -> Result<CorrelationRule, Error> {
+        let area = AreaRepository::get_or_create(&change.area, con).await?;
+
+        sqlx::query!(
+            "INSERT INTO correlation_rule (area_id, min_detections, min_devices, window_s, first_source, then_source) VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (area_id) DO UPDATE SET min_detections = excluded.min_detections, min_devices = excluded.min_devices,
+                 window_s = excluded.window_s, first_source = excluded.first_source, then_source = excluded.then_source, updated_at = now()",
+            area.id,
+            change.min_detections,
+            change.min_devices,
+            change.window_s,
+            change.first_source.as_deref().map(str::trim),
+            change.then_source.as_deref().map(str::trim),
+        )
+            .execute(&mut *con).await?;
+
+        Self::get(area.id, con).await?.ok_or(Error::RowNotFound)
+    }
+
+    /// Removes the rule, every detection of the area alarms again. `None` if it had no rule.
+    pub async fn delete(area: &str, con: &mut PgConnection) -> Result<Option<CorrelationRule>, Error> {
+        sqlx::query_as!(
+            CorrelationRule,
+            r#"WITH deleted AS (DELETE FROM correlation_rule USING area WHERE area.id = correlation_rule.area_id AND area.slug = $1 RETURNING correlation_rule.*)
+               SELECT deleted.area_id AS "area_id!", area.slug AS area, deleted.min_detections, deleted.min_devices AS "min_devices!",
+                   deleted.window_s AS "window_s!", deleted.first_source, deleted.then_source, deleted.updated_at AS "updated_at!"
+               FROM deleted JOIN area ON area.id = deleted.area_id"#,
+            Area::slugify(area),
+        )
+            .fetch_optional(con).await
+    }
+}
+
+pub struct PreAlarmRepository;
+
+impl Repository for PreAlarmRepository {
+    type Model = PreAlarm;
+    type New = NewPreAlarm;
+
+    async fn insert(pre_alarm: &NewPreAlarm, con: &mut PgConnection) -> Result<PreAlarm, Error> {
+        sqlx::query_as!(
+            PreAlarm,
+            r#"WITH inserted AS (INSERT INTO pre_alarm (area_id, device_id, source, timestamp, detections, devices) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *)
+               SELECT inserted.id AS "id!", inserted.area_id AS "area_id!", area.slug AS area, inserted.device_id AS "device_id!",
+                   inserted.source AS "source!", inserted.timestamp AS "timestamp!", inserted.detections AS "detections!", inserted.devices AS "devices!"
+               FROM inserted JOIN area ON area.id = inserted.area_id"#,
+            pre_alarm.area_id,
+            pre_alarm.device_id,
+            pre_alarm.source,
+            pre_alarm.timestamp,
+            pre_alarm.detections,
+            pre_alarm.devices,
+        )
+            .fetch_one(con).await
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<PreAlarm>, Error> {
+        sqlx::query_as!(
+            PreAlarm,
+            "SELECT pre_alarm.id, pre_alarm.area_id, area.slug AS area, pre_alarm.device_id, pre_alarm.source, pre_alarm.timestamp,
+                 pre_alarm.detections, pre_alarm.devices
+             FROM pre_alarm JOIN area ON area.id = pre_alarm.area_id
+             WHERE pre_alarm.id = $1",
+            id,
+        )
+            .fetch_optional(con).await
+    }
+}
+
+impl PreAlarmRepository {
+    /// Pre-alarms matching the query, newest first.
+    pub async fn history(query: &PreAlarmQuery, con: &mut PgConnection) -> Result<Vec<PreAlarm>, Error> {
+        sqlx::query_as!(
+            PreAlarm,
+            "SELECT pre_alarm.id, pre_alarm.area_id, area.slug AS area, pre_alarm.device_id, pre_alarm.source, pre_alarm.timestamp,
+                 pre_alarm.detections, pre_alarm.devices
+             FROM pre_alarm JOIN area ON area.id = pre_alarm.area_id
+             WHERE ($1::text IS NULL OR area.slug = $1)
+                 AND ($2::timestamptz IS NULL OR pre_alarm.timestamp >= $2)
+                 AND ($3::timestamptz IS NULL OR pre_alarm.timestamp < $3)
+             ORDER BY pre_alarm.timestamp DESC, pre_alarm.id DESC LIMIT $4",
+            query.area.as_deref().map(Area::slugify),
+            query.from,
+            query.to,
+            query.limit(),
+        )
+            .fetch_all(con).await
+    }
+}
@@ -0,0 +1,65 @@
+use sqlx::{Error, PgConnection};
+
+/// Brings the slugs of the areas in line with `Area::slugify`, the SQL backfill of migration 0002 only folded ASCII.
+pub const AREA_SLUGS: &str = "area_slugs";
+
+/// Normalizes the sources with characters other than ASCII like new detections, migration 0013 only folded ASCII.
+pub const SOURCES: &str = "sources";
+
+/// The one-off steps of the server after the migrations, see migration 0014.
+pub struct DataMigrationRepository;
+
+impl DataMigrationRepository {
+    pub async fn applied(name: &str, con: &mut PgConnection) -> Result<bool, Error> {
+        let applied = sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM data_migration WHERE name = $1) AS "applied!""#, name)
+            .fetch_one(con).await?;
+
+        Ok(applied)
+    }
+
+    /// Run it in the transaction of the step, so the step is recorded only if it went through.
+    pub async fn record(name: &str, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!("INSERT INTO data_migration (name) VALUES ($1)", name)
+            .execute(con).await?;
+
+        Ok(())
+    }
+
+    /// The sources with characters other than ASCII of the detections, archive, daily counts, pre-alarms and rules.
+    pub async fn non_ascii_sources(con: &mut PgConnection) -> Result<Vec<String>, Error> {
+        sqlx::query_scalar!(
+            r#"SELECT source AS "source!" FROM (
+                   SELECT source FROM detection UNION SELECT source FROM detection_archive UNION SELECT source FROM detection_daily
+                   UNION SELECT source FROM pre_alarm UNION SELECT first_source FROM correlation_rule UNION SELECT then_source FROM correlation_rule
+               ) AS sources
+               WHERE source ~ '[^ -~]'"#,
+        )
+            .fetch_all(con).await
+    }
+
+    /// Replaces a source everywhere, daily counts of sources that are now the same are added up.
+    pub async fn rename_source(from: &str, to: &str, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!("UPDATE detection SET source = $2 WHERE source = $1", from, to)
+            .execute(&mut *con).await?;
+        sqlx::query!("UPDATE detection_archive SET source = $2 WHERE source = $1", from, to)
+            .execute(&mut *con).await?;
+        sqlx::query!("UPDATE pre_alarm SET source = $2 WHERE source = $1", from, to)
+            .execute(&mut *con).await?;
+        sqlx::query!("UPDATE correlation_rule SET first_source = $2 WHERE first_source = $1", from, to)
+            .execute(&mut *con).await?;
+        sqlx::query!("UPDATE correlation_rule SET then_source = $2 WHERE then_source = $1", from, to)
+            .execute(&mut *con).await?;
+        sqlx::query!(
+            "INSERT INTO detection_daily (day, device_id, source, count)
+             SELECT day, device_id, $2, count FROM detection_daily WHERE source = $1
+             ON CONFLICT (day, device_id, source) DO UPDATE SET count = detection_daily.count + excluded.count",
+            from,
+            to,
+        )
+            .execute(&mut *con).await?;
+        sqlx::query!("DELETE FROM detection_daily WHERE source = $1", from)
+            .execute(con).await?;
+
+        Ok(())
+    }
+}
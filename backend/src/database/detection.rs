@@ -1,67 +1,231 @@
-use chrono::Utc;
-use sqlx::{Arguments, Error, Pool, Postgres, Row};
-use sqlx::postgres::{PgArguments, PgRow};
-use crate::common::models::detection::Detection;
+use chrono::{DateTime, Utc};
+use futures_channel::mpsc::Sender;
+use futures_util::{SinkExt, TryStreamExt};
+use sqlx::{Error, PgConnection};
+use uuid::Uuid;
+use crate::common::models::detection::{Detection, NewDetection};
 use crate::common::models::device::Device;
-use crate::database::Database;
-use crate::message::receive::detection::DetectionMessage;
-
-async fn test(row: &'_ PgRow, pool: &Pool<Postgres>) -> Result<Detection, Error> {
-    let device_id: i64 = row.try_get(1)?;
-    let device = Device::get_by_id(device_id, pool).await?;
-    let d = device.first().unwrap().clone();
-
-    Ok(Detection {
-        id: row.try_get(0)?,
-        device: d,
-        source: row.try_get(2)?,
-        timestamp: row.try_get(3)?,
-    })
+use crate::common::models::history::{AreaCount, Bucket, DetectionFilter, DetectionPage, DeviceCount, ExportRecord, PageRequest};
+use crate::common::models::sensor::{ContactState, SensorKind};
+use crate::database::Repository;
+
+/// A detection joined with its device and area.
+#[derive(sqlx::FromRow)]
+pub(super) struct DetectionRow {
+    pub(super) id: i64,
+    pub(super) source: String,
+    pub(super) state: Option<ContactState>,
+    pub(super) timestamp: DateTime<Utc>,
+    pub(super) count: i32,
+    pub(super) device_id: i64,
+    pub(super) device_uuid: Uuid,
+    pub(super) device_description: String,
+    pub(super) area: String,
+    pub(super) area_id: i64,
 }
 
-impl Database<Detection> for DetectionMessage {
-    fn table_name() -> &'static str {
-        "detection"
+impl From<DetectionRow> for Detection {
+    fn from(row: DetectionRow) -> Self {
+        Detection {
+            id: row.id,
+            device: Device {
+                id: row.device_id,
+                uuid: row.device_uuid,
+                description: row.device_description,
+                area: row.area,
+                area_id: row.area_id,
+            },
+            kind: SensorKind::of(&row.source),
+            source: row.source,
+            state: row.state,
+            timestamp: row.timestamp,
+            count: row.count,
+        }
     }
+}
 
-    async fn insert(&self, pool: &Pool<Postgres>) -> Result<Detection, Error> {
-        let mut args = PgArguments::default();
-        args.add(&self.device.id);
-        args.add(&self.source);
-        args.add(Utc::now());
-
-        let statement = format!("INSERT INTO {} (device_id, source, timestamp) VALUES ($1, $2, $3) RETURNING id, device_id, source, timestamp", Self::table_name());
-
-        let mut con = pool.acquire().await?;
-        let res = sqlx::query_with(statement.as_str(), args).fetch_one(&mut *con).await;
-
-        match res {
-            Ok(row) => {
-                let a = test(&row, pool).await;
-                match a {
-                    Ok(detection) => {
-                        Ok(detection)
-                    }
-                    Err(err) => {
-                        Err(err)
-                    }
-                }
-            }
-            Err(err) => {
-                Err(err)
-            }
-        }
+pub struct DetectionRepository;
+
+impl Repository for DetectionRepository {
+    type Model = Detection;
+    type New = NewDetection;
+
+    async fn insert(detection: &NewDetection, con: &mut PgConnection) -> Result<Detection, Error> {
+        // The inserted row is joined with its device and area, so the detection comes back in one round-trip
+        let row = sqlx::query_as!(
+            DetectionRow,
+            r#"WITH inserted AS (INSERT INTO detection (device_id, source, state, timestamp) VALUES ($1, $2, $3, $4) RETURNING id, device_id, source, state, timestamp, count)
+               SELECT inserted.id AS "id!", inserted.source AS "source!", inserted.state AS "state: ContactState", inserted.timestamp AS "timestamp!", inserted.count AS "count!", device.id AS device_id, device.uuid AS device_uuid,
+                   device.description AS device_description, area.slug AS area, device.area_id
+               FROM inserted JOIN device ON device.id = inserted.device_id JOIN area ON area.id = device.area_id"#,
+            detection.device_id,
+            detection.source,
+            detection.state as Option<ContactState>,
+            detection.timestamp,
+        )
+            .fetch_one(con).await?;
+
+        Ok(row.into())
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Detection>, Error> {
+        let row = sqlx::query_as!(
+            DetectionRow,
+            r#"SELECT detection.id, detection.source, detection.state AS "state: ContactState", detection.timestamp, detection.count, device.id AS device_id, device.uuid AS device_uuid,
+                 device.description AS device_description, area.slug AS area, device.area_id
+             FROM detection JOIN device ON device.id = detection.device_id JOIN area ON area.id = device.area_id
+             WHERE detection.id = $1"#,
+            id,
+        )
+            .fetch_optional(con).await?;
+
+        Ok(row.map(Detection::from))
+    }
+}
+
+// Each filter is a parameter that disables its condition when it is NULL (or empty for the areas),
+// so the statements stay static and can be checked at compile time.
+impl DetectionRepository {
+    /// Detections matching the filter, newest first.
+    pub async fn history(filter: &DetectionFilter, page: PageRequest, con: &mut PgConnection) -> Result<DetectionPage, Error> {
+        let limit = page.limit();
+
+        // Ids grow with the insert time, ordering by them keeps the cursor stable for detections with the same timestamp
+        let rows = sqlx::query_as!(
+            DetectionRow,
+            r#"SELECT detection.id, detection.source, detection.state AS "state: ContactState", detection.timestamp, detection.count, device.id AS device_id, device.uuid AS device_uuid,
+                 device.description AS device_description, area.slug AS area, device.area_id
+             FROM detection JOIN device ON device.id = detection.device_id JOIN area ON area.id = device.area_id
+             WHERE (cardinality($1::text[]) = 0 OR area.slug = ANY($1))
+                 AND ($2::uuid IS NULL OR device.uuid = $2)
+                 AND ($3::text IS NULL OR detection.source = $3)
+                 AND ($4::timestamptz IS NULL OR detection.timestamp >= $4)
+                 AND ($5::timestamptz IS NULL OR detection.timestamp < $5)
+                 AND ($6::bigint IS NULL OR detection.id < $6)
+             ORDER BY detection.id DESC LIMIT $7"#,
+            &filter.areas,
+            filter.device,
+            filter.source,
+            filter.from,
+            filter.to,
+            page.cursor,
+            limit,
+        )
+            .fetch_all(con).await?;
+
+        let detections: Vec<Detection> = rows.into_iter().map(Detection::from).collect();
+
+        let next_cursor = match detections.last() {
+            Some(last) if detections.len() as i64 == limit => Some(last.id),
+            _ => None,
+        };
+
+        Ok(DetectionPage {
+            detections,
+            next_cursor,
+        })
+    }
+
+    /// Number of detections per area and hour or day.
+    pub async fn counts(filter: &DetectionFilter, bucket: Bucket, con: &mut PgConnection) -> Result<Vec<AreaCount>, Error> {
+        // Pruned detections only survive as daily counts, so day buckets add them to the raw detections
+        sqlx::query_as!(
+            AreaCount,
+            r#"SELECT area.slug AS area, date_trunc($6, detection.timestamp) AS "bucket!", SUM(detection.count)::bigint AS "count!"
+               FROM (SELECT device_id, source, timestamp, count::bigint FROM detection
+                     UNION ALL SELECT device_id, source, day, count FROM detection_daily WHERE $6 = 'day') AS detection
+                   JOIN device ON device.id = detection.device_id JOIN area ON area.id = device.area_id
+               WHERE (cardinality($1::text[]) = 0 OR area.slug = ANY($1))
+                   AND ($2::uuid IS NULL OR device.uuid = $2)
+                   AND ($3::text IS NULL OR detection.source = $3)
+                   AND ($4::timestamptz IS NULL OR detection.timestamp >= $4)
+                   AND ($5::timestamptz IS NULL OR detection.timestamp < $5)
+               GROUP BY 1, 2 ORDER BY 2 DESC, 1"#,
+            &filter.areas,
+            filter.device,
+            filter.source,
+            filter.from,
+            filter.to,
+            bucket.unit(),
+        )
+            .fetch_all(con).await
     }
 
-    async fn update(&self, pool: &Pool<Postgres>) -> Result<(), Error> {
-        todo!()
+    /// Devices with the most detections.
+    pub async fn noisy_devices(filter: &DetectionFilter, limit: i64, con: &mut PgConnection) -> Result<Vec<DeviceCount>, Error> {
+        let rows = sqlx::query!(
+            r#"SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id, SUM(detection.count)::bigint AS "count!"
+               FROM detection JOIN device ON device.id = detection.device_id JOIN area ON area.id = device.area_id
+               WHERE (cardinality($1::text[]) = 0 OR area.slug = ANY($1))
+                   AND ($2::uuid IS NULL OR device.uuid = $2)
+                   AND ($3::text IS NULL OR detection.source = $3)
+                   AND ($4::timestamptz IS NULL OR detection.timestamp >= $4)
+                   AND ($5::timestamptz IS NULL OR detection.timestamp < $5)
+               GROUP BY device.id, area.slug ORDER BY 6 DESC, device.id LIMIT $6"#,
+            &filter.areas,
+            filter.device,
+            filter.source,
+            filter.from,
+            filter.to,
+            limit,
+        )
+            .fetch_all(con).await?;
+
+        Ok(rows.into_iter()
+            .map(|row| DeviceCount {
+                device: Device {
+                    id: row.id,
+                    uuid: row.uuid,
+                    description: row.description,
+                    area: row.area,
+                    area_id: row.area_id,
+                },
+                count: row.count,
+            })
+            .collect())
     }
 
-    async fn get_all(pool: &Pool<Postgres>) -> Result<Vec<Detection>, Error> {
-        todo!()
+    /// Streams the matching detections, archived ones included, oldest first into `records`.
+    /// Stops early when the receiver is dropped.
+    pub async fn export(filter: &DetectionFilter, con: &mut PgConnection, mut records: Sender<ExportRecord>) -> Result<u64, Error> {
+        let mut rows = sqlx::query_as!(
+            ExportRecord,
+            r#"SELECT detection.id AS "id!", detection.timestamp AS "timestamp!", detection.source AS "source!", device.uuid AS device_uuid,
+                   device.description AS device_description, area.slug AS area, area.name AS area_name, detection.count AS "count!",
+                   detection.state AS "state: ContactState"
+               FROM (SELECT id, device_id, source, state, timestamp, count FROM detection
+                     UNION ALL SELECT id, device_id, source, state, timestamp, count FROM detection_archive) AS detection
+                   JOIN device ON device.id = detection.device_id JOIN area ON area.id = device.area_id
+               WHERE (cardinality($1::text[]) = 0 OR area.slug = ANY($1))
+                   AND ($2::uuid IS NULL OR device.uuid = $2)
+                   AND ($3::text IS NULL OR detection.source = $3)
+                   AND ($4::timestamptz IS NULL OR detection.timestamp >= $4)
+                   AND ($5::timestamptz IS NULL OR detection.timestamp < $5)
+               ORDER BY detection.timestamp, detection.id"#,
+            &filter.areas,
+            filter.device,
+            filter.source,
+            filter.from,
+            filter.to,
+        )
+            .fetch(con);
+        let mut exported = 0;
+
+        while let Some(record) = rows.try_next().await? {
+            if records.send(record).await.is_err() {
+                break;
+            }
+            exported += 1;
+        }
+
+        Ok(exported)
     }
 
-    async fn get_by_id(id: i64, pool: &Pool<Postgres>) -> Result<Vec<Detection>, Error> {
-        todo!()
+    /// Counts a repeated detection on the detection that raised the alert.
+    pub async fn add_repeat(id: i64, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!("UPDATE detection SET count = count + 1 WHERE id = $1", id)
+            .execute(con).await?;
+
+        Ok(())
     }
-}
\ No newline at end of file
+}
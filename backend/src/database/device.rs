@@ -1,49 +1,135 @@
-use sqlx::{Arguments, Error, PgPool, Pool, Postgres};
-use sqlx::postgres::PgArguments;
-use crate::common::models::device::Device;
-use crate::database::Database;
+use sqlx::{Error, PgConnection};
+use uuid::Uuid;
+use crate::common::models::device::{Device, NewDevice, Registration};
+use crate::database::{AreaRepository, Repository};
 
-impl Database<Device> for Device {
-    fn table_name() -> &'static str {
-        "device"
+pub struct DeviceRepository;
+
+impl Repository for DeviceRepository {
+    type Model = Device;
+    type New = NewDevice;
+
+    /// Creates the area of the device if it is unknown, run it in a transaction to keep both or neither.
+    /// Fails with `RowNotFound` if a device with the same hardware or request id exists.
+    async fn insert(device: &NewDevice, con: &mut PgConnection) -> Result<Device, Error> {
+        Self::insert_unless_known(device, con).await?.ok_or(Error::RowNotFound)
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Device>, Error> {
+        sqlx::query_as!(
+            Device,
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id
+             FROM device JOIN area ON area.id = device.area_id WHERE device.id = $1",
+            id,
+        )
+            .fetch_optional(con).await
+    }
+}
+
+impl DeviceRepository {
+    pub async fn get_all(con: &mut PgConnection) -> Result<Vec<Device>, Error> {
+        sqlx::query_as!(
+            Device,
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id
+             FROM device JOIN area ON area.id = device.area_id ORDER BY device.id",
+        )
+            .fetch_all(con).await
     }
 
-    async fn insert(&self, pool: &PgPool) -> Result<Device, Error> {
-        let mut args = PgArguments::default();
-        args.add(&self.uuid);
-        args.add(&self.description);
-        args.add(&self.area);
+    pub async fn get_by_uuid(uuid: Uuid, con: &mut PgConnection) -> Result<Option<Device>, Error> {
+        sqlx::query_as!(
+            Device,
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id
+             FROM device JOIN area ON area.id = device.area_id WHERE device.uuid = $1",
+            uuid,
+        )
+            .fetch_optional(con).await
+    }
 
-        let statement = format!("INSERT INTO {} (uuid, description, area) VALUES ($1, $2, $3) RETURNING id, uuid, description, area", Self::table_name());
+    /// Changes description and area of the device.
+    pub async fn update(device: &Device, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE device SET description = $1, area_id = $2 WHERE id = $3",
+            device.description,
+            device.area_id,
+            device.id,
+        )
+            .execute(con).await?;
 
-        let mut con = pool.acquire().await?;
+        Ok(())
+    }
 
-        // TODO: Add error handling
-        let res = sqlx::query_as_with(statement.as_str(), args).fetch_one(&mut *con).await?;
+    /// The device registered with the hardware id or else the request id.
+    pub async fn get_by_identity(hardware_id: Option<&str>, request_id: Option<&str>, con: &mut PgConnection) -> Result<Option<Device>, Error> {
+        if hardware_id.is_none() && request_id.is_none() {
+            return Ok(None);
+        }
 
-        Ok(res)
+        sqlx::query_as!(
+            Device,
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id
+             FROM device JOIN area ON area.id = device.area_id
+             WHERE device.hardware_id = $1 OR device.request_id = $2
+             ORDER BY device.hardware_id = $1 DESC NULLS LAST LIMIT 1",
+            hardware_id,
+            request_id,
+        )
+            .fetch_optional(con).await
     }
 
-    async fn update(&self, pool: &Pool<Postgres>) -> Result<(), Error> {
-        todo!()
+    /// Returns the device already registered with the same hardware or request id, or stores a new one.
+    /// Run it in a transaction, so the area of a new device is only kept with the device.
+    pub async fn register(device: &NewDevice, con: &mut PgConnection) -> Result<Registration, Error> {
+        let hardware_id = device.hardware_id.as_deref();
+        let request_id = device.request_id.as_deref();
+
+        if let Some(known) = Self::get_by_identity(hardware_id, request_id, con).await? {
+            return Ok(Registration { device: known, created: false });
+        }
+
+        match Self::insert_unless_known(device, con).await? {
+            Some(created) => Ok(Registration { device: created, created: true }),
+            // A concurrent registration with the same ids got there first, its row is committed by now
+            None => {
+                let known = Self::get_by_identity(hardware_id, request_id, con).await?.ok_or(Error::RowNotFound)?;
+                Ok(Registration { device: known, created: false })
+            }
+        }
     }
 
-    async fn get_all(pool: &Pool<Postgres>) -> Result<Vec<Device>, Error> {
-        todo!()
+    /// SHA-256 of the token issued to the device, `None` before it got one.
+    pub async fn token_hash(id: i64, con: &mut PgConnection) -> Result<Option<String>, Error> {
+        let hash = sqlx::query_scalar!("SELECT token_hash FROM device WHERE id = $1", id)
+            .fetch_optional(con).await?;
+
+        Ok(hash.flatten())
     }
 
-    async fn get_by_id(id: i64, pool: &Pool<Postgres>) -> Result<Vec<Device>, Error> {
-        let statement = format!(
-            "SELECT * FROM {} WHERE id = $1",
-            Self::table_name(),
-        );
+    pub async fn set_token_hash(id: i64, hash: Option<&str>, con: &mut PgConnection) -> Result<(), Error> {
+        sqlx::query!("UPDATE device SET token_hash = $1 WHERE id = $2", hash, id)
+            .execute(con).await?;
 
-        let mut args = PgArguments::default();
-        args.add(id);
+        Ok(())
+    }
 
-        let mut con = pool.acquire().await?;
-        let res = sqlx::query_as_with(statement.as_str(), args).fetch_all(&mut *con).await?;
+    /// Inserts nothing if the hardware or request id is taken, a failing insert would abort the transaction.
+    async fn insert_unless_known(device: &NewDevice, con: &mut PgConnection) -> Result<Option<Device>, Error> {
+        let area = AreaRepository::get_or_create(&device.area, con).await?;
 
-        Ok(res)
+        sqlx::query_as!(
+            Device,
+            r#"WITH inserted AS (
+                   INSERT INTO device (uuid, description, area_id, hardware_id, request_id) VALUES ($1, $2, $3, $4, $5)
+                   ON CONFLICT DO NOTHING RETURNING id, uuid, description, area_id
+               )
+               SELECT inserted.id AS "id!", inserted.uuid AS "uuid!", inserted.description AS "description!", area.slug AS area, inserted.area_id AS "area_id!"
+               FROM inserted JOIN area ON area.id = inserted.area_id"#,
+            device.uuid,
+            device.description,
+            area.id,
+            device.hardware_id,
+            device.request_id,
+        )
+            .fetch_optional(con).await
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,194 @@
+use sqlx::{Error, PgConnection};
+use crate::common::models::firmware::{Firmware, FirmwareUpdate, NewFirmware, NewRollout, Rollout, UpdateOffer, UpdateStatus};
+use crate::database::Repository;
+
+pub struct FirmwareRepository;
+
+impl Repository for FirmwareRepository {
+    type Model = Firmware;
+    type New = NewFirmware;
+
+    async fn insert(firmware: &NewFirmware, con: &mut PgConnection) -> Result<Firmware, Error> {
+        sqlx::query_as!(
+            Firmware,
+            "INSERT INTO firmware (version, board, size, sha256, md5, image) VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id, version, board, size, sha256, md5, uploaded_at",
+            firmware.version,
+            firmware.board,
+            firmware.size(),
+            firmware.sha256,
+            firmware.md5,
+            firmware.image,
+        )
+            .fetch_one(con).await
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Firmware>, Error> {
+        sqlx::query_as!(
+            Firmware,
+            "SELECT id, version, board, size, sha256, md5, uploaded_at FROM firmware WHERE id = $1",
+            id,
+        )
+            .fetch_optional(con).await
+    }
+}
+
+impl FirmwareRepository {
+    /// Newest first.
+    pub async fn get_all(con: &mut PgConnection) -> Result<Vec<Firmware>, Error> {
+        sqlx::query_as!(
+            Firmware,
+            "SELECT id, version, board, size, sha256, md5, uploaded_at FROM firmware ORDER BY id DESC",
+        )
+            .fetch_all(con).await
+    }
+
+    pub async fn image(id: i64, con: &mut PgConnection) -> Result<Option<Vec<u8>>, Error> {
+        sqlx::query_scalar!("SELECT image FROM firmware WHERE id = $1", id)
+            .fetch_optional(con).await
+    }
+}
+
+pub struct RolloutRepository;
+
+impl Repository for RolloutRepository {
+    type Model = Rollout;
+    type New = NewRollout;
+
+    /// Offers the first stage right away. Run it in a transaction, so the rollout is stored with all its devices.
+    /// Fails with `RowNotFound` if there is no such firmware.
+    async fn insert(rollout: &NewRollout, con: &mut PgConnection) -> Result<Rollout, Error> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO firmware_rollout (firmware_id, batch_size) VALUES ($1, $2) RETURNING id",
+            rollout.firmware_id,
+            rollout.batch_size,
+        )
+            .fetch_one(&mut *con).await?;
+
+        for (index, device_id) in rollout.device_ids.iter().enumerate() {
+            let stage = rollout.stage_of(index);
+            let status = if stage == 1 { UpdateStatus::Offered } else { UpdateStatus::Waiting };
+
+            sqlx::query!(
+                "INSERT INTO firmware_update (rollout_id, device_id, stage, status) VALUES ($1, $2, $3, $4)",
+                id,
+                device_id,
+                stage,
+                status as UpdateStatus,
+            )
+                .execute(&mut *con).await?;
+        }
+
+        Self::get_by_id(id, con).await?.ok_or(Error::RowNotFound)
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Rollout>, Error> {
+        let Some(rollout) = sqlx::query!("SELECT id, firmware_id, batch_size, stage, created_at FROM firmware_rollout WHERE id = $1", id)
+            .fetch_optional(&mut *con).await? else {
+            return Ok(None);
+        };
+
+        let firmware = FirmwareRepository::get_by_id(rollout.firmware_id, con).await?.ok_or(Error::RowNotFound)?;
+        let updates = sqlx::query_as!(
+            FirmwareUpdate,
+            r#"SELECT rollout_id, device_id, stage, status AS "status: UpdateStatus", error, updated_at
+               FROM firmware_update WHERE rollout_id = $1 ORDER BY stage, device_id"#,
+            id,
+        )
+            .fetch_all(&mut *con).await?;
+
+        Ok(Some(Rollout {
+            id: rollout.id,
+            firmware,
+            batch_size: rollout.batch_size,
+            stage: rollout.stage,
+            created_at: rollout.created_at,
+            updates,
+        }))
+    }
+}
+
+impl RolloutRepository {
+    /// Newest first.
+    pub async fn get_all(con: &mut PgConnection) -> Result<Vec<Rollout>, Error> {
+        let ids = sqlx::query_scalar!("SELECT id FROM firmware_rollout ORDER BY id DESC")
+            .fetch_all(&mut *con).await?;
+
+        let mut rollouts = Vec::with_capacity(ids.len());
+        for id in ids {
+            rollouts.extend(Self::get_by_id(id, con).await?);
+        }
+
+        Ok(rollouts)
+    }
+
+    /// Offers the next stage, the rollout stays as it is if it has no more stages.
+    /// Run it in a transaction, so the stage and its devices change together.
+    pub async fn advance(id: i64, con: &mut PgConnection) -> Result<Option<Rollout>, Error> {
+        let stage = sqlx::query_scalar!(
+            "UPDATE firmware_rollout SET stage = stage + 1
+             WHERE id = $1 AND EXISTS (SELECT 1 FROM firmware_update WHERE rollout_id = $1 AND stage > firmware_rollout.stage)
+             RETURNING stage",
+            id,
+        )
+            .fetch_optional(&mut *con).await?;
+
+        if let Some(stage) = stage {
+            sqlx::query!(
+                "UPDATE firmware_update SET status = 'offered', updated_at = now() WHERE rollout_id = $1 AND stage = $2 AND status = 'waiting'",
+                id,
+                stage,
+            )
+                .execute(&mut *con).await?;
+        }
+
+        Self::get_by_id(id, con).await
+    }
+
+    /// Records what the device reported, `None` if the update was not offered to it.
+    pub async fn set_status(rollout_id: i64, device_id: i64, status: UpdateStatus, error: Option<&str>, con: &mut PgConnection) -> Result<Option<Rollout>, Error> {
+        let updated = sqlx::query!(
+            "UPDATE firmware_update SET status = $3, error = $4, updated_at = now() WHERE rollout_id = $1 AND device_id = $2 AND status <> 'waiting'",
+            rollout_id,
+            device_id,
+            status as UpdateStatus,
+            error,
+        )
+            .execute(&mut *con).await?;
+
+        if updated.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        Self::get_by_id(rollout_id, con).await
+    }
+
+    /// Updates offered to the device that it has not started yet.
+    pub async fn offers(device_id: i64, con: &mut PgConnection) -> Result<Vec<UpdateOffer>, Error> {
+        let rows = sqlx::query!(
+            "SELECT firmware_update.rollout_id, firmware.id, firmware.version, firmware.board, firmware.size, firmware.sha256, firmware.md5, firmware.uploaded_at
+             FROM firmware_update
+                 JOIN firmware_rollout ON firmware_rollout.id = firmware_update.rollout_id
+                 JOIN firmware ON firmware.id = firmware_rollout.firmware_id
+             WHERE firmware_update.device_id = $1 AND firmware_update.status = 'offered'
+             ORDER BY firmware_update.rollout_id",
+            device_id,
+        )
+            .fetch_all(con).await?;
+
+        Ok(rows.into_iter()
+            .map(|row| UpdateOffer {
+                rollout_id: row.rollout_id,
+                firmware: Firmware {
+                    id: row.id,
+                    version: row.version,
+                    board: row.board,
+                    size: row.size,
+                    sha256: row.sha256,
+                    md5: row.md5,
+                    uploaded_at: row.uploaded_at,
+                },
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,78 @@
+use futures_channel::mpsc::Sender;
+use futures_util::{SinkExt, TryStreamExt};
+use sqlx::{Error, PgConnection};
+use crate::common::models::history::DetectionFilter;
+use crate::common::models::incident::{Incident, IncidentRecord, NewIncident};
+use crate::common::models::sensor::ContactState;
+use crate::database::Repository;
+
+pub struct IncidentRepository;
+
+impl Repository for IncidentRepository {
+    type Model = Incident;
+    type New = NewIncident;
+
+    async fn insert(incident: &NewIncident, con: &mut PgConnection) -> Result<Incident, Error> {
+        sqlx::query_as!(
+            Incident,
+            r#"WITH inserted AS (INSERT INTO incident (area_id, device_id, detection_id, source, state, recipients, timestamp) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *)
+               SELECT inserted.id AS "id!", inserted.area_id AS "area_id!", area.slug AS area, inserted.device_id AS "device_id!", inserted.detection_id,
+                   inserted.source AS "source!", inserted.state AS "state: ContactState", inserted.recipients AS "recipients!", inserted.timestamp AS "timestamp!"
+               FROM inserted JOIN area ON area.id = inserted.area_id"#,
+            incident.area_id,
+            incident.device_id,
+            incident.detection_id,
+            incident.source,
+            incident.state as Option<ContactState>,
+            incident.recipients,
+            incident.timestamp,
+        )
+            .fetch_one(con).await
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Incident>, Error> {
+        sqlx::query_as!(
+            Incident,
+            r#"SELECT incident.id, incident.area_id, area.slug AS area, incident.device_id, incident.detection_id, incident.source,
+                 incident.state AS "state: ContactState", incident.recipients, incident.timestamp
+             FROM incident JOIN area ON area.id = incident.area_id
+             WHERE incident.id = $1"#,
+            id,
+        )
+            .fetch_optional(con).await
+    }
+}
+
+impl IncidentRepository {
+    /// Passes the matching incidents to `records`, oldest first.
+    pub async fn export(filter: &DetectionFilter, con: &mut PgConnection, mut records: Sender<IncidentRecord>) -> Result<u64, Error> {
+        let mut rows = sqlx::query_as!(
+            IncidentRecord,
+            r#"SELECT incident.id, incident.timestamp, incident.source, device.uuid AS device_uuid, device.description AS device_description,
+                   area.slug AS area, area.name AS area_name, incident.recipients, incident.state AS "state: ContactState"
+               FROM incident JOIN device ON device.id = incident.device_id JOIN area ON area.id = incident.area_id
+               WHERE (cardinality($1::text[]) = 0 OR area.slug = ANY($1))
+                   AND ($2::uuid IS NULL OR device.uuid = $2)
+                   AND ($3::text IS NULL OR incident.source = $3)
+                   AND ($4::timestamptz IS NULL OR incident.timestamp >= $4)
+                   AND ($5::timestamptz IS NULL OR incident.timestamp < $5)
+               ORDER BY incident.timestamp, incident.id"#,
+            &filter.areas,
+            filter.device,
+            filter.source,
+            filter.from,
+            filter.to,
+        )
+            .fetch(con);
+        let mut exported = 0;
+
+        while let Some(record) = rows.try_next().await? {
+            if records.send(record).await.is_err() {
+                break;
+            }
+            exported += 1;
+        }
+
+        Ok(exported)
+    }
+}
@@ -0,0 +1,873 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use async_trait::async_trait;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use futures_channel::mpsc::Sender;
+use futures_util::SinkExt;
+use sqlx::Error;
+use uuid::Uuid;
+use crate::common::models::area::{Area, ZoneKind};
+use crate::common::models::config::{ConfigChange, DeviceConfig};
+use crate::common::models::correlation::{CorrelationRule, NewPreAlarm, PreAlarm, PreAlarmQuery, RuleChange};
+use crate::common::models::detection::{Detection, NewDetection};
+use crate::common::models::device::{Device, NewDevice, PendingDevice, Provisioning, Registration};
+use crate::common::models::firmware::{Firmware, FirmwareUpdate, NewFirmware, NewRollout, Rollout, UpdateOffer, UpdateStatus};
+use crate::common::models::history::{AreaCount, Bucket, DetectionFilter, DetectionPage, DeviceCount, ExportRecord, PageRequest};
+use crate::common::models::incident::{Incident, IncidentRecord, NewIncident};
+use crate::common::models::sensor::{ContactState, SensorKind};
+use crate::common::models::telemetry::{DeviceStatus, NewTelemetry, Telemetry, TelemetryQuery};
+use crate::database::{ArchiveSink, Pruned, Storage};
+
+/// Keeps everything in memory, for tests and demos without a database. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Mutex<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    areas: Vec<Area>,
+    devices: Vec<Device>,
+    /// Hardware and request id of each device, by index like `devices`.
+    identities: Vec<(Option<String>, Option<String>)>,
+    /// SHA-256 of the token issued to a device, by device id.
+    token_hashes: HashMap<i64, String>,
+    pending: Vec<PendingDevice>,
+    /// Description and area are taken from the device when a config is read.
+    configs: BTreeMap<i64, DeviceConfig>,
+    next_pending_id: i64,
+    telemetry: Vec<Telemetry>,
+    /// Firmware images with their metadata, by id.
+    firmware: Vec<(Firmware, Vec<u8>)>,
+    rollouts: Vec<Rollout>,
+    /// Correlation rules by area id.
+    correlation: BTreeMap<i64, CorrelationRule>,
+    pre_alarms: Vec<PreAlarm>,
+    incidents: Vec<Incident>,
+    detections: Vec<StoredDetection>,
+    archive: Vec<StoredDetection>,
+    /// Detection counts by day, device and source of pruned detections.
+    daily: BTreeMap<(DateTime<Utc>, i64, String), i64>,
+}
+
+#[derive(Clone)]
+struct StoredDetection {
+    id: i64,
+    device_id: i64,
+    source: String,
+    state: Option<ContactState>,
+    timestamp: DateTime<Utc>,
+    count: i32,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn data(&self) -> MutexGuard<'_, Data> {
+        // A panicking holder can't leave half an update behind, every change is made after the last fallible step
+        self.data.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Data {
+    fn area(&self, id: i64) -> Option<&Area> {
+        self.areas.iter().find(|area| area.id == id)
+    }
+
+    fn device(&self, id: i64) -> Option<&Device> {
+        self.devices.iter().find(|device| device.id == id)
+    }
+
+    /// The device registered with the hardware id or else the request id.
+    fn device_by_identity(&self, hardware_id: Option<&str>, request_id: Option<&str>) -> Option<&Device> {
+        let index = hardware_id
+            .and_then(|wanted| self.identities.iter().position(|(hardware_id, _)| hardware_id.as_deref() == Some(wanted)))
+            .or_else(|| request_id.and_then(|wanted| self.identities.iter().position(|(_, request_id)| request_id.as_deref() == Some(wanted))))?;
+
+        self.devices.get(index)
+    }
+
+    /// The pending device with the hardware id or else the request id.
+    fn pending_by_identity(&self, hardware_id: Option<&str>, request_id: Option<&str>) -> Option<&PendingDevice> {
+        hardware_id
+            .and_then(|wanted| self.pending.iter().find(|pending| pending.hardware_id.as_deref() == Some(wanted)))
+            .or_else(|| request_id.and_then(|wanted| self.pending.iter().find(|pending| pending.request_id.as_deref() == Some(wanted))))
+    }
+
+    fn insert_device(&mut self, device: &NewDevice) -> Result<Device, Error> {
+        if self.devices.iter().any(|known| known.uuid == device.uuid) {
+            return Err(Error::Protocol(format!("Device {} already exists", device.uuid)));
+        }
+
+        let area = self.area_get_or_create(&device.area);
+        let identity = (device.hardware_id.clone(), device.request_id.clone());
+        let device = Device {
+            id: self.devices.len() as i64 + 1,
+            uuid: device.uuid,
+            description: device.description.clone(),
+            area: area.slug,
+            area_id: area.id,
+        };
+
+        self.devices.push(device.clone());
+        self.identities.push(identity);
+        Ok(device)
+    }
+
+    fn area_get_or_create(&mut self, name: &str) -> Area {
+        let slug = Area::slugify(name);
+
+        if let Some(area) = self.areas.iter().find(|area| area.slug == slug) {
+            return area.clone();
+        }
+
+        let area = Area {
+            id: self.areas.len() as i64 + 1,
+            slug,
+            name: name.trim().trim_matches('/').to_string(),
+            parent_id: None,
+            armed: true,
+            notify: true,
+            notification_topic: None,
+            notification_priority: 3,
+            kind: ZoneKind::Room,
+            propagate: false,
+        };
+
+        self.areas.push(area.clone());
+        area
+    }
+
+    fn config(&self, device_id: i64) -> Option<DeviceConfig> {
+        let mut config = self.configs.get(&device_id)?.clone();
+        let device = self.device(device_id)?;
+
+        config.description = device.description.clone();
+        config.area = device.area.clone();
+        Some(config)
+    }
+
+    fn latest_telemetry(&self, device_id: i64) -> Option<&Telemetry> {
+        self.telemetry.iter()
+            .filter(|telemetry| telemetry.device_id == device_id)
+            .max_by_key(|telemetry| (telemetry.timestamp, telemetry.id))
+    }
+
+    fn to_detection(&self, stored: &StoredDetection) -> Option<Detection> {
+        Some(Detection {
+            id: stored.id,
+            device: self.device(stored.device_id)?.clone(),
+            source: stored.source.clone(),
+            kind: SensorKind::of(&stored.source),
+            state: stored.state,
+            timestamp: stored.timestamp,
+            count: stored.count,
+        })
+    }
+
+    fn matches(&self, filter: &DetectionFilter, device_id: i64, source: &str, timestamp: DateTime<Utc>) -> bool {
+        let Some(device) = self.device(device_id) else {
+            return false;
+        };
+
+        (filter.areas.is_empty() || filter.areas.contains(&device.area))
+            && filter.device.is_none_or(|uuid| uuid == device.uuid)
+            && filter.source.as_ref().is_none_or(|wanted| wanted == source)
+            && filter.from.is_none_or(|from| timestamp >= from)
+            && filter.to.is_none_or(|to| timestamp < to)
+    }
+
+    fn next_detection_id(&self) -> i64 {
+        let max = self.detections.iter().chain(&self.archive).map(|detection| detection.id).max();
+        max.unwrap_or_default() + 1
+    }
+}
+
+fn truncate(timestamp: DateTime<Utc>, bucket: Bucket) -> DateTime<Utc> {
+    let unit = match bucket {
+        Bucket::Hour => TimeDelta::hours(1),
+        Bucket::Day => TimeDelta::days(1),
+    };
+
+    timestamp.duration_trunc(unit).unwrap_or(timestamp)
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    fn backend(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>, Error> {
+        Ok(Vec::new())
+    }
+
+    async fn close(&self) {}
+
+    async fn areas(&self) -> Result<Vec<Area>, Error> {
+        Ok(self.data().areas.clone())
+    }
+
+    async fn area_get_or_create(&self, name: &str) -> Result<Area, Error> {
+        Ok(self.data().area_get_or_create(name))
+    }
+
+    async fn update_area(&self, area: &Area) -> Result<(), Error> {
+        if let Some(stored) = self.data().areas.iter_mut().find(|stored| stored.id == area.id) {
+            *stored = area.clone();
+        }
+
+        Ok(())
+    }
+
+    async fn device_by_uuid(&self, uuid: Uuid) -> Result<Option<Device>, Error> {
+        Ok(self.data().devices.iter().find(|device| device.uuid == uuid).cloned())
+    }
+
+    async fn register_device(&self, device: &NewDevice) -> Result<Registration, Error> {
+        let mut data = self.data();
+
+        if let Some(known) = data.device_by_identity(device.hardware_id.as_deref(), device.request_id.as_deref()) {
+            return Ok(Registration { device: known.clone(), created: false });
+        }
+
+        let device = data.insert_device(device)?;
+        Ok(Registration { device, created: true })
+    }
+
+    async fn provision_device(&self, device: &NewDevice, pairing_code: &str) -> Result<Provisioning, Error> {
+        let mut data = self.data();
+        let hardware_id = device.hardware_id.as_deref();
+        let request_id = device.request_id.as_deref();
+
+        if let Some(known) = data.device_by_identity(hardware_id, request_id) {
+            return Ok(Provisioning::Known(known.clone()));
+        }
+        if let Some(pending) = data.pending_by_identity(hardware_id, request_id) {
+            return Ok(Provisioning::Pending(pending.clone()));
+        }
+
+        data.next_pending_id += 1;
+        let pending = PendingDevice {
+            id: data.next_pending_id,
+            description: device.description.clone(),
+            area: device.area.clone(),
+            hardware_id: device.hardware_id.clone(),
+            request_id: device.request_id.clone(),
+            pairing_code: pairing_code.to_string(),
+            requested_at: Utc::now(),
+        };
+
+        data.pending.push(pending.clone());
+        Ok(Provisioning::Pending(pending))
+    }
+
+    async fn pending_devices(&self) -> Result<Vec<PendingDevice>, Error> {
+        Ok(self.data().pending.clone())
+    }
+
+    async fn approve_device(&self, id: i64, description: &str, area: &str) -> Result<Option<Device>, Error> {
+        let mut data = self.data();
+
+        let Some(index) = data.pending.iter().position(|pending| pending.id == id) else {
+            return Ok(None);
+        };
+
+        let device = NewDevice {
+            uuid: Uuid::new_v4(),
+            description: description.to_string(),
+            area: area.to_string(),
+            hardware_id: data.pending[index].hardware_id.clone(),
+            request_id: data.pending[index].request_id.clone(),
+        };
+
+        let device = data.insert_device(&device)?;
+        data.pending.remove(index);
+        Ok(Some(device))
+    }
+
+    async fn reject_device(&self, id: i64) -> Result<Option<PendingDevice>, Error> {
+        let mut data = self.data();
+
+        let index = data.pending.iter().position(|pending| pending.id == id);
+        Ok(index.map(|index| data.pending.remove(index)))
+    }
+
+    async fn device_token_hash(&self, device_id: i64) -> Result<Option<String>, Error> {
+        Ok(self.data().token_hashes.get(&device_id).cloned())
+    }
+
+    async fn set_device_token_hash(&self, device_id: i64, hash: Option<&str>) -> Result<(), Error> {
+        let mut data = self.data();
+
+        match hash {
+            Some(hash) if data.device(device_id).is_some() => {
+                data.token_hashes.insert(device_id, hash.to_string());
+            }
+            Some(_) => {}
+            None => {
+                data.token_hashes.remove(&device_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn device_config(&self, device_id: i64) -> Result<Option<DeviceConfig>, Error> {
+        Ok(self.data().config(device_id))
+    }
+
+    async fn device_configs(&self) -> Result<Vec<DeviceConfig>, Error> {
+        let data = self.data();
+        Ok(data.configs.keys().filter_map(|device_id| data.config(*device_id)).collect())
+    }
+
+    async fn update_device_config(&self, device_id: i64, change: &ConfigChange) -> Result<Option<DeviceConfig>, Error> {
+        let mut data = self.data();
+
+        let Some(index) = data.devices.iter().position(|device| device.id == device_id) else {
+            return Ok(None);
+        };
+
+        let area = change.area.as_deref().map(|area| data.area_get_or_create(area));
+        let device = &mut data.devices[index];
+        if let Some(description) = &change.description {
+            device.description = description.trim().to_string();
+        }
+        if let Some(area) = area {
+            device.area = area.slug;
+            device.area_id = area.id;
+        }
+
+        let now = Utc::now();
+        let config = data.configs.entry(device_id).or_insert_with(|| DeviceConfig {
+            device_id,
+            version: 0,
+            description: String::new(),
+            area: String::new(),
+            speaker_volume: None,
+            motion_debounce_ms: None,
+            heartbeat_interval_s: None,
+            server_address: None,
+            updated_at: now,
+            applied_version: None,
+            applied_at: None,
+        });
+        config.version += 1;
+        config.speaker_volume = change.speaker_volume;
+        config.motion_debounce_ms = change.motion_debounce_ms;
+        config.heartbeat_interval_s = change.heartbeat_interval_s;
+        config.server_address = change.server_address.clone();
+        config.updated_at = now;
+
+        Ok(data.config(device_id))
+    }
+
+    async fn acknowledge_config(&self, device_id: i64, version: i64) -> Result<Option<DeviceConfig>, Error> {
+        let mut data = self.data();
+
+        if let Some(config) = data.configs.get_mut(&device_id) {
+            if (1..=config.version).contains(&version) {
+                config.applied_version = Some(version);
+                config.applied_at = Some(Utc::now());
+            }
+        }
+
+        Ok(data.config(device_id))
+    }
+
+    async fn device_statuses(&self) -> Result<Vec<DeviceStatus>, Error> {
+        let data = self.data();
+
+        Ok(data.devices.iter()
+            .map(|device| DeviceStatus { device: device.clone(), telemetry: data.latest_telemetry(device.id).cloned() })
+            .collect())
+    }
+
+    async fn insert_telemetry(&self, telemetry: &NewTelemetry) -> Result<Telemetry, Error> {
+        let mut data = self.data();
+
+        // Like the foreign key of the databases
+        if data.device(telemetry.device_id).is_none() {
+            return Err(Error::RowNotFound);
+        }
+
+        let report = telemetry.report.clone();
+        let stored = Telemetry {
+            id: data.telemetry.last().map_or(1, |last| last.id + 1),
+            device_id: telemetry.device_id,
+            timestamp: telemetry.timestamp,
+            rssi: report.rssi,
+            free_heap: report.free_heap,
+            uptime_s: report.uptime_s,
+            reset_reason: report.reset_reason,
+            firmware_version: report.firmware_version,
+            supply_voltage: report.supply_voltage,
+        };
+
+        data.telemetry.push(stored.clone());
+        Ok(stored)
+    }
+
+    async fn latest_telemetry(&self, device_id: i64) -> Result<Option<Telemetry>, Error> {
+        Ok(self.data().latest_telemetry(device_id).cloned())
+    }
+
+    async fn telemetry_history(&self, device_id: i64, query: &TelemetryQuery) -> Result<Vec<Telemetry>, Error> {
+        let data = self.data();
+
+        let mut history: Vec<Telemetry> = data.telemetry.iter()
+            .filter(|telemetry| telemetry.device_id == device_id)
+            .filter(|telemetry| query.from.is_none_or(|from| telemetry.timestamp >= from))
+            .filter(|telemetry| query.to.is_none_or(|to| telemetry.timestamp < to))
+            .cloned()
+            .collect();
+        history.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| b.id.cmp(&a.id)));
+        history.truncate(query.limit() as usize);
+
+        Ok(history)
+    }
+
+    async fn insert_firmware(&self, new: &NewFirmware) -> Result<Firmware, Error> {
+        let mut data = self.data();
+
+        // Like the unique constraint of the databases
+        if data.firmware.iter().any(|(firmware, _)| firmware.board == new.board && firmware.version == new.version) {
+            return Err(Error::Protocol(format!("Firmware {} for {} already exists", new.version, new.board)));
+        }
+
+        let firmware = Firmware {
+            id: data.firmware.last().map_or(1, |(last, _)| last.id + 1),
+            version: new.version.clone(),
+            board: new.board.clone(),
+            size: new.size(),
+            sha256: new.sha256.clone(),
+            md5: new.md5.clone(),
+            uploaded_at: Utc::now(),
+        };
+
+        data.firmware.push((firmware.clone(), new.image.clone()));
+        Ok(firmware)
+    }
+
+    async fn firmwares(&self) -> Result<Vec<Firmware>, Error> {
+        Ok(self.data().firmware.iter().rev().map(|(firmware, _)| firmware.clone()).collect())
+    }
+
+    async fn firmware_image(&self, id: i64) -> Result<Option<(Firmware, Vec<u8>)>, Error> {
+        Ok(self.data().firmware.iter().find(|(firmware, _)| firmware.id == id).cloned())
+    }
+
+    async fn create_rollout(&self, new: &NewRollout) -> Result<Option<Rollout>, Error> {
+        let mut data = self.data();
+
+        let Some((firmware, _)) = data.firmware.iter().find(|(firmware, _)| firmware.id == new.firmware_id) else {
+            return Ok(None);
+        };
+        if new.device_ids.iter().any(|device_id| data.device(*device_id).is_none()) {
+            return Err(Error::RowNotFound);
+        }
+
+        let id = data.rollouts.last().map_or(1, |last| last.id + 1);
+        let now = Utc::now();
+        let mut updates: Vec<FirmwareUpdate> = new.device_ids.iter().enumerate()
+            .map(|(index, device_id)| {
+                let stage = new.stage_of(index);
+                FirmwareUpdate {
+                    rollout_id: id,
+                    device_id: *device_id,
+                    stage,
+                    status: if stage == 1 { UpdateStatus::Offered } else { UpdateStatus::Waiting },
+                    error: None,
+                    updated_at: now,
+                }
+            })
+            .collect();
+        updates.sort_by_key(|update| (update.stage, update.device_id));
+
+        let rollout = Rollout {
+            id,
+            firmware: firmware.clone(),
+            batch_size: new.batch_size,
+            stage: 1,
+            created_at: now,
+            updates,
+        };
+
+        data.rollouts.push(rollout.clone());
+        Ok(Some(rollout))
+    }
+
+    async fn rollouts(&self) -> Result<Vec<Rollout>, Error> {
+        Ok(self.data().rollouts.iter().rev().cloned().collect())
+    }
+
+    async fn rollout(&self, id: i64) -> Result<Option<Rollout>, Error> {
+        Ok(self.data().rollouts.iter().find(|rollout| rollout.id == id).cloned())
+    }
+
+    async fn advance_rollout(&self, id: i64) -> Result<Option<Rollout>, Error> {
+        let mut data = self.data();
+        let Some(rollout) = data.rollouts.iter_mut().find(|rollout| rollout.id == id) else {
+            return Ok(None);
+        };
+
+        if rollout.updates.iter().any(|update| update.stage > rollout.stage) {
+            rollout.stage += 1;
+
+            let now = Utc::now();
+            for update in rollout.updates.iter_mut().filter(|update| update.stage == rollout.stage && update.status == UpdateStatus::Waiting) {
+                update.status = UpdateStatus::Offered;
+                update.updated_at = now;
+            }
+        }
+
+        Ok(Some(rollout.clone()))
+    }
+
+    async fn set_update_status(&self, rollout_id: i64, device_id: i64, status: UpdateStatus, error: Option<&str>) -> Result<Option<Rollout>, Error> {
+        let mut data = self.data();
+        let Some(rollout) = data.rollouts.iter_mut().find(|rollout| rollout.id == rollout_id) else {
+            return Ok(None);
+        };
+        let Some(update) = rollout.updates.iter_mut().find(|update| update.device_id == device_id && update.status != UpdateStatus::Waiting) else {
+            return Ok(None);
+        };
+
+        update.status = status;
+        update.error = error.map(str::to_string);
+        update.updated_at = Utc::now();
+
+        Ok(Some(rollout.clone()))
+    }
+
+    async fn update_offers(&self, device_id: i64) -> Result<Vec<UpdateOffer>, Error> {
+        Ok(self.data().rollouts.iter()
+            .filter(|rollout| rollout.updates.iter().any(|update| update.device_id == device_id && update.status == UpdateStatus::Offered))
+            .map(|rollout| UpdateOffer { rollout_id: rollout.id, firmware: rollout.firmware.clone() })
+            .collect())
+    }
+
+    async fn insert_detection(&self, detection: &NewDetection) -> Result<Detection, Error> {
+        let mut data = self.data();
+
+        // Like the foreign key of the databases
+        if data.device(detection.device_id).is_none() {
+            return Err(Error::RowNotFound);
+        }
+
+        let stored = StoredDetection {
+            id: data.next_detection_id(),
+            device_id: detection.device_id,
+            source: detection.source.clone(),
+            state: detection.state,
+            timestamp: detection.timestamp,
+            count: 1,
+        };
+
+        data.detections.push(stored.clone());
+        data.to_detection(&stored).ok_or(Error::RowNotFound)
+    }
+
+    async fn add_detection_repeat(&self, id: i64) -> Result<(), Error> {
+        if let Some(stored) = self.data().detections.iter_mut().find(|stored| stored.id == id) {
+            stored.count += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn correlation_rule(&self, area_id: i64) -> Result<Option<CorrelationRule>, Error> {
+        Ok(self.data().correlation.get(&area_id).cloned())
+    }
+
+    async fn correlation_rules(&self) -> Result<Vec<CorrelationRule>, Error> {
+        let mut rules: Vec<CorrelationRule> = self.data().correlation.values().cloned().collect();
+        rules.sort_by(|a, b| a.area.cmp(&b.area));
+
+        Ok(rules)
+    }
+
+    async fn set_correlation_rule(&self, change: &RuleChange) -> Result<CorrelationRule, Error> {
+        let mut data = self.data();
+        let area = data.area_get_or_create(&change.area);
+
+        let rule = CorrelationRule {
+            area_id: area.id,
+            area: area.slug,
+            min_detections: change.min_detections,
+            min_devices: change.min_devices,
+            window_s: change.window_s,
+            first_source: change.first_source.as_deref().map(|source| source.trim().to_string()),
+            then_source: change.then_source.as_deref().map(|source| source.trim().to_string()),
+            updated_at: Utc::now(),
+        };
+
+        data.correlation.insert(rule.area_id, rule.clone());
+        Ok(rule)
+    }
+
+    async fn delete_correlation_rule(&self, area: &str) -> Result<Option<CorrelationRule>, Error> {
+        let mut data = self.data();
+        let slug = Area::slugify(area);
+
+        let Some(area_id) = data.areas.iter().find(|area| area.slug == slug).map(|area| area.id) else {
+            return Ok(None);
+        };
+
+        Ok(data.correlation.remove(&area_id))
+    }
+
+    async fn insert_pre_alarm(&self, pre_alarm: &NewPreAlarm) -> Result<PreAlarm, Error> {
+        let mut data = self.data();
+
+        // Like the foreign keys of the databases
+        let (Some(area), Some(_)) = (data.area(pre_alarm.area_id), data.device(pre_alarm.device_id)) else {
+            return Err(Error::RowNotFound);
+        };
+
+        let stored = PreAlarm {
+            id: data.pre_alarms.len() as i64 + 1,
+            area_id: pre_alarm.area_id,
+            area: area.slug.clone(),
+            device_id: pre_alarm.device_id,
+            source: pre_alarm.source.clone(),
+            timestamp: pre_alarm.timestamp,
+            detections: pre_alarm.detections,
+            devices: pre_alarm.devices,
+        };
+
+        data.pre_alarms.push(stored.clone());
+        Ok(stored)
+    }
+
+    async fn pre_alarms(&self, query: &PreAlarmQuery) -> Result<Vec<PreAlarm>, Error> {
+        let area = query.area.as_deref().map(Area::slugify);
+
+        let mut pre_alarms: Vec<PreAlarm> = self.data().pre_alarms.iter()
+            .filter(|pre_alarm| area.as_ref().is_none_or(|area| *area == pre_alarm.area))
+            .filter(|pre_alarm| query.from.is_none_or(|from| pre_alarm.timestamp >= from))
+            .filter(|pre_alarm| query.to.is_none_or(|to| pre_alarm.timestamp < to))
+            .cloned()
+            .collect();
+        pre_alarms.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| b.id.cmp(&a.id)));
+        pre_alarms.truncate(query.limit() as usize);
+
+        Ok(pre_alarms)
+    }
+
+    async fn insert_incident(&self, incident: &NewIncident) -> Result<Incident, Error> {
+        let mut data = self.data();
+
+        let (Some(area), Some(_)) = (data.area(incident.area_id), data.device(incident.device_id)) else {
+            return Err(Error::RowNotFound);
+        };
+
+        let stored = Incident {
+            id: data.incidents.len() as i64 + 1,
+            area_id: incident.area_id,
+            area: area.slug.clone(),
+            device_id: incident.device_id,
+            detection_id: incident.detection_id,
+            source: incident.source.clone(),
+            state: incident.state,
+            recipients: incident.recipients,
+            timestamp: incident.timestamp,
+        };
+
+        data.incidents.push(stored.clone());
+        Ok(stored)
+    }
+
+    async fn detection_history(&self, filter: &DetectionFilter, page: PageRequest) -> Result<DetectionPage, Error> {
+        let data = self.data();
+        let limit = page.limit();
+
+        let detections: Vec<Detection> = data.detections.iter()
+            .rev()
+            .filter(|stored| page.cursor.is_none_or(|cursor| stored.id < cursor))
+            .filter(|stored| data.matches(filter, stored.device_id, &stored.source, stored.timestamp))
+            .take(limit as usize)
+            .filter_map(|stored| data.to_detection(stored))
+            .collect();
+
+        let next_cursor = match detections.last() {
+            Some(last) if detections.len() as i64 == limit => Some(last.id),
+            _ => None,
+        };
+
+        Ok(DetectionPage {
+            detections,
+            next_cursor,
+        })
+    }
+
+    async fn detection_counts(&self, filter: &DetectionFilter, bucket: Bucket) -> Result<Vec<AreaCount>, Error> {
+        let data = self.data();
+        let mut counts: HashMap<(String, DateTime<Utc>), i64> = HashMap::new();
+
+        let raw = data.detections.iter().map(|stored| (stored.device_id, stored.source.as_str(), stored.timestamp, stored.count as i64));
+        let daily = data.daily.iter().map(|((day, device_id, source), count)| (*device_id, source.as_str(), *day, *count));
+
+        // Pruned detections only survive as daily counts, so day buckets add them to the raw detections
+        let rows: Vec<_> = match bucket {
+            Bucket::Hour => raw.collect(),
+            Bucket::Day => raw.chain(daily).collect(),
+        };
+
+        for (device_id, source, timestamp, count) in rows {
+            if !data.matches(filter, device_id, source, timestamp) {
+                continue;
+            }
+
+            let Some(device) = data.device(device_id) else { continue };
+            *counts.entry((device.area.clone(), truncate(timestamp, bucket))).or_default() += count;
+        }
+
+        let mut counts: Vec<AreaCount> = counts.into_iter()
+            .map(|((area, bucket), count)| AreaCount { area, bucket, count })
+            .collect();
+        counts.sort_by(|a, b| b.bucket.cmp(&a.bucket).then_with(|| a.area.cmp(&b.area)));
+
+        Ok(counts)
+    }
+
+    async fn noisy_devices(&self, filter: &DetectionFilter, limit: i64) -> Result<Vec<DeviceCount>, Error> {
+        let data = self.data();
+        let mut counts: HashMap<i64, i64> = HashMap::new();
+
+        for stored in &data.detections {
+            if data.matches(filter, stored.device_id, &stored.source, stored.timestamp) {
+                *counts.entry(stored.device_id).or_default() += stored.count as i64;
+            }
+        }
+
+        let mut devices: Vec<DeviceCount> = counts.into_iter()
+            .filter_map(|(device_id, count)| Some(DeviceCount { device: data.device(device_id)?.clone(), count }))
+            .collect();
+        devices.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.device.id.cmp(&b.device.id)));
+        devices.truncate(limit.max(0) as usize);
+
+        Ok(devices)
+    }
+
+    async fn export_detections(&self, filter: &DetectionFilter, mut records: Sender<ExportRecord>) -> Result<u64, Error> {
+        // Collected up front, the lock can't be held while waiting for the reader
+        let mut matching: Vec<ExportRecord> = {
+            let data = self.data();
+
+            data.detections.iter()
+                .chain(&data.archive)
+                .filter(|stored| data.matches(filter, stored.device_id, &stored.source, stored.timestamp))
+                .filter_map(|stored| {
+                    let device = data.device(stored.device_id)?;
+                    let area = data.area(device.area_id)?;
+
+                    Some(ExportRecord {
+                        id: stored.id,
+                        timestamp: stored.timestamp,
+                        source: stored.source.clone(),
+                        device_uuid: device.uuid,
+                        device_description: device.description.clone(),
+                        area: area.slug.clone(),
+                        area_name: area.name.clone(),
+                        count: stored.count,
+                        state: stored.state,
+                    })
+                })
+                .collect()
+        };
+        matching.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id)));
+
+        let mut exported = 0;
+        for record in matching {
+            if records.send(record).await.is_err() {
+                break;
+            }
+            exported += 1;
+        }
+
+        Ok(exported)
+    }
+
+    async fn export_incidents(&self, filter: &DetectionFilter, mut records: Sender<IncidentRecord>) -> Result<u64, Error> {
+        let matching: Vec<IncidentRecord> = {
+            let data = self.data();
+
+            data.incidents.iter()
+                .filter_map(|incident| {
+                    let device = data.device(incident.device_id)?;
+                    let area = data.area(incident.area_id)?;
+
+                    // The area that was alerted, not the one of the device
+                    let matches = (filter.areas.is_empty() || filter.areas.contains(&area.slug))
+                        && filter.device.is_none_or(|uuid| uuid == device.uuid)
+                        && filter.source.as_ref().is_none_or(|wanted| *wanted == incident.source)
+                        && filter.from.is_none_or(|from| incident.timestamp >= from)
+                        && filter.to.is_none_or(|to| incident.timestamp < to);
+
+                    matches.then(|| IncidentRecord {
+                        id: incident.id,
+                        timestamp: incident.timestamp,
+                        source: incident.source.clone(),
+                        device_uuid: device.uuid,
+                        device_description: device.description.clone(),
+                        area: area.slug.clone(),
+                        area_name: area.name.clone(),
+                        recipients: incident.recipients,
+                        state: incident.state,
+                    })
+                })
+                .collect()
+        };
+
+        let mut exported = 0;
+        for record in matching {
+            if records.send(record).await.is_err() {
+                break;
+            }
+            exported += 1;
+        }
+
+        Ok(exported)
+    }
+
+    async fn prune_detections(&self, cutoff: DateTime<Utc>, archive: bool, sink: Option<&mut dyn ArchiveSink>) -> Result<Pruned, Error> {
+        let mut data = self.data();
+
+        let (old, kept): (Vec<StoredDetection>, Vec<StoredDetection>) = data.detections.iter()
+            .cloned()
+            .partition(|stored| stored.timestamp < cutoff);
+
+        // Nothing is changed before the sink is done, like the rollback of the databases
+        if let Some(sink) = sink {
+            for stored in &old {
+                if let Some(detection) = data.to_detection(stored) {
+                    sink.write(&detection).map_err(Error::Io)?;
+                }
+            }
+            sink.finish().map_err(Error::Io)?;
+        }
+
+        let mut updated = BTreeSet::new();
+        for stored in &old {
+            let key = (truncate(stored.timestamp, Bucket::Day), stored.device_id, stored.source.clone());
+            *data.daily.entry(key.clone()).or_default() += stored.count as i64;
+            updated.insert(key);
+        }
+
+        let removed = old.len() as u64;
+        if archive {
+            data.archive.extend(old);
+        }
+        data.detections = kept;
+
+        Ok(Pruned { removed, days: updated.len() as u64 })
+    }
+}
@@ -1,16 +1,232 @@
+mod area;
+mod config;
+mod correlation;
+mod data_migration;
 mod device;
 mod detection;
+mod firmware;
+mod incident;
+mod pending_device;
+mod retention;
+mod telemetry;
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
 
-use sqlx::{Error, Pool, Postgres};
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_channel::mpsc::Sender;
+use sqlx::{Error, PgConnection};
+use uuid::Uuid;
+use crate::common::models::area::Area;
+use crate::common::models::config::{ConfigChange, DeviceConfig};
+use crate::common::models::correlation::{CorrelationRule, NewPreAlarm, PreAlarm, PreAlarmQuery, RuleChange};
+use crate::common::models::detection::{Detection, NewDetection};
+use crate::common::models::device::{Device, NewDevice, PendingDevice, Provisioning, Registration};
+use crate::common::models::firmware::{Firmware, NewFirmware, NewRollout, Rollout, UpdateOffer, UpdateStatus};
+use crate::common::models::history::{AreaCount, Bucket, DetectionFilter, DetectionPage, DeviceCount, ExportRecord, PageRequest};
+use crate::common::models::incident::{Incident, IncidentRecord, NewIncident};
+use crate::common::models::telemetry::{DeviceStatus, NewTelemetry, Telemetry, TelemetryQuery};
+use self::postgres::PostgresStorage;
+use self::sqlite::SqliteStorage;
 
-pub trait Database<T> {
-    fn table_name() -> &'static str;
+pub use self::area::AreaRepository;
+pub use self::config::DeviceConfigRepository;
+pub use self::correlation::{CorrelationRepository, PreAlarmRepository};
+pub use self::data_migration::DataMigrationRepository;
+pub use self::detection::DetectionRepository;
+pub use self::device::DeviceRepository;
+pub use self::firmware::{FirmwareRepository, RolloutRepository};
+pub use self::incident::IncidentRepository;
+pub use self::pending_device::PendingDeviceRepository;
+pub use self::telemetry::TelemetryRepository;
 
-    async fn insert(&self, pool: &Pool<Postgres>) -> Result<T, Error>;
+/// Shared handle to the storage backend the server was started with.
+pub type Db = Arc<dyn Storage>;
 
-    async fn update(&self, pool: &Pool<Postgres>) -> Result<(), Error>;
+/// Opens the storage backend for the scheme of `url` (`postgres://` or `sqlite://`) and applies its migrations.
+pub async fn connect(url: &str) -> Result<Db, Error> {
+    let scheme = url.split_once(':').map(|(scheme, _)| scheme).unwrap_or_default();
 
-    async fn get_all(pool: &Pool<Postgres>) -> Result<Vec<T>, Error>;
+    match scheme {
+        "postgres" | "postgresql" => Ok(Arc::new(PostgresStorage::connect(url).await?)),
+        "sqlite" => Ok(Arc::new(SqliteStorage::connect(url).await?)),
+        _ => Err(Error::Configuration(format!("Unsupported database URL scheme \"{}\"", scheme).into())),
+    }
+}
+
+/// Everything the server stores and reads, implemented once per database.
+/// Both backends apply the same numbered migrations, from `migrations` and `migrations_sqlite`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Name of the backend for logs and health details.
+    fn backend(&self) -> &'static str;
+
+    async fn ping(&self) -> Result<(), Error>;
+
+    /// Versions of the embedded migrations that are not applied to the database yet.
+    async fn pending_migrations(&self) -> Result<Vec<i64>, Error>;
+
+    async fn close(&self);
+
+    async fn areas(&self) -> Result<Vec<Area>, Error>;
+
+    /// Resolves an area by name or connection path, creating it with default settings if it is unknown.
+    async fn area_get_or_create(&self, name: &str) -> Result<Area, Error>;
+
+    /// Replaces the settings of the area with the same id.
+    async fn update_area(&self, area: &Area) -> Result<(), Error>;
+
+    async fn device_by_uuid(&self, uuid: Uuid) -> Result<Option<Device>, Error>;
+
+    /// Returns the device registered before with the same hardware or request id, or else stores the device
+    /// together with its area, if the area is new. Runs in one transaction.
+    async fn register_device(&self, device: &NewDevice) -> Result<Registration, Error>;
+
+    /// Like `register_device`, but a device that is not known yet is queued for approval with `pairing_code`
+    /// instead of being stored. Runs in one transaction.
+    async fn provision_device(&self, device: &NewDevice, pairing_code: &str) -> Result<Provisioning, Error>;
+
+    /// Devices waiting for approval, oldest first.
+    async fn pending_devices(&self) -> Result<Vec<PendingDevice>, Error>;
+
+    /// Stores a pending device with the description and area chosen by the admin and removes it from the queue.
+    /// `None` if there is no such pending device.
+    async fn approve_device(&self, id: i64, description: &str, area: &str) -> Result<Option<Device>, Error>;
+
+    /// Removes a device from the approval queue, `None` if there is no such pending device.
+    async fn reject_device(&self, id: i64) -> Result<Option<PendingDevice>, Error>;
+
+    /// SHA-256 of the token issued to the device, `None` before it got one.
+    async fn device_token_hash(&self, device_id: i64) -> Result<Option<String>, Error>;
+
+    /// `None` withdraws the token, the next registration of the device gets a new one.
+    async fn set_device_token_hash(&self, device_id: i64, hash: Option<&str>) -> Result<(), Error>;
+
+    /// `None` until the configuration of the device is edited the first time.
+    async fn device_config(&self, device_id: i64) -> Result<Option<DeviceConfig>, Error>;
+
+    /// Configurations of all devices that have one, by device id.
+    async fn device_configs(&self) -> Result<Vec<DeviceConfig>, Error>;
+
+    /// Changes description, area and settings of the device and increments the config version.
+    /// `None` if there is no such device. Runs in one transaction.
+    async fn update_device_config(&self, device_id: i64, change: &ConfigChange) -> Result<Option<DeviceConfig>, Error>;
+
+    /// Records the config version the device applied and returns its current configuration.
+    async fn acknowledge_config(&self, device_id: i64, version: i64) -> Result<Option<DeviceConfig>, Error>;
+
+    /// All devices with their latest telemetry, by id.
+    async fn device_statuses(&self) -> Result<Vec<DeviceStatus>, Error>;
+
+    async fn insert_telemetry(&self, telemetry: &NewTelemetry) -> Result<Telemetry, Error>;
+
+    /// `None` until the device sends its first telemetry.
+    async fn latest_telemetry(&self, device_id: i64) -> Result<Option<Telemetry>, Error>;
+
+    /// Telemetry of the device in the time range of the query, newest first.
+    async fn telemetry_history(&self, device_id: i64, query: &TelemetryQuery) -> Result<Vec<Telemetry>, Error>;
+
+    async fn insert_firmware(&self, firmware: &NewFirmware) -> Result<Firmware, Error>;
+
+    /// Firmware images, newest first.
+    async fn firmwares(&self) -> Result<Vec<Firmware>, Error>;
+
+    async fn firmware_image(&self, id: i64) -> Result<Option<(Firmware, Vec<u8>)>, Error>;
+
+    /// Stores the rollout and offers its first stage. `None` if there is no such firmware. Runs in one transaction.
+    async fn create_rollout(&self, rollout: &NewRollout) -> Result<Option<Rollout>, Error>;
+
+    /// Rollouts, newest first.
+    async fn rollouts(&self) -> Result<Vec<Rollout>, Error>;
+
+    async fn rollout(&self, id: i64) -> Result<Option<Rollout>, Error>;
+
+    /// Offers the next stage of the rollout, whatever the state of the current one. Runs in one transaction.
+    async fn advance_rollout(&self, id: i64) -> Result<Option<Rollout>, Error>;
+
+    /// Records the progress a device reported, `None` if the update was not offered to it.
+    async fn set_update_status(&self, rollout_id: i64, device_id: i64, status: UpdateStatus, error: Option<&str>) -> Result<Option<Rollout>, Error>;
+
+    /// Updates offered to the device that it has not started yet.
+    async fn update_offers(&self, device_id: i64) -> Result<Vec<UpdateOffer>, Error>;
+
+    async fn insert_detection(&self, detection: &NewDetection) -> Result<Detection, Error>;
+
+    /// Counts a repeated detection on the detection that raised the alert.
+    async fn add_detection_repeat(&self, id: i64) -> Result<(), Error>;
+
+    /// `None` if every detection of the area raises the alarm.
+    async fn correlation_rule(&self, area_id: i64) -> Result<Option<CorrelationRule>, Error>;
+
+    /// Rules of all areas that have one, by area slug.
+    async fn correlation_rules(&self) -> Result<Vec<CorrelationRule>, Error>;
+
+    /// Replaces the rule of the area, creating the area if it is unknown. Runs in one transaction.
+    async fn set_correlation_rule(&self, change: &RuleChange) -> Result<CorrelationRule, Error>;
+
+    /// `None` if the area had no rule.
+    async fn delete_correlation_rule(&self, area: &str) -> Result<Option<CorrelationRule>, Error>;
+
+    async fn insert_pre_alarm(&self, pre_alarm: &NewPreAlarm) -> Result<PreAlarm, Error>;
+
+    /// Pre-alarms matching the query, newest first.
+    async fn pre_alarms(&self, query: &PreAlarmQuery) -> Result<Vec<PreAlarm>, Error>;
+
+    async fn insert_incident(&self, incident: &NewIncident) -> Result<Incident, Error>;
+
+    /// Detections matching the filter, newest first.
+    async fn detection_history(&self, filter: &DetectionFilter, page: PageRequest) -> Result<DetectionPage, Error>;
+
+    /// Number of detections per area and hour or day. Day buckets include pruned detections.
+    async fn detection_counts(&self, filter: &DetectionFilter, bucket: Bucket) -> Result<Vec<AreaCount>, Error>;
+
+    /// Devices with the most detections.
+    async fn noisy_devices(&self, filter: &DetectionFilter, limit: i64) -> Result<Vec<DeviceCount>, Error>;
+
+    /// Streams the matching detections, archived ones included, oldest first into `records`.
+    /// Stops early when the receiver is dropped.
+    async fn export_detections(&self, filter: &DetectionFilter, records: Sender<ExportRecord>) -> Result<u64, Error>;
+
+    /// Streams the matching incidents oldest first into `records`, filtered by the area that was alerted.
+    /// Stops early when the receiver is dropped.
+    async fn export_incidents(&self, filter: &DetectionFilter, records: Sender<IncidentRecord>) -> Result<u64, Error>;
+
+    /// Folds the detections before `cutoff` into the daily counts and deletes or archives them,
+    /// after passing them to `sink`. Runs in one transaction, so a failing sink keeps them.
+    async fn prune_detections(&self, cutoff: DateTime<Utc>, archive: bool, sink: Option<&mut dyn ArchiveSink>) -> Result<Pruned, Error>;
+}
+
+/// Receives the detections a retention run removes, before they are removed.
+pub trait ArchiveSink: Send {
+    fn write(&mut self, detection: &Detection) -> std::io::Result<()>;
+
+    /// Called before the removal is committed, an error keeps the detections.
+    /// The output must not be handed on before the commit succeeded.
+    fn finish(&mut self) -> std::io::Result<()>;
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Pruned {
+    pub removed: u64,
+    /// Updated daily counts.
+    pub days: u64,
+}
+
+/// Storage of one model in Postgres. The queries are checked against the schema at compile time,
+/// offline from the metadata in `.sqlx`, which `cargo sqlx prepare` updates.
+/// Only Postgres has repositories, `SqliteStorage` builds its statements at runtime.
+///
+/// Every method takes a connection, so several calls can share a transaction.
+/// The scenarios in `tests` run them against a server when `TEST_DATABASE_URL` is set.
+#[allow(async_fn_in_trait)]
+pub trait Repository {
+    type Model;
+    /// What is needed to store a new model.
+    type New: ?Sized;
+
+    async fn insert(new: &Self::New, con: &mut PgConnection) -> Result<Self::Model, Error>;
 
-    async fn get_by_id(id: i64, pool: &Pool<Postgres>) -> Result<Vec<T>, Error>;
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Self::Model>, Error>;
 }
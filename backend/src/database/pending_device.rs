@@ -0,0 +1,125 @@
+use sqlx::{Error, PgConnection};
+use uuid::Uuid;
+use crate::common::models::device::{Device, NewDevice, PendingDevice, Provisioning};
+use crate::database::{DeviceRepository, Repository};
+
+pub struct PendingDeviceRepository;
+
+impl Repository for PendingDeviceRepository {
+    type Model = PendingDevice;
+    /// The requested device, its id and request time are ignored.
+    type New = PendingDevice;
+
+    async fn insert(device: &PendingDevice, con: &mut PgConnection) -> Result<PendingDevice, Error> {
+        sqlx::query_as!(
+            PendingDevice,
+            "INSERT INTO pending_device (description, area, hardware_id, request_id, pairing_code) VALUES ($1, $2, $3, $4, $5)
+             RETURNING id, description, area, hardware_id, request_id, pairing_code, requested_at",
+            device.description,
+            device.area,
+            device.hardware_id,
+            device.request_id,
+            device.pairing_code,
+        )
+            .fetch_one(con).await
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<PendingDevice>, Error> {
+        sqlx::query_as!(
+            PendingDevice,
+            "SELECT id, description, area, hardware_id, request_id, pairing_code, requested_at FROM pending_device WHERE id = $1",
+            id,
+        )
+            .fetch_optional(con).await
+    }
+}
+
+impl PendingDeviceRepository {
+    /// Oldest request first.
+    pub async fn get_all(con: &mut PgConnection) -> Result<Vec<PendingDevice>, Error> {
+        sqlx::query_as!(
+            PendingDevice,
+            "SELECT id, description, area, hardware_id, request_id, pairing_code, requested_at FROM pending_device ORDER BY id",
+        )
+            .fetch_all(con).await
+    }
+
+    /// The pending device with the hardware id or else the request id.
+    pub async fn get_by_identity(hardware_id: Option<&str>, request_id: Option<&str>, con: &mut PgConnection) -> Result<Option<PendingDevice>, Error> {
+        if hardware_id.is_none() && request_id.is_none() {
+            return Ok(None);
+        }
+
+        sqlx::query_as!(
+            PendingDevice,
+            "SELECT id, description, area, hardware_id, request_id, pairing_code, requested_at FROM pending_device
+             WHERE hardware_id = $1 OR request_id = $2
+             ORDER BY hardware_id = $1 DESC NULLS LAST LIMIT 1",
+            hardware_id,
+            request_id,
+        )
+            .fetch_optional(con).await
+    }
+
+    pub async fn delete(id: i64, con: &mut PgConnection) -> Result<Option<PendingDevice>, Error> {
+        sqlx::query_as!(
+            PendingDevice,
+            "DELETE FROM pending_device WHERE id = $1
+             RETURNING id, description, area, hardware_id, request_id, pairing_code, requested_at",
+            id,
+        )
+            .fetch_optional(con).await
+    }
+
+    /// Returns the approved device or the pending request with the same hardware or request id,
+    /// or queues the device with `pairing_code`. Run it in a transaction.
+    pub async fn provision(device: &NewDevice, pairing_code: &str, con: &mut PgConnection) -> Result<Provisioning, Error> {
+        let hardware_id = device.hardware_id.as_deref();
+        let request_id = device.request_id.as_deref();
+
+        if let Some(known) = DeviceRepository::get_by_identity(hardware_id, request_id, con).await? {
+            return Ok(Provisioning::Known(known));
+        }
+        if let Some(pending) = Self::get_by_identity(hardware_id, request_id, con).await? {
+            return Ok(Provisioning::Pending(pending));
+        }
+
+        let inserted = sqlx::query_as!(
+            PendingDevice,
+            "INSERT INTO pending_device (description, area, hardware_id, request_id, pairing_code) VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT DO NOTHING RETURNING id, description, area, hardware_id, request_id, pairing_code, requested_at",
+            device.description,
+            device.area,
+            device.hardware_id,
+            device.request_id,
+            pairing_code,
+        )
+            .fetch_optional(&mut *con).await?;
+
+        // A concurrent registration with the same ids got there first
+        match inserted {
+            Some(pending) => Ok(Provisioning::Pending(pending)),
+            None => Self::get_by_identity(hardware_id, request_id, con).await?
+                .map(Provisioning::Pending)
+                .ok_or(Error::RowNotFound),
+        }
+    }
+
+    /// Moves the pending device to the devices, with the description and area given by the admin.
+    /// Run it in a transaction.
+    pub async fn approve(id: i64, description: &str, area: &str, con: &mut PgConnection) -> Result<Option<Device>, Error> {
+        let Some(pending) = Self::delete(id, con).await? else {
+            return Ok(None);
+        };
+
+        let device = NewDevice {
+            uuid: Uuid::new_v4(),
+            description: description.to_string(),
+            area: area.to_string(),
+            hardware_id: pending.hardware_id,
+            request_id: pending.request_id,
+        };
+
+        DeviceRepository::insert(&device, con).await.map(Some)
+    }
+}
@@ -0,0 +1,342 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_channel::mpsc::Sender;
+use sqlx::{Error, PgPool};
+use sqlx::migrate::Migrator;
+use sqlx::postgres::PgPoolOptions;
+use tracing::info;
+use uuid::Uuid;
+use crate::common::models::area::{slug_fixes, Area, SlugFix};
+use crate::common::models::config::{ConfigChange, DeviceConfig};
+use crate::common::models::correlation::{CorrelationRule, NewPreAlarm, PreAlarm, PreAlarmQuery, RuleChange};
+use crate::common::models::detection::{Detection, NewDetection};
+use crate::common::models::device::{Device, NewDevice, PendingDevice, Provisioning, Registration};
+use crate::common::models::firmware::{Firmware, NewFirmware, NewRollout, Rollout, UpdateOffer, UpdateStatus};
+use crate::common::models::history::{AreaCount, Bucket, DetectionFilter, DetectionPage, DeviceCount, ExportRecord, PageRequest};
+use crate::common::models::incident::{Incident, IncidentRecord, NewIncident};
+use crate::common::models::sensor::normalize_source;
+use crate::common::models::telemetry::{DeviceStatus, NewTelemetry, Telemetry, TelemetryQuery};
+use crate::database::{ArchiveSink, AreaRepository, CorrelationRepository, DataMigrationRepository, DetectionRepository, DeviceConfigRepository, DeviceRepository, FirmwareRepository, IncidentRepository, PendingDeviceRepository, PreAlarmRepository, Pruned, Repository, RolloutRepository, Storage, TelemetryRepository};
+use crate::database::data_migration::{AREA_SLUGS, SOURCES};
+use crate::database::retention::{aggregate_before, archive_before, delete_before, export_before};
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./../migrations");
+
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let pool = PgPoolOptions::new()
+            .min_connections(10)
+            .connect(url).await?;
+
+        MIGRATOR.run(&pool).await?;
+        Self::normalize_area_slugs(&pool).await?;
+        Self::normalize_sources(&pool).await?;
+
+        Ok(PostgresStorage { pool })
+    }
+
+    /// Renames areas whose slug doesn't match `Area::slugify`, or merges them into the area that already has it.
+    /// Otherwise `area_get_or_create` would create a second area for them.
+    async fn normalize_area_slugs(pool: &PgPool) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        if DataMigrationRepository::applied(AREA_SLUGS, &mut tx).await? {
+            return Ok(());
+        }
+
+        let areas = AreaRepository::get_all(&mut tx).await?;
+        for fix in slug_fixes(&areas) {
+            info!(?fix, "Normalizing area slug");
+
+            match fix {
+                SlugFix::Rename { id, slug } => AreaRepository::set_slug(id, &slug, &mut tx).await?,
+                SlugFix::Merge { from, into } => AreaRepository::merge(from, into, &mut tx).await?,
+            }
+        }
+
+        DataMigrationRepository::record(AREA_SLUGS, &mut tx).await?;
+        tx.commit().await
+    }
+
+    /// Finishes the normalization of the sources by migration 0013, which only folded ASCII like `lower()` does
+    /// with the C collation. Sources like `TÜR` are normalized here, the same way as new detections.
+    async fn normalize_sources(pool: &PgPool) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        if DataMigrationRepository::applied(SOURCES, &mut tx).await? {
+            return Ok(());
+        }
+
+        for source in DataMigrationRepository::non_ascii_sources(&mut tx).await? {
+            let normalized = normalize_source(&source);
+            if normalized != source {
+                DataMigrationRepository::rename_source(&source, &normalized, &mut tx).await?;
+            }
+        }
+
+        DataMigrationRepository::record(SOURCES, &mut tx).await?;
+        tx.commit().await
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    fn backend(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>, Error> {
+        let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(&self.pool).await?;
+
+        Ok(MIGRATOR.iter()
+            .map(|migration| migration.version)
+            .filter(|version| !applied.contains(version))
+            .collect())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    async fn areas(&self) -> Result<Vec<Area>, Error> {
+        AreaRepository::get_all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn area_get_or_create(&self, name: &str) -> Result<Area, Error> {
+        AreaRepository::get_or_create(name, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn update_area(&self, area: &Area) -> Result<(), Error> {
+        AreaRepository::update(area, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn device_by_uuid(&self, uuid: Uuid) -> Result<Option<Device>, Error> {
+        DeviceRepository::get_by_uuid(uuid, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn register_device(&self, device: &NewDevice) -> Result<Registration, Error> {
+        let mut tx = self.pool.begin().await?;
+        let registration = DeviceRepository::register(device, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(registration)
+    }
+
+    async fn provision_device(&self, device: &NewDevice, pairing_code: &str) -> Result<Provisioning, Error> {
+        let mut tx = self.pool.begin().await?;
+        let provisioning = PendingDeviceRepository::provision(device, pairing_code, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(provisioning)
+    }
+
+    async fn pending_devices(&self) -> Result<Vec<PendingDevice>, Error> {
+        PendingDeviceRepository::get_all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn approve_device(&self, id: i64, description: &str, area: &str) -> Result<Option<Device>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let device = PendingDeviceRepository::approve(id, description, area, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(device)
+    }
+
+    async fn reject_device(&self, id: i64) -> Result<Option<PendingDevice>, Error> {
+        PendingDeviceRepository::delete(id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn device_token_hash(&self, device_id: i64) -> Result<Option<String>, Error> {
+        DeviceRepository::token_hash(device_id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn set_device_token_hash(&self, device_id: i64, hash: Option<&str>) -> Result<(), Error> {
+        DeviceRepository::set_token_hash(device_id, hash, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn device_config(&self, device_id: i64) -> Result<Option<DeviceConfig>, Error> {
+        DeviceConfigRepository::get(device_id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn device_configs(&self) -> Result<Vec<DeviceConfig>, Error> {
+        DeviceConfigRepository::get_all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn update_device_config(&self, device_id: i64, change: &ConfigChange) -> Result<Option<DeviceConfig>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let config = DeviceConfigRepository::update(device_id, change, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(config)
+    }
+
+    async fn acknowledge_config(&self, device_id: i64, version: i64) -> Result<Option<DeviceConfig>, Error> {
+        DeviceConfigRepository::acknowledge(device_id, version, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn device_statuses(&self) -> Result<Vec<DeviceStatus>, Error> {
+        let mut con = self.pool.acquire().await?;
+        let devices = DeviceRepository::get_all(&mut con).await?;
+        let mut latest = TelemetryRepository::latest_all(&mut con).await?;
+
+        Ok(devices.into_iter()
+            .map(|device| {
+                let telemetry = latest.iter().position(|t| t.device_id == device.id).map(|index| latest.swap_remove(index));
+                DeviceStatus { device, telemetry }
+            })
+            .collect())
+    }
+
+    async fn insert_telemetry(&self, telemetry: &NewTelemetry) -> Result<Telemetry, Error> {
+        TelemetryRepository::insert(telemetry, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn latest_telemetry(&self, device_id: i64) -> Result<Option<Telemetry>, Error> {
+        TelemetryRepository::latest(device_id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn telemetry_history(&self, device_id: i64, query: &TelemetryQuery) -> Result<Vec<Telemetry>, Error> {
+        TelemetryRepository::history(device_id, query, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn insert_firmware(&self, firmware: &NewFirmware) -> Result<Firmware, Error> {
+        FirmwareRepository::insert(firmware, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn firmwares(&self) -> Result<Vec<Firmware>, Error> {
+        FirmwareRepository::get_all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn firmware_image(&self, id: i64) -> Result<Option<(Firmware, Vec<u8>)>, Error> {
+        let mut con = self.pool.acquire().await?;
+        let Some(firmware) = FirmwareRepository::get_by_id(id, &mut con).await? else {
+            return Ok(None);
+        };
+
+        Ok(FirmwareRepository::image(id, &mut con).await?.map(|image| (firmware, image)))
+    }
+
+    async fn create_rollout(&self, rollout: &NewRollout) -> Result<Option<Rollout>, Error> {
+        let mut tx = self.pool.begin().await?;
+        if FirmwareRepository::get_by_id(rollout.firmware_id, &mut tx).await?.is_none() {
+            return Ok(None);
+        }
+
+        let rollout = RolloutRepository::insert(rollout, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(Some(rollout))
+    }
+
+    async fn rollouts(&self) -> Result<Vec<Rollout>, Error> {
+        RolloutRepository::get_all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn rollout(&self, id: i64) -> Result<Option<Rollout>, Error> {
+        RolloutRepository::get_by_id(id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn advance_rollout(&self, id: i64) -> Result<Option<Rollout>, Error> {
+        let mut tx = self.pool.begin().await?;
+        let rollout = RolloutRepository::advance(id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(rollout)
+    }
+
+    async fn set_update_status(&self, rollout_id: i64, device_id: i64, status: UpdateStatus, error: Option<&str>) -> Result<Option<Rollout>, Error> {
+        RolloutRepository::set_status(rollout_id, device_id, status, error, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn update_offers(&self, device_id: i64) -> Result<Vec<UpdateOffer>, Error> {
+        RolloutRepository::offers(device_id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn insert_detection(&self, detection: &NewDetection) -> Result<Detection, Error> {
+        DetectionRepository::insert(detection, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn add_detection_repeat(&self, id: i64) -> Result<(), Error> {
+        DetectionRepository::add_repeat(id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn correlation_rule(&self, area_id: i64) -> Result<Option<CorrelationRule>, Error> {
+        CorrelationRepository::get(area_id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn correlation_rules(&self) -> Result<Vec<CorrelationRule>, Error> {
+        CorrelationRepository::get_all(&mut *self.pool.acquire().await?).await
+    }
+
+    async fn set_correlation_rule(&self, change: &RuleChange) -> Result<CorrelationRule, Error> {
+        let mut tx = self.pool.begin().await?;
+        let rule = CorrelationRepository::set(change, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(rule)
+    }
+
+    async fn delete_correlation_rule(&self, area: &str) -> Result<Option<CorrelationRule>, Error> {
+        CorrelationRepository::delete(area, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn insert_pre_alarm(&self, pre_alarm: &NewPreAlarm) -> Result<PreAlarm, Error> {
+        PreAlarmRepository::insert(pre_alarm, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn pre_alarms(&self, query: &PreAlarmQuery) -> Result<Vec<PreAlarm>, Error> {
+        PreAlarmRepository::history(query, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn insert_incident(&self, incident: &NewIncident) -> Result<Incident, Error> {
+        IncidentRepository::insert(incident, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn detection_history(&self, filter: &DetectionFilter, page: PageRequest) -> Result<DetectionPage, Error> {
+        DetectionRepository::history(filter, page, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn detection_counts(&self, filter: &DetectionFilter, bucket: Bucket) -> Result<Vec<AreaCount>, Error> {
+        DetectionRepository::counts(filter, bucket, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn noisy_devices(&self, filter: &DetectionFilter, limit: i64) -> Result<Vec<DeviceCount>, Error> {
+        DetectionRepository::noisy_devices(filter, limit, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn export_detections(&self, filter: &DetectionFilter, records: Sender<ExportRecord>) -> Result<u64, Error> {
+        DetectionRepository::export(filter, &mut *self.pool.acquire().await?, records).await
+    }
+
+    async fn export_incidents(&self, filter: &DetectionFilter, records: Sender<IncidentRecord>) -> Result<u64, Error> {
+        IncidentRepository::export(filter, &mut *self.pool.acquire().await?, records).await
+    }
+
+    async fn prune_detections(&self, cutoff: DateTime<Utc>, archive: bool, sink: Option<&mut dyn ArchiveSink>) -> Result<Pruned, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(sink) = sink {
+            export_before(cutoff, &mut tx, sink).await?;
+            sink.finish().map_err(Error::Io)?;
+        }
+
+        let days = aggregate_before(cutoff, &mut tx).await?;
+        let removed = if archive {
+            archive_before(cutoff, &mut tx).await?
+        } else {
+            delete_before(cutoff, &mut tx).await?
+        };
+
+        tx.commit().await?;
+
+        Ok(Pruned { removed, days })
+    }
+}
@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use sqlx::{Error, PgConnection};
+use crate::common::models::sensor::ContactState;
+use crate::database::ArchiveSink;
+use crate::database::detection::DetectionRow;
+
+/// Passes every detection before `cutoff` to `sink`, oldest first, without loading them all at once.
+pub async fn export_before(cutoff: DateTime<Utc>, con: &mut PgConnection, sink: &mut dyn ArchiveSink) -> Result<u64, Error> {
+    let mut rows = sqlx::query_as!(
+        DetectionRow,
+        r#"SELECT detection.id, detection.source, detection.state AS "state: ContactState", detection.timestamp, detection.count, device.id AS device_id, device.uuid AS device_uuid,
+             device.description AS device_description, area.slug AS area, device.area_id
+         FROM detection JOIN device ON device.id = detection.device_id JOIN area ON area.id = device.area_id
+         WHERE detection.timestamp < $1 ORDER BY detection.id"#,
+        cutoff,
+    )
+        .fetch(con);
+    let mut exported = 0;
+
+    while let Some(row) = rows.try_next().await? {
+        sink.write(&row.into()).map_err(Error::Io)?;
+        exported += 1;
+    }
+
+    Ok(exported)
+}
+
+/// Adds the detections before `cutoff` to the daily counts, returns the number of updated days.
+pub async fn aggregate_before(cutoff: DateTime<Utc>, con: &mut PgConnection) -> Result<u64, Error> {
+    let res = sqlx::query!(
+        "INSERT INTO detection_daily (day, device_id, source, count)
+         SELECT date_trunc('day', timestamp), device_id, source, SUM(count) FROM detection WHERE timestamp < $1 GROUP BY 1, 2, 3
+         ON CONFLICT (day, device_id, source) DO UPDATE SET count = detection_daily.count + excluded.count",
+        cutoff,
+    )
+        .execute(con).await?;
+
+    Ok(res.rows_affected())
+}
+
+/// Moves the detections before `cutoff` to the archive table.
+pub async fn archive_before(cutoff: DateTime<Utc>, con: &mut PgConnection) -> Result<u64, Error> {
+    let res = sqlx::query!(
+        "WITH moved AS (DELETE FROM detection WHERE timestamp < $1 RETURNING id, device_id, source, state, timestamp, count)
+         INSERT INTO detection_archive (id, device_id, source, state, timestamp, count) SELECT id, device_id, source, state, timestamp, count FROM moved",
+        cutoff,
+    )
+        .execute(con).await?;
+
+    Ok(res.rows_affected())
+}
+
+pub async fn delete_before(cutoff: DateTime<Utc>, con: &mut PgConnection) -> Result<u64, Error> {
+    let res = sqlx::query!("DELETE FROM detection WHERE timestamp < $1", cutoff)
+        .execute(con).await?;
+
+    Ok(res.rows_affected())
+}
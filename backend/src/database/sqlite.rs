@@ -0,0 +1,1121 @@
+use std::str::FromStr;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_channel::mpsc::Sender;
+use futures_util::{SinkExt, TryStreamExt};
+use sqlx::{Arguments, Encode, Error, SqliteConnection, SqlitePool, Sqlite, Type};
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::{SqliteArguments, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use tracing::info;
+use uuid::Uuid;
+use crate::common::models::area::{slug_fixes, Area, SlugFix};
+use crate::common::models::config::{ConfigChange, DeviceConfig};
+use crate::common::models::correlation::{CorrelationRule, NewPreAlarm, PreAlarm, PreAlarmQuery, RuleChange};
+use crate::common::models::detection::{Detection, NewDetection};
+use crate::common::models::device::{Device, NewDevice, PendingDevice, Provisioning, Registration};
+use crate::common::models::firmware::{Firmware, FirmwareUpdate, NewFirmware, NewRollout, Rollout, UpdateOffer, UpdateStatus};
+use crate::common::models::history::{AreaCount, Bucket, DetectionFilter, DetectionPage, DeviceCount, ExportRecord, PageRequest};
+use crate::common::models::incident::{Incident, IncidentRecord, NewIncident};
+use crate::common::models::sensor::normalize_source;
+use crate::common::models::telemetry::{DeviceStatus, NewTelemetry, Telemetry, TelemetryQuery};
+use crate::database::{ArchiveSink, Pruned, Storage};
+use crate::database::data_migration::{AREA_SLUGS, SOURCES};
+use crate::database::detection::DetectionRow;
+
+pub static MIGRATOR: Migrator = sqlx::migrate!("./../migrations_sqlite");
+
+/// Single file database, for installs without a Postgres server. Timestamps are stored as RFC 3339 text in UTC,
+/// so they compare and sort as text.
+///
+/// The queries are checked at runtime only, the compile time checks of sqlx work against one database
+/// and that is Postgres.
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    pub async fn connect(url: &str) -> Result<Self, Error> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .foreign_keys(true)
+            .journal_mode(SqliteJournalMode::Wal);
+
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options).await?;
+
+        MIGRATOR.run(&pool).await?;
+        Self::normalize_area_slugs(&pool).await?;
+        Self::normalize_sources(&pool).await?;
+
+        Ok(SqliteStorage { pool })
+    }
+
+    /// Renames areas whose slug doesn't match `Area::slugify`, or merges them into the area that already has it.
+    /// Otherwise `area_get_or_create` would create a second area for them.
+    async fn normalize_area_slugs(pool: &SqlitePool) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        if Self::data_migration_applied(AREA_SLUGS, &mut tx).await? {
+            return Ok(());
+        }
+
+        let areas: Vec<Area> = sqlx::query_as("SELECT * FROM area ORDER BY id").fetch_all(&mut *tx).await?;
+        for fix in slug_fixes(&areas) {
+            info!(?fix, "Normalizing area slug");
+
+            match fix {
+                SlugFix::Rename { id, slug } => {
+                    sqlx::query("UPDATE area SET slug = ?1 WHERE id = ?2")
+                        .bind(&slug)
+                        .bind(id)
+                        .execute(&mut *tx).await?;
+                }
+                SlugFix::Merge { from, into } => {
+                    // The rule of the area that already had the slug wins
+                    let statements = [
+                        "UPDATE device SET area_id = ?2 WHERE area_id = ?1",
+                        "UPDATE area SET parent_id = CASE WHEN id = ?2 THEN NULL ELSE ?2 END WHERE parent_id = ?1",
+                        "UPDATE pre_alarm SET area_id = ?2 WHERE area_id = ?1",
+                        "UPDATE incident SET area_id = ?2 WHERE area_id = ?1",
+                        "UPDATE correlation_rule SET area_id = ?2 WHERE area_id = ?1 AND NOT EXISTS (SELECT 1 FROM correlation_rule WHERE area_id = ?2)",
+                        "DELETE FROM correlation_rule WHERE area_id = ?1",
+                        "DELETE FROM area WHERE id = ?1",
+                    ];
+                    for statement in statements {
+                        sqlx::query(statement)
+                            .bind(from)
+                            .bind(into)
+                            .execute(&mut *tx).await?;
+                    }
+                }
+            }
+        }
+
+        Self::record_data_migration(AREA_SLUGS, &mut tx).await?;
+        tx.commit().await
+    }
+
+    async fn data_migration_applied(name: &str, con: &mut SqliteConnection) -> Result<bool, Error> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM data_migration WHERE name = ?1)")
+            .bind(name)
+            .fetch_one(con).await
+    }
+
+    /// Run it in the transaction of the step, so the step is recorded only if it went through.
+    async fn record_data_migration(name: &str, con: &mut SqliteConnection) -> Result<(), Error> {
+        sqlx::query("INSERT INTO data_migration (name) VALUES (?1)")
+            .bind(name)
+            .execute(con).await?;
+
+        Ok(())
+    }
+
+    /// Finishes the normalization of the sources by migration 0013. `lower()` of SQLite only folds ASCII,
+    /// so sources with other characters like `TÜR` are normalized here, the same way as new detections.
+    async fn normalize_sources(pool: &SqlitePool) -> Result<(), Error> {
+        const COLUMNS: [(&str, &str); 5] = [
+            ("detection", "source"),
+            ("detection_archive", "source"),
+            ("pre_alarm", "source"),
+            ("correlation_rule", "first_source"),
+            ("correlation_rule", "then_source"),
+        ];
+
+        let mut tx = pool.begin().await?;
+        if Self::data_migration_applied(SOURCES, &mut tx).await? {
+            return Ok(());
+        }
+
+        for (table, column) in COLUMNS {
+            for source in Self::non_ascii_sources(table, column, &mut tx).await? {
+                sqlx::query(&format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, column))
+                    .bind(normalize_source(&source))
+                    .bind(&source)
+                    .execute(&mut *tx).await?;
+            }
+        }
+
+        // Daily counts of sources that are now the same are added up
+        for source in Self::non_ascii_sources("detection_daily", "source", &mut tx).await? {
+            sqlx::query(
+                "INSERT INTO detection_daily (day, device_id, source, count) \
+                 SELECT day, device_id, ?1, count FROM detection_daily WHERE source = ?2 \
+                 ON CONFLICT (day, device_id, source) DO UPDATE SET count = detection_daily.count + excluded.count",
+            )
+                .bind(normalize_source(&source))
+                .bind(&source)
+                .execute(&mut *tx).await?;
+            sqlx::query("DELETE FROM detection_daily WHERE source = ?1")
+                .bind(&source)
+                .execute(&mut *tx).await?;
+        }
+
+        Self::record_data_migration(SOURCES, &mut tx).await?;
+        tx.commit().await
+    }
+
+    /// The sources with non-ASCII characters that aren't normalized yet.
+    async fn non_ascii_sources(table: &str, column: &str, con: &mut SqliteConnection) -> Result<Vec<String>, Error> {
+        let statement = format!("SELECT DISTINCT {} FROM {} WHERE {} GLOB '*[^ -~]*'", column, table, column);
+        let sources: Vec<String> = sqlx::query_scalar(&statement).fetch_all(con).await?;
+
+        Ok(sources.into_iter().filter(|source| normalize_source(source) != *source).collect())
+    }
+
+    async fn area_get_or_create_in(name: &str, con: &mut SqliteConnection) -> Result<Area, Error> {
+        let slug = Area::slugify(name);
+
+        sqlx::query("INSERT INTO area (slug, name) VALUES (?1, ?2) ON CONFLICT (slug) DO NOTHING")
+            .bind(&slug)
+            .bind(name.trim().trim_matches('/'))
+            .execute(&mut *con).await?;
+
+        sqlx::query_as("SELECT * FROM area WHERE slug = ?1")
+            .bind(&slug)
+            .fetch_one(con).await
+    }
+
+    /// The device registered with the hardware id or else the request id of `device`.
+    async fn device_by_identity(device: &NewDevice, con: &mut SqliteConnection) -> Result<Option<Device>, Error> {
+        if device.hardware_id.is_none() && device.request_id.is_none() {
+            return Ok(None);
+        }
+
+        sqlx::query_as(
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id \
+             FROM device JOIN area ON area.id = device.area_id \
+             WHERE device.hardware_id = ?1 OR device.request_id = ?2 \
+             ORDER BY device.hardware_id = ?1 DESC LIMIT 1",
+        )
+            .bind(&device.hardware_id)
+            .bind(&device.request_id)
+            .fetch_optional(con).await
+    }
+
+    /// Inserts nothing if the hardware or request id is taken.
+    async fn insert_device_in(device: &NewDevice, con: &mut SqliteConnection) -> Result<Option<Device>, Error> {
+        let area = Self::area_get_or_create_in(&device.area, con).await?;
+
+        let id: Option<i64> = sqlx::query_scalar(
+            "INSERT INTO device (uuid, description, area_id, hardware_id, request_id) VALUES (?1, ?2, ?3, ?4, ?5) \
+             ON CONFLICT DO NOTHING RETURNING id",
+        )
+            .bind(device.uuid)
+            .bind(&device.description)
+            .bind(area.id)
+            .bind(&device.hardware_id)
+            .bind(&device.request_id)
+            .fetch_optional(con).await?;
+
+        Ok(id.map(|id| Device {
+            id,
+            uuid: device.uuid,
+            description: device.description.clone(),
+            area: area.slug,
+            area_id: area.id,
+        }))
+    }
+
+    /// The pending device with the hardware id or else the request id of `device`.
+    async fn pending_by_identity(device: &NewDevice, con: &mut SqliteConnection) -> Result<Option<PendingDevice>, Error> {
+        if device.hardware_id.is_none() && device.request_id.is_none() {
+            return Ok(None);
+        }
+
+        sqlx::query_as("SELECT * FROM pending_device WHERE hardware_id = ?1 OR request_id = ?2 ORDER BY hardware_id = ?1 DESC LIMIT 1")
+            .bind(&device.hardware_id)
+            .bind(&device.request_id)
+            .fetch_optional(con).await
+    }
+
+    async fn config_in(device_id: i64, con: &mut SqliteConnection) -> Result<Option<DeviceConfig>, Error> {
+        let statement = format!("SELECT {} WHERE device_config.device_id = ?1", CONFIG_QUERY);
+
+        sqlx::query_as(&statement).bind(device_id).fetch_optional(con).await
+    }
+
+    async fn firmware_in(id: i64, con: &mut SqliteConnection) -> Result<Option<Firmware>, Error> {
+        let statement = format!("SELECT {} FROM firmware WHERE id = ?1", FIRMWARE_COLUMNS);
+
+        sqlx::query_as(&statement).bind(id).fetch_optional(con).await
+    }
+
+    async fn rollout_in(id: i64, con: &mut SqliteConnection) -> Result<Option<Rollout>, Error> {
+        let rollout: Option<(i64, i32, i32, DateTime<Utc>)> = sqlx::query_as("SELECT firmware_id, batch_size, stage, created_at FROM firmware_rollout WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&mut *con).await?;
+        let Some((firmware_id, batch_size, stage, created_at)) = rollout else {
+            return Ok(None);
+        };
+
+        let firmware = Self::firmware_in(firmware_id, con).await?.ok_or(Error::RowNotFound)?;
+        let updates: Vec<FirmwareUpdate> = sqlx::query_as("SELECT * FROM firmware_update WHERE rollout_id = ?1 ORDER BY stage, device_id")
+            .bind(id)
+            .fetch_all(&mut *con).await?;
+
+        Ok(Some(Rollout { id, firmware, batch_size, stage, created_at, updates }))
+    }
+
+    async fn detection_by_id(id: i64, con: &mut SqliteConnection) -> Result<Detection, Error> {
+        let statement = format!("SELECT {} {} WHERE detection.id = ?1", DETECTION_COLUMNS, from_clause());
+
+        let row: DetectionRow = sqlx::query_as(&statement).bind(id).fetch_one(con).await?;
+
+        Ok(row.into())
+    }
+}
+
+
+// ---- Query building
+
+/// Collects the arguments of a query built from optional filters and hands out their placeholders.
+#[derive(Default)]
+struct Parameters {
+    args: SqliteArguments<'static>,
+    count: usize,
+}
+
+impl Parameters {
+    fn bind<T>(&mut self, value: T) -> String
+    where
+        T: 'static + Send + Encode<'static, Sqlite> + Type<Sqlite>,
+    {
+        self.args.add(value);
+        self.count += 1;
+        format!("?{}", self.count)
+    }
+}
+
+fn conditions(filter: &DetectionFilter, params: &mut Parameters) -> Vec<String> {
+    let mut conditions = Vec::new();
+
+    if !filter.areas.is_empty() {
+        let placeholders: Vec<String> = filter.areas.iter().map(|area| params.bind(area.clone())).collect();
+        conditions.push(format!("area.slug IN ({})", placeholders.join(", ")));
+    }
+    if let Some(device) = filter.device {
+        conditions.push(format!("device.uuid = {}", params.bind(device)));
+    }
+    if let Some(source) = &filter.source {
+        conditions.push(format!("detection.source = {}", params.bind(source.clone())));
+    }
+    if let Some(from) = filter.from {
+        conditions.push(format!("detection.timestamp >= {}", params.bind(from)));
+    }
+    if let Some(to) = filter.to {
+        conditions.push(format!("detection.timestamp < {}", params.bind(to)));
+    }
+
+    conditions
+}
+
+fn where_clause(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
+/// Columns of a detection with its device and area, read into a `DetectionRow`.
+const DETECTION_COLUMNS: &str = "detection.id, detection.source, detection.state, detection.timestamp, detection.count, device.id AS device_id, device.uuid AS device_uuid, \
+    device.description AS device_description, area.slug AS area, device.area_id";
+
+/// Columns and tables of a `DeviceConfig`.
+const CONFIG_QUERY: &str = "device_config.device_id, device_config.version, device.description, area.slug AS area, device_config.speaker_volume, \
+    device_config.motion_debounce_ms, device_config.heartbeat_interval_s, device_config.server_address, device_config.updated_at, \
+    device_config.applied_version, device_config.applied_at \
+    FROM device_config JOIN device ON device.id = device_config.device_id JOIN area ON area.id = device.area_id";
+
+/// Columns and tables of a `CorrelationRule`.
+const CORRELATION_QUERY: &str = "correlation_rule.area_id, area.slug AS area, correlation_rule.min_detections, correlation_rule.min_devices, \
+    correlation_rule.window_s, correlation_rule.first_source, correlation_rule.then_source, correlation_rule.updated_at \
+    FROM correlation_rule JOIN area ON area.id = correlation_rule.area_id";
+
+/// Columns and tables of a `PreAlarm`.
+const PRE_ALARM_QUERY: &str = "pre_alarm.id, pre_alarm.area_id, area.slug AS area, pre_alarm.device_id, pre_alarm.source, pre_alarm.timestamp, \
+    pre_alarm.detections, pre_alarm.devices \
+    FROM pre_alarm JOIN area ON area.id = pre_alarm.area_id";
+
+/// Columns of a `Firmware`, without the image.
+const FIRMWARE_COLUMNS: &str = "id, version, board, size, sha256, md5, uploaded_at";
+
+const TELEMETRY_COLUMNS: &str = "id, device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage";
+
+/// Joins detection, device and area, so every filter and aggregation can use all three tables.
+fn from_clause() -> String {
+    from_clause_of("detection")
+}
+
+/// Like `from_clause`, with `source` in place of the detection table. It has to be named `detection`.
+fn from_clause_of(source: &str) -> String {
+    format!("FROM {} JOIN device ON device.id = detection.device_id JOIN area ON area.id = device.area_id", source)
+}
+
+/// Start of the hour or day of a timestamp, in the same text format as the stored timestamps.
+fn truncate(bucket: Bucket, column: &str) -> String {
+    match bucket {
+        Bucket::Hour => format!("strftime('%Y-%m-%dT%H:00:00+00:00', {})", column),
+        Bucket::Day => format!("strftime('%Y-%m-%dT00:00:00+00:00', {})", column),
+    }
+}
+
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn backend(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn ping(&self) -> Result<(), Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> Result<Vec<i64>, Error> {
+        let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(&self.pool).await?;
+
+        Ok(MIGRATOR.iter()
+            .map(|migration| migration.version)
+            .filter(|version| !applied.contains(version))
+            .collect())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    async fn areas(&self) -> Result<Vec<Area>, Error> {
+        sqlx::query_as("SELECT * FROM area ORDER BY id").fetch_all(&self.pool).await
+    }
+
+    async fn area_get_or_create(&self, name: &str) -> Result<Area, Error> {
+        let mut tx = self.pool.begin().await?;
+        let area = Self::area_get_or_create_in(name, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(area)
+    }
+
+    async fn update_area(&self, area: &Area) -> Result<(), Error> {
+        sqlx::query(
+            "UPDATE area SET slug = ?1, name = ?2, parent_id = ?3, armed = ?4, notify = ?5, notification_topic = ?6, notification_priority = ?7, \
+                 kind = ?8, propagate = ?9 \
+             WHERE id = ?10",
+        )
+            .bind(&area.slug)
+            .bind(&area.name)
+            .bind(area.parent_id)
+            .bind(area.armed)
+            .bind(area.notify)
+            .bind(&area.notification_topic)
+            .bind(area.notification_priority)
+            .bind(area.kind)
+            .bind(area.propagate)
+            .bind(area.id)
+            .execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn device_by_uuid(&self, uuid: Uuid) -> Result<Option<Device>, Error> {
+        sqlx::query_as(
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id \
+             FROM device JOIN area ON area.id = device.area_id WHERE device.uuid = ?1",
+        )
+            .bind(uuid)
+            .fetch_optional(&self.pool).await
+    }
+
+    async fn register_device(&self, device: &NewDevice) -> Result<Registration, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(known) = Self::device_by_identity(device, &mut tx).await? {
+            return Ok(Registration { device: known, created: false });
+        }
+
+        // Inserts nothing if a concurrent registration with the same ids got there first
+        let registration = match Self::insert_device_in(device, &mut tx).await? {
+            Some(created) => Registration { device: created, created: true },
+            None => Registration {
+                device: Self::device_by_identity(device, &mut tx).await?.ok_or(Error::RowNotFound)?,
+                created: false,
+            },
+        };
+
+        tx.commit().await?;
+
+        Ok(registration)
+    }
+
+    async fn provision_device(&self, device: &NewDevice, pairing_code: &str) -> Result<Provisioning, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(known) = Self::device_by_identity(device, &mut tx).await? {
+            return Ok(Provisioning::Known(known));
+        }
+        if let Some(pending) = Self::pending_by_identity(device, &mut tx).await? {
+            return Ok(Provisioning::Pending(pending));
+        }
+
+        let inserted: Option<PendingDevice> = sqlx::query_as(
+            "INSERT INTO pending_device (description, area, hardware_id, request_id, pairing_code, requested_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+             ON CONFLICT DO NOTHING RETURNING *",
+        )
+            .bind(&device.description)
+            .bind(&device.area)
+            .bind(&device.hardware_id)
+            .bind(&device.request_id)
+            .bind(pairing_code)
+            .bind(Utc::now())
+            .fetch_optional(&mut *tx).await?;
+
+        let pending = match inserted {
+            Some(pending) => pending,
+            None => Self::pending_by_identity(device, &mut tx).await?.ok_or(Error::RowNotFound)?,
+        };
+
+        tx.commit().await?;
+
+        Ok(Provisioning::Pending(pending))
+    }
+
+    async fn pending_devices(&self) -> Result<Vec<PendingDevice>, Error> {
+        sqlx::query_as("SELECT * FROM pending_device ORDER BY id").fetch_all(&self.pool).await
+    }
+
+    async fn approve_device(&self, id: i64, description: &str, area: &str) -> Result<Option<Device>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let pending: Option<PendingDevice> = sqlx::query_as("DELETE FROM pending_device WHERE id = ?1 RETURNING *")
+            .bind(id)
+            .fetch_optional(&mut *tx).await?;
+        let Some(pending) = pending else {
+            return Ok(None);
+        };
+
+        let device = NewDevice {
+            uuid: Uuid::new_v4(),
+            description: description.to_string(),
+            area: area.to_string(),
+            hardware_id: pending.hardware_id,
+            request_id: pending.request_id,
+        };
+        let device = Self::insert_device_in(&device, &mut tx).await?.ok_or(Error::RowNotFound)?;
+
+        tx.commit().await?;
+
+        Ok(Some(device))
+    }
+
+    async fn reject_device(&self, id: i64) -> Result<Option<PendingDevice>, Error> {
+        sqlx::query_as("DELETE FROM pending_device WHERE id = ?1 RETURNING *")
+            .bind(id)
+            .fetch_optional(&self.pool).await
+    }
+
+    async fn device_token_hash(&self, device_id: i64) -> Result<Option<String>, Error> {
+        let hash: Option<Option<String>> = sqlx::query_scalar("SELECT token_hash FROM device WHERE id = ?1")
+            .bind(device_id)
+            .fetch_optional(&self.pool).await?;
+
+        Ok(hash.flatten())
+    }
+
+    async fn set_device_token_hash(&self, device_id: i64, hash: Option<&str>) -> Result<(), Error> {
+        sqlx::query("UPDATE device SET token_hash = ?1 WHERE id = ?2")
+            .bind(hash)
+            .bind(device_id)
+            .execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn device_config(&self, device_id: i64) -> Result<Option<DeviceConfig>, Error> {
+        Self::config_in(device_id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn device_configs(&self) -> Result<Vec<DeviceConfig>, Error> {
+        let statement = format!("SELECT {} ORDER BY device_config.device_id", CONFIG_QUERY);
+
+        sqlx::query_as(&statement).fetch_all(&self.pool).await
+    }
+
+    async fn update_device_config(&self, device_id: i64, change: &ConfigChange) -> Result<Option<DeviceConfig>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let device: Option<(String, i64)> = sqlx::query_as("SELECT description, area_id FROM device WHERE id = ?1")
+            .bind(device_id)
+            .fetch_optional(&mut *tx).await?;
+        let Some((description, area_id)) = device else {
+            return Ok(None);
+        };
+
+        let description = change.description.as_deref().map_or(description, |description| description.trim().to_string());
+        let area_id = match &change.area {
+            Some(area) => Self::area_get_or_create_in(area, &mut tx).await?.id,
+            None => area_id,
+        };
+
+        sqlx::query("UPDATE device SET description = ?1, area_id = ?2 WHERE id = ?3")
+            .bind(&description)
+            .bind(area_id)
+            .bind(device_id)
+            .execute(&mut *tx).await?;
+
+        sqlx::query(
+            "INSERT INTO device_config (device_id, speaker_volume, motion_debounce_ms, heartbeat_interval_s, server_address, updated_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6) \
+             ON CONFLICT (device_id) DO UPDATE SET version = device_config.version + 1, speaker_volume = excluded.speaker_volume, \
+                 motion_debounce_ms = excluded.motion_debounce_ms, heartbeat_interval_s = excluded.heartbeat_interval_s, \
+                 server_address = excluded.server_address, updated_at = excluded.updated_at",
+        )
+            .bind(device_id)
+            .bind(change.speaker_volume)
+            .bind(change.motion_debounce_ms)
+            .bind(change.heartbeat_interval_s)
+            .bind(&change.server_address)
+            .bind(Utc::now())
+            .execute(&mut *tx).await?;
+
+        let config = Self::config_in(device_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(config)
+    }
+
+    async fn acknowledge_config(&self, device_id: i64, version: i64) -> Result<Option<DeviceConfig>, Error> {
+        let mut con = self.pool.acquire().await?;
+
+        sqlx::query("UPDATE device_config SET applied_version = ?2, applied_at = ?3 WHERE device_id = ?1 AND ?2 BETWEEN 1 AND version")
+            .bind(device_id)
+            .bind(version)
+            .bind(Utc::now())
+            .execute(&mut *con).await?;
+
+        Self::config_in(device_id, &mut con).await
+    }
+
+    async fn device_statuses(&self) -> Result<Vec<DeviceStatus>, Error> {
+        let mut con = self.pool.acquire().await?;
+
+        let devices: Vec<Device> = sqlx::query_as(
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id \
+             FROM device JOIN area ON area.id = device.area_id ORDER BY device.id",
+        )
+            .fetch_all(&mut *con).await?;
+
+        let statement = format!(
+            "SELECT {} FROM telemetry WHERE id = \
+                 (SELECT latest.id FROM telemetry AS latest WHERE latest.device_id = telemetry.device_id ORDER BY latest.timestamp DESC, latest.id DESC LIMIT 1)",
+            TELEMETRY_COLUMNS,
+        );
+        let mut latest: Vec<Telemetry> = sqlx::query_as(&statement).fetch_all(&mut *con).await?;
+
+        Ok(devices.into_iter()
+            .map(|device| {
+                let telemetry = latest.iter().position(|t| t.device_id == device.id).map(|index| latest.swap_remove(index));
+                DeviceStatus { device, telemetry }
+            })
+            .collect())
+    }
+
+    async fn insert_telemetry(&self, telemetry: &NewTelemetry) -> Result<Telemetry, Error> {
+        let report = &telemetry.report;
+        let statement = format!(
+            "INSERT INTO telemetry (device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8) RETURNING {}",
+            TELEMETRY_COLUMNS,
+        );
+
+        sqlx::query_as(&statement)
+            .bind(telemetry.device_id)
+            .bind(telemetry.timestamp)
+            .bind(report.rssi)
+            .bind(report.free_heap)
+            .bind(report.uptime_s)
+            .bind(&report.reset_reason)
+            .bind(&report.firmware_version)
+            .bind(report.supply_voltage)
+            .fetch_one(&self.pool).await
+    }
+
+    async fn latest_telemetry(&self, device_id: i64) -> Result<Option<Telemetry>, Error> {
+        let statement = format!("SELECT {} FROM telemetry WHERE device_id = ?1 ORDER BY timestamp DESC, id DESC LIMIT 1", TELEMETRY_COLUMNS);
+
+        sqlx::query_as(&statement).bind(device_id).fetch_optional(&self.pool).await
+    }
+
+    async fn telemetry_history(&self, device_id: i64, query: &TelemetryQuery) -> Result<Vec<Telemetry>, Error> {
+        let statement = format!(
+            "SELECT {} FROM telemetry \
+             WHERE device_id = ?1 AND (?2 IS NULL OR timestamp >= ?2) AND (?3 IS NULL OR timestamp < ?3) \
+             ORDER BY timestamp DESC, id DESC LIMIT ?4",
+            TELEMETRY_COLUMNS,
+        );
+
+        sqlx::query_as(&statement)
+            .bind(device_id)
+            .bind(query.from)
+            .bind(query.to)
+            .bind(query.limit())
+            .fetch_all(&self.pool).await
+    }
+
+    async fn insert_firmware(&self, firmware: &NewFirmware) -> Result<Firmware, Error> {
+        let statement = format!(
+            "INSERT INTO firmware (version, board, size, sha256, md5, image, uploaded_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) RETURNING {}",
+            FIRMWARE_COLUMNS,
+        );
+
+        sqlx::query_as(&statement)
+            .bind(&firmware.version)
+            .bind(&firmware.board)
+            .bind(firmware.size())
+            .bind(&firmware.sha256)
+            .bind(&firmware.md5)
+            .bind(&firmware.image)
+            .bind(Utc::now())
+            .fetch_one(&self.pool).await
+    }
+
+    async fn firmwares(&self) -> Result<Vec<Firmware>, Error> {
+        let statement = format!("SELECT {} FROM firmware ORDER BY id DESC", FIRMWARE_COLUMNS);
+
+        sqlx::query_as(&statement).fetch_all(&self.pool).await
+    }
+
+    async fn firmware_image(&self, id: i64) -> Result<Option<(Firmware, Vec<u8>)>, Error> {
+        let mut con = self.pool.acquire().await?;
+        let Some(firmware) = Self::firmware_in(id, &mut con).await? else {
+            return Ok(None);
+        };
+
+        let image: Option<Vec<u8>> = sqlx::query_scalar("SELECT image FROM firmware WHERE id = ?1")
+            .bind(id)
+            .fetch_optional(&mut *con).await?;
+
+        Ok(image.map(|image| (firmware, image)))
+    }
+
+    async fn create_rollout(&self, rollout: &NewRollout) -> Result<Option<Rollout>, Error> {
+        let mut tx = self.pool.begin().await?;
+        if Self::firmware_in(rollout.firmware_id, &mut tx).await?.is_none() {
+            return Ok(None);
+        }
+
+        let now = Utc::now();
+        let id: i64 = sqlx::query_scalar("INSERT INTO firmware_rollout (firmware_id, batch_size, created_at) VALUES (?1, ?2, ?3) RETURNING id")
+            .bind(rollout.firmware_id)
+            .bind(rollout.batch_size)
+            .bind(now)
+            .fetch_one(&mut *tx).await?;
+
+        for (index, device_id) in rollout.device_ids.iter().enumerate() {
+            let stage = rollout.stage_of(index);
+            let status = if stage == 1 { UpdateStatus::Offered } else { UpdateStatus::Waiting };
+
+            sqlx::query("INSERT INTO firmware_update (rollout_id, device_id, stage, status, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)")
+                .bind(id)
+                .bind(device_id)
+                .bind(stage)
+                .bind(status)
+                .bind(now)
+                .execute(&mut *tx).await?;
+        }
+
+        let rollout = Self::rollout_in(id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(rollout)
+    }
+
+    async fn rollouts(&self) -> Result<Vec<Rollout>, Error> {
+        let mut con = self.pool.acquire().await?;
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM firmware_rollout ORDER BY id DESC")
+            .fetch_all(&mut *con).await?;
+
+        let mut rollouts = Vec::with_capacity(ids.len());
+        for id in ids {
+            rollouts.extend(Self::rollout_in(id, &mut con).await?);
+        }
+
+        Ok(rollouts)
+    }
+
+    async fn rollout(&self, id: i64) -> Result<Option<Rollout>, Error> {
+        Self::rollout_in(id, &mut *self.pool.acquire().await?).await
+    }
+
+    async fn advance_rollout(&self, id: i64) -> Result<Option<Rollout>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let stage: Option<i32> = sqlx::query_scalar(
+            "UPDATE firmware_rollout SET stage = stage + 1 \
+             WHERE id = ?1 AND EXISTS (SELECT 1 FROM firmware_update WHERE rollout_id = ?1 AND stage > firmware_rollout.stage) \
+             RETURNING stage",
+        )
+            .bind(id)
+            .fetch_optional(&mut *tx).await?;
+
+        if let Some(stage) = stage {
+            sqlx::query("UPDATE firmware_update SET status = 'offered', updated_at = ?3 WHERE rollout_id = ?1 AND stage = ?2 AND status = 'waiting'")
+                .bind(id)
+                .bind(stage)
+                .bind(Utc::now())
+                .execute(&mut *tx).await?;
+        }
+
+        let rollout = Self::rollout_in(id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(rollout)
+    }
+
+    async fn set_update_status(&self, rollout_id: i64, device_id: i64, status: UpdateStatus, error: Option<&str>) -> Result<Option<Rollout>, Error> {
+        let mut con = self.pool.acquire().await?;
+
+        let updated = sqlx::query(
+            "UPDATE firmware_update SET status = ?3, error = ?4, updated_at = ?5 WHERE rollout_id = ?1 AND device_id = ?2 AND status <> 'waiting'",
+        )
+            .bind(rollout_id)
+            .bind(device_id)
+            .bind(status)
+            .bind(error)
+            .bind(Utc::now())
+            .execute(&mut *con).await?;
+
+        if updated.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        Self::rollout_in(rollout_id, &mut con).await
+    }
+
+    async fn update_offers(&self, device_id: i64) -> Result<Vec<UpdateOffer>, Error> {
+        let mut con = self.pool.acquire().await?;
+
+        let offered: Vec<(i64, i64)> = sqlx::query_as(
+            "SELECT firmware_update.rollout_id, firmware_rollout.firmware_id \
+             FROM firmware_update JOIN firmware_rollout ON firmware_rollout.id = firmware_update.rollout_id \
+             WHERE firmware_update.device_id = ?1 AND firmware_update.status = 'offered' ORDER BY firmware_update.rollout_id",
+        )
+            .bind(device_id)
+            .fetch_all(&mut *con).await?;
+
+        let mut offers = Vec::with_capacity(offered.len());
+        for (rollout_id, firmware_id) in offered {
+            let firmware = Self::firmware_in(firmware_id, &mut con).await?.ok_or(Error::RowNotFound)?;
+            offers.push(UpdateOffer { rollout_id, firmware });
+        }
+
+        Ok(offers)
+    }
+
+    async fn insert_detection(&self, detection: &NewDetection) -> Result<Detection, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let id: i64 = sqlx::query_scalar("INSERT INTO detection (device_id, source, state, timestamp) VALUES (?1, ?2, ?3, ?4) RETURNING id")
+            .bind(detection.device_id)
+            .bind(&detection.source)
+            .bind(detection.state)
+            .bind(detection.timestamp)
+            .fetch_one(&mut *tx).await?;
+
+        let detection = Self::detection_by_id(id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(detection)
+    }
+
+    async fn add_detection_repeat(&self, id: i64) -> Result<(), Error> {
+        sqlx::query("UPDATE detection SET count = count + 1 WHERE id = ?1")
+            .bind(id)
+            .execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn correlation_rule(&self, area_id: i64) -> Result<Option<CorrelationRule>, Error> {
+        let statement = format!("SELECT {} WHERE correlation_rule.area_id = ?1", CORRELATION_QUERY);
+
+        sqlx::query_as(&statement).bind(area_id).fetch_optional(&self.pool).await
+    }
+
+    async fn correlation_rules(&self) -> Result<Vec<CorrelationRule>, Error> {
+        let statement = format!("SELECT {} ORDER BY area.slug", CORRELATION_QUERY);
+
+        sqlx::query_as(&statement).fetch_all(&self.pool).await
+    }
+
+    async fn set_correlation_rule(&self, change: &RuleChange) -> Result<CorrelationRule, Error> {
+        let mut tx = self.pool.begin().await?;
+        let area = Self::area_get_or_create_in(&change.area, &mut tx).await?;
+
+        sqlx::query(
+            "INSERT INTO correlation_rule (area_id, min_detections, min_devices, window_s, first_source, then_source, updated_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) \
+             ON CONFLICT (area_id) DO UPDATE SET min_detections = excluded.min_detections, min_devices = excluded.min_devices, \
+                 window_s = excluded.window_s, first_source = excluded.first_source, then_source = excluded.then_source, updated_at = excluded.updated_at",
+        )
+            .bind(area.id)
+            .bind(change.min_detections)
+            .bind(change.min_devices)
+            .bind(change.window_s)
+            .bind(change.first_source.as_deref().map(str::trim))
+            .bind(change.then_source.as_deref().map(str::trim))
+            .bind(Utc::now())
+            .execute(&mut *tx).await?;
+
+        let statement = format!("SELECT {} WHERE correlation_rule.area_id = ?1", CORRELATION_QUERY);
+        let rule = sqlx::query_as(&statement).bind(area.id).fetch_one(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(rule)
+    }
+
+    async fn delete_correlation_rule(&self, area: &str) -> Result<Option<CorrelationRule>, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let statement = format!("SELECT {} WHERE area.slug = ?1", CORRELATION_QUERY);
+        let rule: Option<CorrelationRule> = sqlx::query_as(&statement).bind(Area::slugify(area)).fetch_optional(&mut *tx).await?;
+
+        if let Some(rule) = &rule {
+            sqlx::query("DELETE FROM correlation_rule WHERE area_id = ?1")
+                .bind(rule.area_id)
+                .execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(rule)
+    }
+
+    async fn insert_pre_alarm(&self, pre_alarm: &NewPreAlarm) -> Result<PreAlarm, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO pre_alarm (area_id, device_id, source, timestamp, detections, devices) VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING id",
+        )
+            .bind(pre_alarm.area_id)
+            .bind(pre_alarm.device_id)
+            .bind(&pre_alarm.source)
+            .bind(pre_alarm.timestamp)
+            .bind(pre_alarm.detections)
+            .bind(pre_alarm.devices)
+            .fetch_one(&mut *tx).await?;
+
+        let statement = format!("SELECT {} WHERE pre_alarm.id = ?1", PRE_ALARM_QUERY);
+        let pre_alarm = sqlx::query_as(&statement).bind(id).fetch_one(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(pre_alarm)
+    }
+
+    async fn pre_alarms(&self, query: &PreAlarmQuery) -> Result<Vec<PreAlarm>, Error> {
+        let statement = format!(
+            "SELECT {} WHERE (?1 IS NULL OR area.slug = ?1) AND (?2 IS NULL OR pre_alarm.timestamp >= ?2) AND (?3 IS NULL OR pre_alarm.timestamp < ?3) \
+             ORDER BY pre_alarm.timestamp DESC, pre_alarm.id DESC LIMIT ?4",
+            PRE_ALARM_QUERY,
+        );
+
+        sqlx::query_as(&statement)
+            .bind(query.area.as_deref().map(Area::slugify))
+            .bind(query.from)
+            .bind(query.to)
+            .bind(query.limit())
+            .fetch_all(&self.pool).await
+    }
+
+    async fn insert_incident(&self, incident: &NewIncident) -> Result<Incident, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let id: i64 = sqlx::query_scalar(
+            "INSERT INTO incident (area_id, device_id, detection_id, source, state, recipients, timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) RETURNING id",
+        )
+            .bind(incident.area_id)
+            .bind(incident.device_id)
+            .bind(incident.detection_id)
+            .bind(&incident.source)
+            .bind(incident.state)
+            .bind(incident.recipients)
+            .bind(incident.timestamp)
+            .fetch_one(&mut *tx).await?;
+
+        let incident = sqlx::query_as(
+            "SELECT incident.id, incident.area_id, area.slug AS area, incident.device_id, incident.detection_id, incident.source, incident.state, \
+                 incident.recipients, incident.timestamp \
+             FROM incident JOIN area ON area.id = incident.area_id WHERE incident.id = ?1",
+        )
+            .bind(id)
+            .fetch_one(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(incident)
+    }
+
+    async fn detection_history(&self, filter: &DetectionFilter, page: PageRequest) -> Result<DetectionPage, Error> {
+        let limit = page.limit();
+
+        let mut params = Parameters::default();
+        let mut conditions = conditions(filter, &mut params);
+        if let Some(cursor) = page.cursor {
+            conditions.push(format!("detection.id < {}", params.bind(cursor)));
+        }
+
+        let statement = format!(
+            "SELECT {} {} {} ORDER BY detection.id DESC LIMIT {}",
+            DETECTION_COLUMNS,
+            from_clause(),
+            where_clause(&conditions),
+            params.bind(limit),
+        );
+
+        let rows: Vec<DetectionRow> = sqlx::query_as_with(&statement, params.args).fetch_all(&self.pool).await?;
+        let detections: Vec<Detection> = rows.into_iter().map(Detection::from).collect();
+
+        let next_cursor = match detections.last() {
+            Some(last) if detections.len() as i64 == limit => Some(last.id),
+            _ => None,
+        };
+
+        Ok(DetectionPage {
+            detections,
+            next_cursor,
+        })
+    }
+
+    async fn detection_counts(&self, filter: &DetectionFilter, bucket: Bucket) -> Result<Vec<AreaCount>, Error> {
+        let mut params = Parameters::default();
+        let conditions = conditions(filter, &mut params);
+
+        // Pruned detections only survive as daily counts, so day buckets add them to the raw detections
+        let source = match bucket {
+            Bucket::Hour => "(SELECT device_id, source, timestamp, count FROM detection) AS detection",
+            Bucket::Day => "(SELECT device_id, source, timestamp, count FROM detection UNION ALL SELECT device_id, source, day, count FROM detection_daily) AS detection",
+        };
+
+        let statement = format!(
+            "SELECT area.slug AS area, {} AS bucket, SUM(detection.count) AS count {} {} GROUP BY 1, 2 ORDER BY 2 DESC, 1",
+            truncate(bucket, "detection.timestamp"),
+            from_clause_of(source),
+            where_clause(&conditions),
+        );
+
+        sqlx::query_as_with(&statement, params.args).fetch_all(&self.pool).await
+    }
+
+    async fn noisy_devices(&self, filter: &DetectionFilter, limit: i64) -> Result<Vec<DeviceCount>, Error> {
+        let mut params = Parameters::default();
+        let conditions = conditions(filter, &mut params);
+
+        let statement = format!(
+            "SELECT device.id, device.uuid, device.description, area.slug AS area, device.area_id, SUM(detection.count) AS count {} {} \
+             GROUP BY device.id, area.slug ORDER BY count DESC, device.id LIMIT {}",
+            from_clause(),
+            where_clause(&conditions),
+            params.bind(limit),
+        );
+
+        sqlx::query_as_with(&statement, params.args).fetch_all(&self.pool).await
+    }
+
+    async fn export_detections(&self, filter: &DetectionFilter, mut records: Sender<ExportRecord>) -> Result<u64, Error> {
+        let mut params = Parameters::default();
+        let conditions = conditions(filter, &mut params);
+
+        let statement = format!(
+            "SELECT detection.id, detection.timestamp, detection.source, device.uuid AS device_uuid, device.description AS device_description, \
+             area.slug AS area, area.name AS area_name, detection.count, detection.state {} {} ORDER BY detection.timestamp, detection.id",
+            from_clause_of("(SELECT id, device_id, source, state, timestamp, count FROM detection \
+                UNION ALL SELECT id, device_id, source, state, timestamp, count FROM detection_archive) AS detection"),
+            where_clause(&conditions),
+        );
+
+        let mut con = self.pool.acquire().await?;
+        let mut rows = sqlx::query_as_with::<_, ExportRecord, _>(&statement, params.args).fetch(&mut *con);
+        let mut exported = 0;
+
+        while let Some(record) = rows.try_next().await? {
+            if records.send(record).await.is_err() {
+                break;
+            }
+            exported += 1;
+        }
+
+        Ok(exported)
+    }
+
+    async fn export_incidents(&self, filter: &DetectionFilter, mut records: Sender<IncidentRecord>) -> Result<u64, Error> {
+        let mut params = Parameters::default();
+        let conditions = conditions(filter, &mut params);
+
+        // Named like the detections, so their filter conditions apply. The area is the one that was alerted.
+        let statement = format!(
+            "SELECT detection.id, detection.timestamp, detection.source, device.uuid AS device_uuid, device.description AS device_description, \
+             area.slug AS area, area.name AS area_name, detection.recipients, detection.state \
+             FROM incident AS detection JOIN device ON device.id = detection.device_id JOIN area ON area.id = detection.area_id {} \
+             ORDER BY detection.timestamp, detection.id",
+            where_clause(&conditions),
+        );
+
+        let mut con = self.pool.acquire().await?;
+        let mut rows = sqlx::query_as_with::<_, IncidentRecord, _>(&statement, params.args).fetch(&mut *con);
+        let mut exported = 0;
+
+        while let Some(record) = rows.try_next().await? {
+            if records.send(record).await.is_err() {
+                break;
+            }
+            exported += 1;
+        }
+
+        Ok(exported)
+    }
+
+    async fn prune_detections(&self, cutoff: DateTime<Utc>, archive: bool, sink: Option<&mut dyn ArchiveSink>) -> Result<Pruned, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(sink) = sink {
+            let statement = format!(
+                "SELECT {} {} WHERE detection.timestamp < ?1 ORDER BY detection.id",
+                DETECTION_COLUMNS,
+                from_clause(),
+            );
+
+            let mut rows = sqlx::query_as::<_, DetectionRow>(&statement).bind(cutoff).fetch(&mut *tx);
+            while let Some(row) = rows.try_next().await? {
+                sink.write(&row.into()).map_err(Error::Io)?;
+            }
+            drop(rows);
+
+            sink.finish().map_err(Error::Io)?;
+        }
+
+        let days = sqlx::query(&format!(
+            "INSERT INTO detection_daily (day, device_id, source, count) \
+             SELECT {}, device_id, source, SUM(count) FROM detection WHERE timestamp < ?1 GROUP BY 1, 2, 3 \
+             ON CONFLICT (day, device_id, source) DO UPDATE SET count = detection_daily.count + excluded.count",
+            truncate(Bucket::Day, "timestamp"),
+        ))
+            .bind(cutoff)
+            .execute(&mut *tx).await?
+            .rows_affected();
+
+        // SQLite has no DELETE ... RETURNING inside a CTE, so the rows are copied first
+        if archive {
+            sqlx::query("INSERT INTO detection_archive (id, device_id, source, state, timestamp, count) \
+                SELECT id, device_id, source, state, timestamp, count FROM detection WHERE timestamp < ?1")
+                .bind(cutoff)
+                .execute(&mut *tx).await?;
+        }
+
+        let removed = sqlx::query("DELETE FROM detection WHERE timestamp < ?1")
+            .bind(cutoff)
+            .execute(&mut *tx).await?
+            .rows_affected();
+
+        tx.commit().await?;
+
+        Ok(Pruned { removed, days })
+    }
+}
@@ -0,0 +1,78 @@
+use sqlx::{Error, PgConnection};
+use crate::common::models::telemetry::{NewTelemetry, Telemetry, TelemetryQuery};
+use crate::database::Repository;
+
+pub struct TelemetryRepository;
+
+impl Repository for TelemetryRepository {
+    type Model = Telemetry;
+    type New = NewTelemetry;
+
+    async fn insert(telemetry: &NewTelemetry, con: &mut PgConnection) -> Result<Telemetry, Error> {
+        let report = &telemetry.report;
+
+        sqlx::query_as!(
+            Telemetry,
+            "INSERT INTO telemetry (device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             RETURNING id, device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage",
+            telemetry.device_id,
+            telemetry.timestamp,
+            report.rssi,
+            report.free_heap,
+            report.uptime_s,
+            report.reset_reason,
+            report.firmware_version,
+            report.supply_voltage,
+        )
+            .fetch_one(con).await
+    }
+
+    async fn get_by_id(id: i64, con: &mut PgConnection) -> Result<Option<Telemetry>, Error> {
+        sqlx::query_as!(
+            Telemetry,
+            "SELECT id, device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage
+             FROM telemetry WHERE id = $1",
+            id,
+        )
+            .fetch_optional(con).await
+    }
+}
+
+impl TelemetryRepository {
+    pub async fn latest(device_id: i64, con: &mut PgConnection) -> Result<Option<Telemetry>, Error> {
+        sqlx::query_as!(
+            Telemetry,
+            "SELECT id, device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage
+             FROM telemetry WHERE device_id = $1 ORDER BY timestamp DESC, id DESC LIMIT 1",
+            device_id,
+        )
+            .fetch_optional(con).await
+    }
+
+    /// The latest telemetry of every device that sent any, by device id.
+    pub async fn latest_all(con: &mut PgConnection) -> Result<Vec<Telemetry>, Error> {
+        sqlx::query_as!(
+            Telemetry,
+            "SELECT DISTINCT ON (device_id) id, device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage
+             FROM telemetry ORDER BY device_id, timestamp DESC, id DESC",
+        )
+            .fetch_all(con).await
+    }
+
+    /// Telemetry of the device in the time range of the query, newest first.
+    pub async fn history(device_id: i64, query: &TelemetryQuery, con: &mut PgConnection) -> Result<Vec<Telemetry>, Error> {
+        sqlx::query_as!(
+            Telemetry,
+            "SELECT id, device_id, timestamp, rssi, free_heap, uptime_s, reset_reason, firmware_version, supply_voltage
+             FROM telemetry
+             WHERE device_id = $1 AND ($2::timestamptz IS NULL OR timestamp >= $2) AND ($3::timestamptz IS NULL OR timestamp < $3)
+             ORDER BY timestamp DESC, id DESC LIMIT $4",
+            device_id,
+            query.from,
+            query.to,
+            query.limit(),
+        )
+            .fetch_all(con).await
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+/// Errors of the connection and message pipeline.
+#[derive(Debug)]
+pub enum Error {
+    Database(sqlx::Error),
+    Json(serde_json::Error),
+    Notification(ntfy::NtfyError),
+    WebSocket(Box<tungstenite::Error>),
+    Io(std::io::Error),
+    /// The client disconnected before a message could be delivered to it.
+    PeerGone(SocketAddr),
+    NotFound(String),
+    /// The device has not been approved or sent a wrong token, see `DEVICE_APPROVAL`.
+    NotApproved(i64),
+    WrongPairingCode,
+    /// The request to the HTTP API lacks the `ADMIN_TOKEN` or has a wrong one.
+    Unauthorized,
+    InvalidConfig(String),
+    InvalidFirmware(String),
+    /// The device is not connected, so it can't get a command.
+    DeviceOffline(Uuid),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Database(err) => write!(f, "Database error: {}", err),
+            Error::Json(err) => write!(f, "Invalid message: {}", err),
+            Error::Notification(err) => write!(f, "Notification failed: {}", err),
+            Error::WebSocket(err) => write!(f, "WebSocket error: {}", err),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::PeerGone(addr) => write!(f, "Client {} is no longer connected", addr),
+            Error::NotFound(what) => write!(f, "Not found: {}", what),
+            Error::NotApproved(device_id) => write!(f, "Device {} is not approved", device_id),
+            Error::WrongPairingCode => write!(f, "Wrong pairing code"),
+            Error::Unauthorized => write!(f, "Missing or wrong admin token"),
+            Error::InvalidConfig(reason) => write!(f, "Invalid configuration: {}", reason),
+            Error::InvalidFirmware(reason) => write!(f, "Invalid firmware: {}", reason),
+            Error::DeviceOffline(uuid) => write!(f, "Device {} is not connected", uuid),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Database(err) => Some(err),
+            Error::Json(err) => Some(err),
+            Error::Notification(err) => Some(err),
+            Error::WebSocket(err) => Some(err.as_ref()),
+            Error::Io(err) => Some(err),
+            Error::PeerGone(_) | Error::NotFound(_) | Error::NotApproved(_) | Error::WrongPairingCode | Error::Unauthorized | Error::InvalidConfig(_) | Error::InvalidFirmware(_)
+            | Error::DeviceOffline(_) => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        Error::Database(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl From<ntfy::NtfyError> for Error {
+    fn from(err: ntfy::NtfyError) -> Self {
+        Error::Notification(err)
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Self {
+        Error::WebSocket(Box::new(err))
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
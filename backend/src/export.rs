@@ -0,0 +1,160 @@
+use std::future::Future;
+use futures_channel::mpsc;
+use futures_util::{future, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+use crate::common::models::history::{DetectionFilter, ExportRecord};
+use crate::common::models::incident::IncidentRecord;
+use crate::database::Db;
+use crate::error::{Error, Result};
+
+/// Records buffered between the database and a slow reader.
+const BUFFER: usize = 256;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Jsonl => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+        }
+    }
+
+    fn header<R: Record>(&self) -> Option<String> {
+        match self {
+            ExportFormat::Csv => Some(format!("{}\n", R::CSV_HEADER)),
+            ExportFormat::Jsonl => None,
+        }
+    }
+
+    fn line<R: Record>(&self, record: &R) -> String {
+        match self {
+            ExportFormat::Csv => format!("{}\n", record.csv_fields().join(",")),
+            ExportFormat::Jsonl => match serde_json::to_string(record) {
+                Ok(json) => json + "\n",
+                Err(err) => {
+                    error!(%err, id = record.id(), "Could not serialize export record");
+                    String::new()
+                }
+            },
+        }
+    }
+}
+
+/// A record of an export, one per line.
+pub trait Record: Serialize + Send + 'static {
+    /// What is exported, for the logs.
+    const NAME: &'static str;
+    const CSV_HEADER: &'static str;
+
+    fn id(&self) -> i64;
+
+    /// Fields of the CSV line in the order of the header, already quoted.
+    fn csv_fields(&self) -> Vec<String>;
+}
+
+impl Record for ExportRecord {
+    const NAME: &'static str = "detections";
+    const CSV_HEADER: &'static str = "id,timestamp,source,device_uuid,device_description,area,area_name,count,state";
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.timestamp.to_rfc3339(),
+            csv_field(&self.source),
+            self.device_uuid.to_string(),
+            csv_field(&self.device_description),
+            csv_field(&self.area),
+            csv_field(&self.area_name),
+            self.count.to_string(),
+            self.state.map_or("", |state| state.as_str()).to_string(),
+        ]
+    }
+}
+
+impl Record for IncidentRecord {
+    const NAME: &'static str = "incidents";
+    const CSV_HEADER: &'static str = "id,timestamp,source,device_uuid,device_description,area,area_name,recipients,state";
+
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.timestamp.to_rfc3339(),
+            csv_field(&self.source),
+            self.device_uuid.to_string(),
+            csv_field(&self.device_description),
+            csv_field(&self.area),
+            csv_field(&self.area_name),
+            self.recipients.to_string(),
+            self.state.map_or("", |state| state.as_str()).to_string(),
+        ]
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Exports the matching detections, archived ones included, line by line.
+pub fn detections(filter: DetectionFilter, format: ExportFormat, db: Db) -> impl Stream<Item = Result<String>> {
+    lines(format, move |records| async move { db.export_detections(&filter, records).await })
+}
+
+/// Exports the matching incidents line by line, the alarm history.
+pub fn incidents(filter: DetectionFilter, format: ExportFormat, db: Db) -> impl Stream<Item = Result<String>> {
+    lines(format, move |records| async move { db.export_incidents(&filter, records).await })
+}
+
+/// The query runs in its own task and only reads ahead a few records, so large time ranges don't end up in memory.
+/// A failed query ends the stream with the error, so a cut-off export doesn't pass as complete.
+fn lines<R, Q, F>(format: ExportFormat, query: Q) -> impl Stream<Item = Result<String>>
+where
+    R: Record,
+    Q: FnOnce(mpsc::Sender<R>) -> F,
+    F: Future<Output = std::result::Result<u64, sqlx::Error>> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(BUFFER);
+    let query = tokio::spawn(query(tx));
+
+    let outcome = futures_util::stream::once(async move {
+        match query.await {
+            Ok(Ok(exported)) => {
+                info!(exported, ?format, records = R::NAME, "Records exported");
+                None
+            }
+            Ok(Err(err)) => Some(Err(Error::from(err))),
+            Err(err) => Some(Err(Error::Io(std::io::Error::other(err)))),
+        }
+    }).filter_map(future::ready);
+
+    futures_util::stream::iter(format.header::<R>().map(Ok))
+        .chain(rx.map(move |record| Ok(format.line(&record))))
+        .chain(outcome)
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::telemetry::threshold;
+
+/// Protects the alarm processing from devices that report too much, like a PIR sensor flapping on every edge.
+#[derive(Clone, Copy, Debug)]
+pub struct FloodProtection {
+    /// Repeated detections of a device and source within this window are counted on the first one instead of alerting again.
+    pub debounce: Option<Duration>,
+    /// Messages a connection may send per `rate_window`, more are dropped.
+    pub rate_limit: Option<u32>,
+    pub rate_window: Duration,
+}
+
+impl Default for FloodProtection {
+    fn default() -> Self {
+        FloodProtection {
+            debounce: Some(Duration::from_secs(30)),
+            rate_limit: Some(20),
+            rate_window: Duration::from_secs(10),
+        }
+    }
+}
+
+impl FloodProtection {
+    /// Reads `DETECTION_DEBOUNCE_SECONDS`, `RATE_LIMIT_MESSAGES` and `RATE_LIMIT_WINDOW_SECONDS`.
+    /// Unset ones keep their default, an empty value disables debouncing or the rate limit.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let window = threshold("RATE_LIMIT_WINDOW_SECONDS", Some(defaults.rate_window.as_secs()))
+            .unwrap_or(defaults.rate_window.as_secs());
+
+        FloodProtection {
+            debounce: threshold("DETECTION_DEBOUNCE_SECONDS", defaults.debounce.map(|debounce| debounce.as_secs()))
+                .filter(|seconds| *seconds > 0)
+                .map(Duration::from_secs),
+            rate_limit: threshold("RATE_LIMIT_MESSAGES", defaults.rate_limit),
+            rate_window: Duration::from_secs(window.max(1)),
+        }
+    }
+}
+
+/// The result of a detection passing the debounce window of its device and source.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Debounced {
+    /// No window is open, the detection goes on to the correlation rule and the alarm.
+    New,
+    /// A repeat within the window, it is counted on the stored detection of the window if there is one.
+    Repeat {
+        detection_id: Option<i64>,
+        /// Detections in the window so far, the first one included.
+        count: u32,
+    },
+}
+
+struct Window {
+    started: Instant,
+    detection_id: Option<i64>,
+    count: u32,
+}
+
+/// Debounce windows by device and source. A window starts with the detection that raises the alarm and
+/// isn't extended by its repeats, so a lasting intrusion alarms again once per window.
+/// Detections held back by a correlation rule open none, their repeats count towards the rule.
+#[derive(Default)]
+pub struct Debouncer {
+    window: Option<Duration>,
+    windows: HashMap<(i64, String), Window>,
+}
+
+impl Debouncer {
+    pub fn new(window: Option<Duration>) -> Self {
+        Debouncer {
+            window,
+            windows: HashMap::new(),
+        }
+    }
+
+    pub fn detect(&mut self, device_id: i64, source: &str, now: Instant) -> Debounced {
+        let Some(length) = self.window else {
+            return Debounced::New;
+        };
+
+        // Closed windows are forgotten, so the map only holds the devices active right now
+        self.windows.retain(|_, window| now.duration_since(window.started) < length);
+
+        match self.windows.get_mut(&(device_id, source.to_string())) {
+            Some(window) => {
+                window.count += 1;
+                Debounced::Repeat { detection_id: window.detection_id, count: window.count }
+            }
+            None => Debounced::New,
+        }
+    }
+
+    /// Opens the window of a detection that passed the correlation rule, its repeats are counted on the stored detection.
+    pub fn open(&mut self, device_id: i64, source: &str, detection_id: Option<i64>, now: Instant) {
+        if self.window.is_some() {
+            self.windows.insert((device_id, source.to_string()), Window { started: now, detection_id, count: 1 });
+        }
+    }
+}
+
+/// The result of a message passing the rate limit of its connection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rate {
+    Allowed,
+    /// The first message over the limit in the current window, it is dropped.
+    Exceeded,
+    Dropped,
+}
+
+/// Counts the messages of one connection in fixed windows.
+pub struct RateLimiter {
+    limit: Option<u32>,
+    window: Duration,
+    started: Instant,
+    count: u32,
+}
+
+impl RateLimiter {
+    pub fn new(protection: &FloodProtection, now: Instant) -> Self {
+        RateLimiter {
+            limit: protection.rate_limit,
+            window: protection.rate_window,
+            started: now,
+            count: 0,
+        }
+    }
+
+    pub fn check(&mut self, now: Instant) -> Rate {
+        let Some(limit) = self.limit else {
+            return Rate::Allowed;
+        };
+
+        if now.duration_since(self.started) >= self.window {
+            self.started = now;
+            self.count = 0;
+        }
+        self.count = self.count.saturating_add(1);
+
+        match self.count {
+            count if count <= limit => Rate::Allowed,
+            count if count == limit.saturating_add(1) => Rate::Exceeded,
+            _ => Rate::Dropped,
+        }
+    }
+}
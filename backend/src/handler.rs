@@ -0,0 +1,997 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use ntfy::{Dispatcher, Payload, Priority};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::{debug, error, info, warn, Instrument, Span};
+use tungstenite::protocol::{CloseFrame, Message};
+use tungstenite::protocol::frame::coding::CloseCode;
+use uuid::Uuid;
+use crate::client::{publish, Client, PeerMap};
+use crate::command::CommandLog;
+use crate::common::models::command::{CommandKind, CommandRecord, CommandStatus};
+use crate::common::models::area::Area;
+use crate::common::models::config::DeviceConfig;
+use crate::common::models::correlation::NewPreAlarm;
+use crate::common::models::detection::NewDetection;
+use crate::common::models::device::{Device, NewDevice, Provisioning};
+use crate::common::models::firmware::{Rollout, UpdateOffer, UpdateStatus};
+use crate::common::models::incident::NewIncident;
+use crate::common::models::sensor::{describe, normalize_source, ContactState, SensorKind};
+use crate::common::models::telemetry::NewTelemetry;
+use crate::common::route::Route;
+use crate::common::topic::{EventType, Topic};
+use crate::common::zone::{Subscription, ZoneTree};
+use crate::correlation::{Correlator, Verdict};
+use crate::database::{Db, Storage};
+use crate::error::{Error, Result};
+use crate::flood::{Debounced, Debouncer};
+use crate::message::receive::command::CommandResponseMessage;
+use crate::message::receive::config::ConfigAckMessage;
+use crate::message::receive::detection::DetectionMessage;
+use crate::message::receive::device::DeviceRef;
+use crate::message::receive::subscription::SubscriptionMessage;
+use crate::message::receive::telemetry::TelemetryMessage;
+use crate::message::receive::update::UpdateStatusMessage;
+use crate::message::send::alert::Alert;
+use crate::message::send::command::CommandMessage;
+use crate::message::send::config::ConfigUpdate;
+use crate::message::send::error::ErrorMessage;
+use crate::message::send::event::{Event, Subscriptions};
+use crate::message::send::provisioning::ProvisioningMessage;
+use crate::message::send::update::UpdateAvailable;
+use crate::metrics::{METRICS, UNKNOWN_AREA};
+use crate::provisioning;
+use crate::telemetry::{self, TelemetryThresholds};
+
+/// Queue of the message handler. Each action carries the span it was queued in.
+pub type ActionSender = mpsc::UnboundedSender<(MessageAction, Span)>;
+pub type ActionReceiver = mpsc::UnboundedReceiver<(MessageAction, Span)>;
+
+pub enum MessageAction {
+    Register((NewDevice, SocketAddr)),
+    Detection {
+        message: DetectionMessage,
+        area_id: Option<i64>,
+        socket_addr: SocketAddr,
+        /// When the connection received the detection, for the end-to-end latency.
+        received: Instant,
+    },
+    Subscribe(SubscriptionMessage, SocketAddr),
+    ConfigAck(ConfigAckMessage, SocketAddr),
+    Telemetry(TelemetryMessage, SocketAddr),
+    /// The config of a device was edited, it is pushed if the device is connected.
+    PushConfig(DeviceConfig),
+    /// Sends a command to the connection of the device. `sent` gets the record, or `None` if the device is offline.
+    Command {
+        device: Device,
+        command: CommandKind,
+        sent: oneshot::Sender<Option<CommandRecord>>,
+    },
+    CommandResponse(CommandResponseMessage, SocketAddr),
+    /// The connection exceeded its rate limit, it is flagged and the admin is told once.
+    Flooding(SocketAddr),
+    /// A rollout started or advanced, the devices it offers the update to are told if they are connected.
+    OfferUpdates(Rollout),
+    UpdateStatus(UpdateStatusMessage, SocketAddr),
+    /// An admin approved a pending device, it gets its token if it is still connected.
+    Approved {
+        pending_id: i64,
+        device: Device,
+    },
+    CloseConnection(String, String, Option<i64>),
+    OpenConnection(String, String, Option<i64>),
+    /// Announces the shutdown to the clients and closes their connections.
+    Stopping(oneshot::Sender<()>),
+    /// Last action of the handler, everything queued before it has been processed.
+    Shutdown(oneshot::Sender<()>),
+}
+
+impl MessageAction {
+    /// The client that sent the message, errors are reported back to it.
+    fn origin(&self) -> Option<SocketAddr> {
+        match self {
+            MessageAction::Register((_, socket_addr)) => Some(*socket_addr),
+            MessageAction::Detection { socket_addr, .. } => Some(*socket_addr),
+            MessageAction::Subscribe(_, socket_addr) => Some(*socket_addr),
+            MessageAction::ConfigAck(_, socket_addr) => Some(*socket_addr),
+            MessageAction::Telemetry(_, socket_addr) => Some(*socket_addr),
+            MessageAction::CommandResponse(_, socket_addr) => Some(*socket_addr),
+            MessageAction::UpdateStatus(_, socket_addr) => Some(*socket_addr),
+            _ => None,
+        }
+    }
+}
+
+/// Maps the priority stored with an area (1 = min, 5 = max) to the ntfy priority.
+pub fn notification_priority(level: i16) -> Priority {
+    match level {
+        i16::MIN..=1 => Priority::Min,
+        2 => Priority::Low,
+        3 => Priority::Default,
+        4 => Priority::High,
+        _ => Priority::Max,
+    }
+}
+
+#[derive(Default)]
+struct CurrentRun {
+    abort: Option<AbortHandle>,
+    stopped: bool,
+}
+
+/// The supervised message handler, see `MessageHandler::spawn`.
+pub struct HandlerTask {
+    supervisor: JoinHandle<()>,
+    /// The run the supervisor waits for, replaced on every restart.
+    current: Arc<std::sync::Mutex<CurrentRun>>,
+}
+
+impl HandlerTask {
+    /// Stops the handler in the middle of its queue and waits until it is gone, so it doesn't touch the storage afterwards.
+    pub async fn abort(self) {
+        {
+            let mut current = self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            current.stopped = true;
+            if let Some(run) = &current.abort {
+                run.abort();
+            }
+        }
+
+        // The supervisor ends once the aborted run has finished
+        let _ = self.supervisor.await;
+    }
+}
+
+/// Resolves what a client listens to by its route: `/all` is the whole zone tree, `/zone/<slug>` the subtree
+/// of a zone and any area path the sirens of that area. The dashboard doesn't subscribe to alerts.
+pub async fn resolve_subscription(route: &Route, db: &dyn Storage) -> Result<Option<Subscription>> {
+    let slug = match route {
+        Route::Root | Route::Ui => return Ok(None),
+        Route::All => return Ok(Some(Subscription::Subtree(None))),
+        Route::Zone(slug) | Route::Area(slug) => slug,
+    };
+
+    let area = db.area_get_or_create(slug).await?;
+
+    match route {
+        Route::Zone(_) => Ok(Some(Subscription::Subtree(Some(area.id)))),
+        _ => Ok(Some(Subscription::Node(area.id))),
+    }
+}
+
+/// Processes the actions of the connections: storing, alerting and notifying.
+#[derive(Clone)]
+pub struct MessageHandler {
+    pub db: Db,
+    pub peer_map: PeerMap,
+    pub ntfy_dispatcher: Arc<Dispatcher>,
+    stopping: Arc<AtomicBool>,
+    require_approval: bool,
+    /// Connections of the pending devices, by pending id.
+    awaiting: Arc<Mutex<HashMap<i64, SocketAddr>>>,
+    commands: CommandLog,
+    telemetry_thresholds: TelemetryThresholds,
+    /// Where the devices reach the HTTP endpoints, for the firmware download links.
+    public_url: String,
+    debouncer: Arc<Mutex<Debouncer>>,
+    correlator: Arc<Mutex<Correlator>>,
+}
+
+impl MessageHandler {
+    pub fn new(db: Db, peer_map: PeerMap, ntfy_dispatcher: Arc<Dispatcher>) -> Self {
+        MessageHandler {
+            db,
+            peer_map,
+            ntfy_dispatcher,
+            stopping: Arc::new(AtomicBool::new(false)),
+            require_approval: false,
+            awaiting: Arc::new(Mutex::new(HashMap::new())),
+            commands: CommandLog::default(),
+            telemetry_thresholds: TelemetryThresholds::default(),
+            public_url: String::new(),
+            debouncer: Arc::new(Mutex::new(Debouncer::default())),
+            correlator: Arc::new(Mutex::new(Correlator::default())),
+        }
+    }
+
+    /// Shares the log of the sent commands, the HTTP API reads the answers from it.
+    pub fn commands(mut self, commands: CommandLog) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    pub fn telemetry_thresholds(mut self, thresholds: TelemetryThresholds) -> Self {
+        self.telemetry_thresholds = thresholds;
+        self
+    }
+
+    pub fn public_url(mut self, public_url: String) -> Self {
+        self.public_url = public_url;
+        self
+    }
+
+    /// Repeated detections of a device and source within the window are counted instead of alerting again.
+    pub fn debounce(mut self, window: Option<Duration>) -> Self {
+        self.debouncer = Arc::new(Mutex::new(Debouncer::new(window)));
+        self
+    }
+
+    /// Unknown devices wait for approval and detections need the token issued on approval.
+    pub fn require_approval(mut self, require_approval: bool) -> Self {
+        self.require_approval = require_approval;
+        self
+    }
+
+    /// Runs the handler under supervision: if it panics, it is restarted on the same queue,
+    /// so one bad message can't stop the alarm processing.
+    pub fn spawn(self, rx: ActionReceiver) -> HandlerTask {
+        let rx = Arc::new(Mutex::new(rx));
+        let current = Arc::new(std::sync::Mutex::new(CurrentRun::default()));
+        let runs = current.clone();
+
+        let supervisor = tokio::spawn(async move {
+            loop {
+                let handler = self.clone();
+                let queue = rx.clone();
+
+                // Registered under the lock, so an abort can't slip in between two runs
+                let run = {
+                    let mut current = runs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    if current.stopped {
+                        break;
+                    }
+
+                    let run = tokio::spawn(async move { handler.run(&queue).await });
+                    current.abort = Some(run.abort_handle());
+                    run
+                };
+
+                match run.await {
+                    Ok(()) => break,
+                    Err(err) if err.is_panic() => {
+                        error!(%err, "Message handler failed, restarting it");
+
+                        let payload = Payload::new("Alert-Net-Status")
+                            .title("Alert Net server")
+                            .message("Nachrichtenverarbeitung ist abgestürzt und wurde neu gestartet")
+                            .priority(Priority::High);
+
+                        if let Err(err) = self.ntfy_dispatcher.send(&payload).await {
+                            warn!(%err, "Could not send restart notification");
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+
+        HandlerTask { supervisor, current }
+    }
+
+    async fn run(&self, rx: &Mutex<ActionReceiver>) {
+        let mut zones = ZoneTree::default();
+        let mut rx = rx.lock().await;
+
+        while let Some((received, span)) = rx.recv().await {
+            let origin = received.origin();
+            let shutdown = matches!(received, MessageAction::Shutdown(_));
+
+            async {
+                if let Err(err) = self.handle(received, &mut zones).await {
+                    error!(%err, "Failed to process message");
+
+                    if let Some(origin) = origin {
+                        self.report_error(origin, &err).await;
+                    }
+                }
+            }.instrument(span).await;
+
+            if shutdown {
+                break;
+            }
+        }
+    }
+
+    async fn report_error(&self, socket_addr: SocketAddr, err: &Error) {
+        let peers = self.peer_map.lock().await;
+
+        if let Some(client) = peers.iter().find(|c| c.socket_addr == socket_addr) {
+            let message = ErrorMessage {
+                error: err.to_string(),
+            };
+
+            if let Err(err) = client.send_json(&message) {
+                warn!(%err, "Could not report error to client");
+            }
+        }
+    }
+
+    // Reloads the zone tree, so changed arm states and parents apply right away.
+    // If the database is unavailable the last known tree is kept.
+    async fn refresh_zones(&self, zones: &mut ZoneTree) {
+        match self.db.areas().await {
+            Ok(areas) => *zones = ZoneTree::new(areas),
+            Err(err) => warn!(%err, "Could not load areas, using the last known zone tree"),
+        }
+    }
+
+    async fn notify(&self, payload: Payload) -> Result<()> {
+        let ntfy_result = self.ntfy_dispatcher.send(&payload).await;
+        debug!(topic = %payload.topic, ?ntfy_result, "Notification sent");
+
+        if ntfy_result.is_err() {
+            METRICS.ntfy_failures.inc();
+        }
+
+        Ok(ntfy_result?)
+    }
+
+    /// Issues a new token to the device, only its hash is stored.
+    async fn issue_token(&self, device_id: i64) -> Result<String> {
+        let token = provisioning::generate_token();
+        self.db.set_device_token_hash(device_id, Some(&provisioning::hash_token(&token))).await?;
+
+        Ok(token)
+    }
+
+    async fn verify_token(&self, device_id: i64, token: Option<&str>) -> Result<()> {
+        let hash = self.db.device_token_hash(device_id).await?;
+
+        match (hash, token) {
+            (Some(hash), Some(token)) if hash == provisioning::hash_token(token) => Ok(()),
+            _ => Err(Error::NotApproved(device_id)),
+        }
+    }
+
+    /// The stored device a message names, so its current description is shown.
+    /// A device the storage doesn't know, or can't look up, is taken as sent.
+    async fn device(&self, device: DeviceRef) -> Device {
+        match self.db.device_by_uuid(device.uuid).await {
+            Ok(Some(stored)) => {
+                if stored.id != device.id {
+                    warn!(uuid = %device.uuid, sent_id = device.id, id = stored.id, "Device sent the id of another device");
+                }
+                stored
+            }
+            Ok(None) => Device::from(device),
+            Err(err) => {
+                warn!(%err, "Could not load device, using the one sent");
+                Device::from(device)
+            }
+        }
+    }
+
+    /// Registration while approval is required: approved devices get their device back,
+    /// unknown ones are queued for the admin.
+    async fn provision(&self, device: NewDevice, socket_addr: SocketAddr, zones: &ZoneTree) -> Result<()> {
+        let timer = METRICS.db_insert_seconds.with_label_values(&["device"]).start_timer();
+        let provisioning = self.db.provision_device(&device, &provisioning::pairing_code()).await?;
+        timer.observe_duration();
+
+        let (message, pending, device_id) = match provisioning {
+            Provisioning::Known(device) => {
+                // The token is issued once, to the first registration after the approval if the device was offline then
+                let token = match self.db.device_token_hash(device.id).await? {
+                    Some(_) => None,
+                    None => Some(self.issue_token(device.id).await?),
+                };
+                info!(id = device.id, uuid = %device.uuid, token_issued = token.is_some(), "Approved device registered");
+
+                let device_id = device.id;
+                (ProvisioningMessage::Approved { device, token }, None, Some(device_id))
+            }
+            Provisioning::Pending(pending) => {
+                info!(pending_id = pending.id, description = %pending.description, area = %pending.area, "Device awaits approval");
+                self.awaiting.lock().await.insert(pending.id, socket_addr);
+
+                (ProvisioningMessage::Pending { pairing_code: pending.pairing_code.clone() }, Some(pending), None)
+            }
+        };
+
+        let mut peers = self.peer_map.lock().await;
+        let client = peers.iter_mut()
+            .find(|c| c.socket_addr == socket_addr)
+            .ok_or(Error::PeerGone(socket_addr))?;
+
+        client.device_id = device_id;
+        client.send_json(&message)?;
+
+        if let Some(pending) = pending {
+            let status = Event::Status {
+                message: format!("Gerät wartet auf Freigabe: {} im Bereich {}", pending.description, pending.area),
+            };
+            publish(&peers, EventType::Status, None, zones, &Message::text(serde_json::to_string(&status)?));
+
+            let event = Event::Pending { device: pending };
+            publish(&peers, EventType::Device, None, zones, &Message::text(serde_json::to_string(&event)?));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the detection against the correlation rule of its area, the pre-alarm to record if the rule isn't satisfied.
+    /// Without a rule, or if it can't be loaded, the detection alarms.
+    async fn correlate(&self, area: &Area, device_id: i64, source: &str, received: Instant) -> Option<NewPreAlarm> {
+        let rule = match self.db.correlation_rule(area.id).await {
+            Ok(rule) => rule?,
+            Err(err) => {
+                error!(%err, "Could not load correlation rule, alarming anyway");
+                return None;
+            }
+        };
+
+        match self.correlator.lock().await.detect(&rule, device_id, source, received) {
+            Verdict::Alarm => None,
+            Verdict::PreAlarm { detections, devices } => Some(NewPreAlarm {
+                area_id: area.id,
+                device_id,
+                source: source.to_string(),
+                timestamp: Utc::now(),
+                detections: detections as i32,
+                devices: devices as i32,
+            }),
+        }
+    }
+
+    /// Tells the connected devices the rollout offers the update to.
+    async fn offer_updates(&self, rollout: &Rollout, zones: &ZoneTree) -> Result<()> {
+        let peers = self.peer_map.lock().await;
+        let mut sent = 0;
+
+        for update in rollout.offered() {
+            if let Some(client) = peers.iter().find(|c| c.device_id == Some(update.device_id)) {
+                let offer = UpdateOffer { rollout_id: rollout.id, firmware: rollout.firmware.clone() };
+                client.send_json(&UpdateAvailable::new(&offer, &self.public_url))?;
+                sent += 1;
+            }
+        }
+        info!(rollout_id = rollout.id, stage = rollout.stage, version = %rollout.firmware.version, sent, "Update offered");
+
+        let event = Event::Rollout { rollout: rollout.clone() };
+        publish(&peers, EventType::Device, None, zones, &Message::text(serde_json::to_string(&event)?));
+
+        Ok(())
+    }
+
+    async fn handle(&self, action: MessageAction, zones: &mut ZoneTree) -> Result<()> {
+        match action {
+            MessageAction::Register((device, socket_addr)) if self.require_approval => {
+                self.provision(device, socket_addr, zones).await?;
+            }
+            MessageAction::Register((device, socket_addr)) => {
+                let timer = METRICS.db_insert_seconds.with_label_values(&["device"]).start_timer();
+                let registration = self.db.register_device(&device).await?;
+                timer.observe_duration();
+                let dev = registration.device;
+
+                let mut peers = self.peer_map.lock().await;
+                let receiver_device: &mut Client = peers.iter_mut()
+                    .find(|c| c.socket_addr == socket_addr)
+                    .ok_or(Error::PeerGone(socket_addr))?;
+
+                receiver_device.device_id = Some(dev.id);
+                receiver_device.send_json(&dev)?;
+
+                // A repeated registration only gets its device back
+                if !registration.created {
+                    info!(id = dev.id, uuid = %dev.uuid, area = %dev.area, "Device already registered");
+                    return Ok(());
+                }
+                info!(id = dev.id, uuid = %dev.uuid, area = %dev.area, "Registered new device");
+
+                let event = Event::Status {
+                    message: format!("Neues Gerät registriert: {} im Bereich {}", dev.description, dev.area),
+                };
+                publish(&peers, EventType::Status, None, zones, &Message::text(serde_json::to_string(&event)?));
+            },
+            MessageAction::Detection { message: detection_message, area_id, received, .. } => {
+                let device = self.device(detection_message.device).await;
+                if self.require_approval {
+                    self.verify_token(device.id, detection_message.token.as_deref()).await?;
+                }
+
+                let device_id = device.id;
+                let source = normalize_source(&detection_message.source);
+                let kind = SensorKind::of(&source);
+                let state = match kind {
+                    SensorKind::Contact => detection_message.state.as_deref().and_then(ContactState::parse),
+                    _ => None,
+                };
+
+                // A contact closing again is only kept in the history
+                if state == Some(ContactState::Closed) {
+                    self.db.insert_detection(&NewDetection { device_id, source, state, timestamp: Utc::now() }).await?;
+                    debug!("Contact closed, no alert");
+                    return Ok(());
+                }
+
+                let debounced = self.debouncer.lock().await.detect(device_id, &source, received);
+                if let Debounced::Repeat { detection_id, count } = debounced {
+                    debug!(count, "Repeated detection within the debounce window");
+                    // The last known zone tree is good enough for the label, repeats shouldn't cost a reload
+                    let area_label = area_id.and_then(|id| zones.get(id)).map_or(UNKNOWN_AREA, |a| a.slug.as_str());
+                    METRICS.detections_coalesced.with_label_values(&[area_label]).inc();
+
+                    if let Some(id) = detection_id {
+                        self.db.add_detection_repeat(id).await?;
+                    }
+                    return Ok(());
+                }
+
+                self.refresh_zones(zones).await;
+
+                // The area of the connection decides who hears the alarm. If it is unknown, alert anyway.
+                let area = area_id.and_then(|id| zones.get(id)).cloned();
+                let area_label = area.as_ref().map_or(UNKNOWN_AREA, |a| a.slug.as_str());
+                METRICS.detections.with_label_values(&[area_label, kind.as_str()]).inc();
+
+                let armed = kind.always_armed() || area.as_ref().is_none_or(|a| a.armed);
+
+                // Detections short of the correlation rule of the area only raise a pre-alarm and are stored as one
+                let pre_alarm = match &area {
+                    Some(area) if armed && !kind.always_armed() => self.correlate(area, device_id, &source, received).await,
+                    _ => None,
+                };
+                if let Some(pre_alarm) = pre_alarm {
+                    info!(detections = pre_alarm.detections, devices = pre_alarm.devices, "Correlation rule not satisfied, pre-alarm");
+                    METRICS.pre_alarms.with_label_values(&[area_label]).inc();
+
+                    let event = Event::PreAlarm { pre_alarm: self.db.insert_pre_alarm(&pre_alarm).await? };
+                    publish(&self.peer_map.lock().await, EventType::PreAlarm, area_id, zones, &Message::text(serde_json::to_string(&event)?));
+
+                    return Ok(());
+                }
+
+                let timer = METRICS.db_insert_seconds.with_label_values(&["detection"]).start_timer();
+                let new_detection = NewDetection {
+                    device_id,
+                    source: source.clone(),
+                    state,
+                    timestamp: Utc::now(),
+                };
+                let result = self.db.insert_detection(&new_detection).await;
+                timer.observe_duration();
+                match &result {
+                    Ok(detection) => debug!(id = detection.id, "Detection stored"),
+                    Err(err) => error!(%err, "Could not store detection"),
+                }
+
+                // Only now, repeats of a pre-alarm have to reach the correlation rule
+                let detection_id = result.as_ref().ok().map(|detection| detection.id);
+                self.debouncer.lock().await.open(device_id, &source, detection_id, received);
+
+                let peers = self.peer_map.lock().await;
+                let mut alerted = None;
+                if armed {
+                    let alert = Alert {
+                        led: true,
+                        speaker: true,
+                    };
+
+                    let recipients = publish(&peers, EventType::Detection, area_id, zones, &Message::text(serde_json::to_string(&alert)?));
+                    METRICS.alerts_sent.with_label_values(&[area_label]).inc();
+                    METRICS.detection_to_alert_seconds.observe(received.elapsed().as_secs_f64());
+                    info!(recipients, "Alert sent");
+                    alerted = Some(recipients);
+                } else {
+                    info!(area = %device.area, "Area is disarmed, no alert sent");
+
+                    let event = Event::Status {
+                        message: format!("Bereich {} ist unscharf, kein Alarm für Auslöser {}", device.area, describe(&source, state)),
+                    };
+                    publish(&peers, EventType::Status, area_id, zones, &Message::text(serde_json::to_string(&event)?));
+                }
+                drop(peers);
+
+                // Kept for the alarm history, also when the detection could not be stored
+                let incident = match alerted {
+                    Some(recipients) => {
+                        let incident = NewIncident {
+                            area_id: area.as_ref().map_or(device.area_id, |area| area.id),
+                            device_id,
+                            detection_id,
+                            source: source.clone(),
+                            state,
+                            recipients: recipients as i32,
+                            timestamp: Utc::now(),
+                        };
+                        self.db.insert_incident(&incident).await.inspect_err(|err| error!(%err, "Could not store incident")).map(Some)
+                    }
+                    None => Ok(None),
+                };
+
+                if armed && area.as_ref().is_none_or(|a| a.notify) {
+                    // Smoke or a panic button is urgent even in an area with a low priority
+                    let (topic, title, priority) = match &area {
+                        Some(area) => (area.topic(), area.name.clone(), area.notification_priority.max(kind.priority())),
+                        None => (format!("Alert-Net-{}", &device.area), device.area.clone(), kind.priority().max(3)),
+                    };
+                    let title = match kind.alarm_title() {
+                        Some(alarm) => format!("{} im Bereich {}", alarm, title),
+                        None => format!("Bereich {}", title),
+                    };
+
+                    let payload = Payload::new(topic)
+                        .title(title)
+                        .message(format!("Gerät: {}, Auslöser: {}", device.description, describe(&source, state)))
+                        .priority(notification_priority(priority));
+
+                    self.notify(payload).await?;
+                }
+
+                // Report a failed insert only after everybody has been alerted
+                result?;
+                incident?;
+            }
+            MessageAction::Subscribe(subscription_message, socket_addr) => {
+                let (subscribe, selection) = match subscription_message {
+                    SubscriptionMessage::Subscribe(selection) => (true, selection),
+                    SubscriptionMessage::Unsubscribe(selection) => (false, selection),
+                };
+
+                let mut subscriptions = Vec::new();
+                if selection.areas.is_empty() {
+                    subscriptions.push(Subscription::Subtree(None));
+                }
+
+                for area in &selection.areas {
+                    match resolve_subscription(&Route::parse(area), self.db.as_ref()).await? {
+                        Some(subscription) => subscriptions.push(subscription),
+                        None => debug!(%area, "Ignoring subscription without an area"),
+                    }
+                }
+
+                let mut peers = self.peer_map.lock().await;
+                let client = peers.iter_mut()
+                    .find(|c| c.socket_addr == socket_addr)
+                    .ok_or(Error::PeerGone(socket_addr))?;
+
+                for event in &selection.events {
+                    for subscription in &subscriptions {
+                        let topic = Topic { event: *event, subscription: *subscription };
+
+                        if !subscribe {
+                            client.topics.retain(|t| *t != topic);
+                        } else if !client.topics.contains(&topic) {
+                            client.topics.push(topic);
+                        }
+                    }
+                }
+
+                let confirmation = Subscriptions {
+                    subscriptions: client.topics.clone(),
+                };
+                client.send_json(&confirmation)?;
+            }
+            MessageAction::Approved { pending_id, device } => {
+                // The device may have asked for a new area
+                self.refresh_zones(zones).await;
+
+                let socket_addr = self.awaiting.lock().await.remove(&pending_id);
+                // Issued before locking the peers, the connections mustn't wait for the database
+                let token = match socket_addr {
+                    Some(_) => Some(self.issue_token(device.id).await?),
+                    None => None,
+                };
+                let issued = token.is_some();
+
+                let delivered = {
+                    let mut peers = self.peer_map.lock().await;
+
+                    match (socket_addr.and_then(|addr| peers.iter_mut().find(|c| c.socket_addr == addr)), token) {
+                        (Some(client), Some(token)) => {
+                            client.device_id = Some(device.id);
+                            client.send_json(&ProvisioningMessage::Approved { device: device.clone(), token: Some(token) })?;
+                            true
+                        }
+                        _ => false,
+                    }
+                };
+
+                if delivered {
+                    info!(id = device.id, uuid = %device.uuid, "Token sent to approved device");
+                } else {
+                    // It disconnected while the token was issued
+                    if issued {
+                        self.db.set_device_token_hash(device.id, None).await?;
+                    }
+                    info!(id = device.id, "Approved device is offline, it gets its token on the next registration");
+                }
+
+                let peers = self.peer_map.lock().await;
+                let event = Event::Status {
+                    message: format!("Gerät freigegeben: {} im Bereich {}", device.description, device.area),
+                };
+                publish(&peers, EventType::Status, None, zones, &Message::text(serde_json::to_string(&event)?));
+            }
+            MessageAction::ConfigAck(message, socket_addr) => {
+                let device_id = self.device(message.device).await.id;
+                if self.require_approval {
+                    self.verify_token(device_id, message.token.as_deref()).await?;
+                }
+
+                let config = self.db.acknowledge_config(device_id, message.config_version).await?;
+                // Updates offered while the device was offline
+                let offers = self.db.update_offers(device_id).await?;
+
+                let mut peers = self.peer_map.lock().await;
+                let client = peers.iter_mut()
+                    .find(|c| c.socket_addr == socket_addr)
+                    .ok_or(Error::PeerGone(socket_addr))?;
+                client.device_id = Some(device_id);
+
+                match config {
+                    Some(config) if config.version != message.config_version => {
+                        client.send_json(&ConfigUpdate::from(&config))?;
+                        info!(device_id, version = config.version, applied = message.config_version, "Config pushed");
+                    }
+                    Some(config) => debug!(device_id, version = config.version, "Device runs the current config"),
+                    None => debug!(device_id, "Device has no config"),
+                }
+
+                for offer in &offers {
+                    client.send_json(&UpdateAvailable::new(offer, &self.public_url))?;
+                    info!(device_id, rollout_id = offer.rollout_id, version = %offer.firmware.version, "Update offered");
+                }
+            }
+            MessageAction::Telemetry(message, socket_addr) => {
+                let device = self.device(message.device).await;
+                if self.require_approval {
+                    self.verify_token(device.id, message.token.as_deref()).await?;
+                }
+
+                let previous = self.db.latest_telemetry(device.id).await?;
+                let new_telemetry = NewTelemetry {
+                    device_id: device.id,
+                    timestamp: Utc::now(),
+                    report: message.telemetry,
+                };
+                let telemetry = self.db.insert_telemetry(&new_telemetry).await?;
+                debug!(id = telemetry.id, "Telemetry stored");
+
+                let mut warnings = self.telemetry_thresholds.crossed(previous.as_ref(), &telemetry);
+                if let Some(reason) = telemetry::crash(previous.as_ref(), &telemetry) {
+                    warnings.push(format!("Neustart nach Absturz: {}", reason));
+                }
+
+                let mut peers = self.peer_map.lock().await;
+                let client = peers.iter_mut()
+                    .find(|c| c.socket_addr == socket_addr)
+                    .ok_or(Error::PeerGone(socket_addr))?;
+                client.device_id = Some(device.id);
+                let area_id = client.area_id;
+
+                let event = Event::Telemetry { telemetry };
+                publish(&peers, EventType::Device, area_id, zones, &Message::text(serde_json::to_string(&event)?));
+
+                if warnings.is_empty() {
+                    return Ok(());
+                }
+                warn!(uuid = %device.uuid, ?warnings, "Telemetry out of range");
+
+                for warning in &warnings {
+                    let event = Event::Status {
+                        message: format!("Gerät {} im Bereich {}: {}", device.description, device.area, warning),
+                    };
+                    publish(&peers, EventType::Status, area_id, zones, &Message::text(serde_json::to_string(&event)?));
+                }
+                drop(peers);
+
+                let payload = Payload::new("Alert-Net-Status")
+                    .title(format!("Gerät {}", device.description))
+                    .message(format!("Bereich: {}\n{}", device.area, warnings.join("\n")))
+                    .priority(Priority::High);
+
+                self.notify(payload).await?;
+            }
+            MessageAction::PushConfig(config) => {
+                let peers = self.peer_map.lock().await;
+
+                match peers.iter().find(|c| c.device_id == Some(config.device_id)) {
+                    Some(client) => {
+                        client.send_json(&ConfigUpdate::from(&config))?;
+                        info!(device_id = config.device_id, version = config.version, "Config pushed");
+                    }
+                    None => info!(device_id = config.device_id, version = config.version, "Device is offline, the config is pushed when it connects"),
+                }
+            }
+            MessageAction::Command { device, command, sent } => {
+                let peers = self.peer_map.lock().await;
+
+                let Some(client) = peers.iter().find(|c| c.device_id == Some(device.id)) else {
+                    info!(uuid = %device.uuid, ?command, "Device is offline, command not sent");
+                    let _ = sent.send(None);
+                    return Ok(());
+                };
+
+                let request_id = Uuid::new_v4();
+                client.send_json(&CommandMessage { request_id, command })?;
+                info!(uuid = %device.uuid, %request_id, ?command, "Command sent");
+
+                let record = CommandRecord {
+                    request_id,
+                    device_id: device.id,
+                    device_uuid: device.uuid,
+                    command,
+                    status: CommandStatus::Sent,
+                    sent_at: Utc::now(),
+                    answered_at: None,
+                    error: None,
+                    diagnostics: None,
+                };
+                self.commands.sent(record.clone());
+
+                let event = Event::Command { record: record.clone() };
+                publish(&peers, EventType::Device, client.area_id, zones, &Message::text(serde_json::to_string(&event)?));
+
+                let _ = sent.send(Some(record));
+            }
+            MessageAction::CommandResponse(response, socket_addr) => {
+                let peers = self.peer_map.lock().await;
+                let client = peers.iter()
+                    .find(|c| c.socket_addr == socket_addr)
+                    .ok_or(Error::PeerGone(socket_addr))?;
+
+                // Only the connection the command went to can answer it, not one without a registered device
+                let record = client.device_id.and_then(|device_id| self.commands.answer(&response, device_id))
+                    .ok_or_else(|| Error::NotFound(format!("command {}", response.request_id)))?;
+                info!(request_id = %record.request_id, command = ?record.command, status = ?record.status, "Command answered");
+
+                let event = Event::Command { record };
+                publish(&peers, EventType::Device, client.area_id, zones, &Message::text(serde_json::to_string(&event)?));
+            }
+            MessageAction::Flooding(socket_addr) => {
+                let mut peers = self.peer_map.lock().await;
+                let client = peers.iter_mut()
+                    .find(|c| c.socket_addr == socket_addr)
+                    .ok_or(Error::PeerGone(socket_addr))?;
+
+                // Flagged once per connection, later windows over the limit are only dropped
+                if client.flooding {
+                    return Ok(());
+                }
+                client.flooding = true;
+                let uri = client.uri.to_string();
+                let area_id = client.area_id;
+                warn!(%uri, device_id = ?client.device_id, "Connection exceeds the rate limit");
+
+                let event = Event::Status {
+                    message: format!("Gerät unter {} ({}) sendet zu viele Nachrichten, sie werden verworfen", uri, socket_addr),
+                };
+                publish(&peers, EventType::Status, area_id, zones, &Message::text(serde_json::to_string(&event)?));
+                drop(peers);
+
+                let payload = Payload::new("Alert-Net-Status")
+                    .title("Zu viele Nachrichten")
+                    .message(format!("Bereich: {}, Adresse: {}. Nachrichten über dem Limit werden verworfen.", uri, socket_addr))
+                    .priority(Priority::High);
+
+                self.notify(payload).await?;
+            }
+            MessageAction::OfferUpdates(rollout) => {
+                self.offer_updates(&rollout, zones).await?;
+            }
+            MessageAction::UpdateStatus(message, socket_addr) => {
+                let device = self.device(message.device).await;
+                if self.require_approval {
+                    self.verify_token(device.id, message.token.as_deref()).await?;
+                }
+
+                let rollout = self.db.set_update_status(message.rollout_id, device.id, message.status, message.error.as_deref()).await?
+                    .ok_or_else(|| Error::NotFound(format!("update of device {} in rollout {}", device.id, message.rollout_id)))?;
+                info!(rollout_id = rollout.id, uuid = %device.uuid, status = ?message.status, error = ?message.error, "Update status");
+
+                let mut peers = self.peer_map.lock().await;
+                let client = peers.iter_mut()
+                    .find(|c| c.socket_addr == socket_addr)
+                    .ok_or(Error::PeerGone(socket_addr))?;
+                client.device_id = Some(device.id);
+
+                let event = Event::Rollout { rollout: rollout.clone() };
+                publish(&peers, EventType::Device, None, zones, &Message::text(serde_json::to_string(&event)?));
+                drop(peers);
+
+                match message.status {
+                    UpdateStatus::Failed => {
+                        let payload = Payload::new("Alert-Net-Status")
+                            .title("Firmware-Update fehlgeschlagen")
+                            .message(format!(
+                                "Gerät: {}, Version: {}, Fehler: {}. Der Rollout ist angehalten.",
+                                device.description,
+                                rollout.firmware.version,
+                                message.error.as_deref().unwrap_or("unbekannt"),
+                            ))
+                            .priority(Priority::High);
+
+                        self.notify(payload).await?;
+                    }
+                    UpdateStatus::Succeeded if rollout.stage_done() => {
+                        if let Some(rollout) = self.db.advance_rollout(rollout.id).await? {
+                            self.offer_updates(&rollout, zones).await?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            MessageAction::CloseConnection(uri, socket, area_id) => {
+                let event = Event::Device {
+                    online: false,
+                    uri: uri.clone(),
+                    socket_addr: socket.clone(),
+                    area_id,
+                };
+                publish(&self.peer_map.lock().await, EventType::Device, area_id, zones, &Message::text(serde_json::to_string(&event)?));
+
+                // Connections closed by the shutdown are covered by its notification
+                if self.stopping.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+
+                let payload = Payload::new("Alert-Net-Status")
+                    .title("Verbindung geschlossen")
+                    .message(format!("Bereich: {}, Adresse: {}", uri, socket))
+                    .priority(Priority::High);
+
+                self.notify(payload).await?;
+            }
+            MessageAction::OpenConnection(uri, socket, area_id) => {
+                // A new connection may have created its area
+                self.refresh_zones(zones).await;
+
+                let event = Event::Device {
+                    online: true,
+                    uri: uri.clone(),
+                    socket_addr: socket.clone(),
+                    area_id,
+                };
+                publish(&self.peer_map.lock().await, EventType::Device, area_id, zones, &Message::text(serde_json::to_string(&event)?));
+
+                let payload = Payload::new("Alert-Net-Status")
+                    .title("Verbindung gestartet")
+                    .message(format!("Bereich: {}, Adresse: {}", uri, socket))
+                    .priority(Priority::Default);
+
+                self.notify(payload).await?;
+            }
+            MessageAction::Stopping(done) => {
+                self.stopping.store(true, Ordering::SeqCst);
+
+                let event = Event::Status {
+                    message: "Alert Net server wird beendet".to_string(),
+                };
+
+                let close_message = Message::Close(Some(CloseFrame {
+                    code: CloseCode::Away,
+                    reason: "Server wird beendet".into(),
+                }));
+
+                let peers = self.peer_map.lock().await;
+                publish(&peers, EventType::Status, None, zones, &Message::text(serde_json::to_string(&event)?));
+
+                for peer in peers.iter() {
+                    let _ = peer.send(close_message.clone());
+                }
+
+                let _ = done.send(());
+            }
+            MessageAction::Shutdown(done) => {
+                let payload = Payload::new("Alert-Net-Status")
+                    .title("Alert Net server")
+                    .message("Alert Net server beendet")
+                    .priority(Priority::High);
+
+                let result = self.notify(payload).await;
+                let _ = done.send(());
+
+                result?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,706 @@
+use std::future::Future;
+use std::time::Duration;
+use axum::body::{Body, Bytes};
+use axum::extract::DefaultBodyLimit;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderName, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+use crate::command::CommandLog;
+use crate::common::models::command::{CommandKind, CommandRecord, CommandStatus};
+use crate::common::models::config::{ConfigChange, DeviceConfig};
+use crate::common::models::area::{Area, AreaChange};
+use crate::common::models::correlation::{CorrelationRule, PreAlarm, PreAlarmQuery, RuleChange};
+use crate::common::models::device::{Device, PendingDevice};
+use crate::common::models::firmware::{Firmware, NewFirmware, NewRollout, Rollout};
+use crate::common::models::history::{AreaCount, Bucket, DetectionFilter, DetectionPage, DeviceCount, PageRequest};
+use crate::common::models::telemetry::{DeviceStatus, Telemetry, TelemetryQuery};
+use crate::common::zone::ZoneTree;
+use crate::database::Db;
+use crate::error::{Error, Result};
+use crate::export::{self, ExportFormat};
+use crate::handler::{ActionSender, MessageAction};
+use crate::message::send::error::ErrorMessage;
+use crate::metrics::METRICS;
+use crate::provisioning::hash_token;
+
+/// Upper bound for a single readiness check, so a hanging dependency can't block the probe.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest a request waits for the answer of a device.
+const MAX_COMMAND_WAIT: Duration = Duration::from_secs(30);
+/// Largest firmware image accepted for upload.
+const MAX_FIRMWARE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct HttpState {
+    pub db: Db,
+    pub actions: ActionSender,
+    pub ntfy_url: String,
+    pub client: reqwest::Client,
+    pub commands: CommandLog,
+    /// `ADMIN_TOKEN`, without it every request to the API is rejected.
+    pub admin_token: Option<String>,
+}
+
+/// HTTP endpoints served next to the WebSocket server.
+/// The API needs the admin token, only probes and firmware downloads of the devices are open.
+pub fn router(state: HttpState) -> Router {
+    Router::new()
+        .route("/api/detections", get(detections))
+        .route("/api/detections/counts", get(detection_counts))
+        .route("/api/areas", get(areas).put(update_area))
+        .route("/api/correlation", get(correlation_rules).put(set_correlation_rule).delete(delete_correlation_rule))
+        .route("/api/pre-alarms", get(pre_alarms))
+        .route("/api/devices", get(device_statuses))
+        .route("/api/devices/noisy", get(noisy_devices))
+        .route("/api/devices/config", get(device_configs))
+        .route("/api/devices/:id/config", get(device_config).put(update_device_config))
+        .route("/api/devices/:id/telemetry", get(device_telemetry))
+        .route("/api/devices/pending", get(pending_devices))
+        .route("/api/devices/pending/:id", delete(reject_device))
+        .route("/api/devices/pending/:id/approve", post(approve_device))
+        .route("/api/commands", get(commands).post(send_command))
+        .route("/api/commands/:request_id", get(command))
+        .route("/api/firmware", get(firmwares).post(upload_firmware).layer(DefaultBodyLimit::max(MAX_FIRMWARE_SIZE)))
+        .route("/api/firmware/rollouts", get(rollouts).post(create_rollout))
+        .route("/api/firmware/rollouts/:id", get(rollout))
+        .route("/api/firmware/rollouts/:id/advance", post(advance_rollout))
+        .route("/api/export/detections", get(export_detections))
+        .route("/api/export/incidents", get(export_incidents))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        // The devices can't send the admin token
+        .route("/api/firmware/:id/image", get(firmware_image))
+        .with_state(state)
+}
+
+async fn metrics() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], METRICS.render())
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Json(_) | Error::InvalidConfig(_) | Error::InvalidFirmware(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::NotApproved(_) | Error::WrongPairingCode => StatusCode::FORBIDDEN,
+            Error::DeviceOffline(_) => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        if status.is_server_error() {
+            tracing::error!(err = %self, "Request failed");
+        }
+
+        (status, Json(ErrorMessage { error: self.to_string() })).into_response()
+    }
+}
+
+
+// ---- Admin authentication
+
+/// Lets requests with `Authorization: Bearer <ADMIN_TOKEN>` through.
+async fn require_admin(State(state): State<HttpState>, headers: HeaderMap, request: Request, next: Next) -> Result<Response> {
+    let token = headers.get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    // Comparing the hashes takes the same time however much of the token is right
+    match (token, &state.admin_token) {
+        (Some(token), Some(admin_token)) if hash_token(token.trim()) == hash_token(admin_token) => Ok(next.run(request).await),
+        _ => Err(Error::Unauthorized),
+    }
+}
+
+
+// ---- Health
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+}
+
+/// Answers as long as the process is able to serve requests.
+async fn healthz() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+
+// ---- Readiness
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    async fn run<F>(check: F) -> Self
+    where
+        F: Future<Output = std::result::Result<(), String>>,
+    {
+        let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("Timed out after {}s", CHECK_TIMEOUT.as_secs())),
+        };
+
+        Check {
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    backend: &'static str,
+    database: Check,
+    migrations: Check,
+    message_handler: Check,
+    notifier: Check,
+}
+
+/// Reports whether the server can do its job: database and schema are in place,
+/// the message handler works off the queue and ntfy is reachable.
+async fn readyz(State(state): State<HttpState>) -> impl IntoResponse {
+    let database = Check::run(async {
+        state.db.ping().await.map_err(|err| err.to_string())
+    }).await;
+
+    let migrations = Check::run(async {
+        match state.db.pending_migrations().await {
+            Ok(pending) if pending.is_empty() => Ok(()),
+            Ok(pending) => Err(format!("Pending migrations: {:?}", pending)),
+            Err(err) => Err(err.to_string()),
+        }
+    }).await;
+
+    // The queue closes once the handler and its supervisor stopped
+    let message_handler = Check::run(async {
+        if state.actions.is_closed() {
+            Err("Message handler is not running".to_string())
+        } else {
+            Ok(())
+        }
+    }).await;
+
+    let notifier = Check::run(async {
+        let url = format!("{}/v1/health", state.ntfy_url.trim_end_matches('/'));
+
+        match state.client.get(&url).send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("ntfy answered with {}", response.status())),
+            Err(err) => Err(err.to_string()),
+        }
+    }).await;
+
+    let ready = database.ok && migrations.ok && message_handler.ok && notifier.ok;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (status, Json(Readiness {
+        status: if ready { "ok" } else { "unavailable" },
+        backend: state.db.backend(),
+        database,
+        migrations,
+        message_handler,
+        notifier,
+    }))
+}
+
+
+// ---- Detection history
+
+#[derive(Deserialize)]
+struct CountsQuery {
+    #[serde(default)]
+    bucket: Bucket,
+}
+
+#[derive(Deserialize)]
+struct NoisyQuery {
+    limit: Option<i64>,
+}
+
+/// `GET /api/detections?areas=laden&device=<uuid>&source=pir&from=<rfc3339>&to=<rfc3339>&cursor=<id>&limit=100`
+async fn detections(
+    State(state): State<HttpState>,
+    Query(filter): Query<DetectionFilter>,
+    Query(page): Query<PageRequest>,
+) -> Result<Json<DetectionPage>> {
+    let page = state.db.detection_history(&filter, page).await?;
+
+    Ok(Json(page))
+}
+
+/// `GET /api/detections/counts?bucket=hour` with the filters of `/api/detections`
+async fn detection_counts(
+    State(state): State<HttpState>,
+    Query(filter): Query<DetectionFilter>,
+    Query(query): Query<CountsQuery>,
+) -> Result<Json<Vec<AreaCount>>> {
+    let counts = state.db.detection_counts(&filter, query.bucket).await?;
+
+    Ok(Json(counts))
+}
+
+/// `GET /api/devices/noisy?limit=10` with the filters of `/api/detections`
+async fn noisy_devices(
+    State(state): State<HttpState>,
+    Query(filter): Query<DetectionFilter>,
+    Query(query): Query<NoisyQuery>,
+) -> Result<Json<Vec<DeviceCount>>> {
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+    let devices = state.db.noisy_devices(&filter, limit).await?;
+
+    Ok(Json(devices))
+}
+
+
+// ---- Areas and zones
+
+/// `GET /api/areas`, every area and zone with its settings
+async fn areas(State(state): State<HttpState>) -> Result<Json<Vec<Area>>> {
+    let areas = state.db.areas().await?;
+
+    Ok(Json(areas))
+}
+
+/// `PUT /api/areas`, e.g. `{"area": "eg/küche", "parent": "eg", "propagate": true}`. Unknown areas are created,
+/// the message handler picks the change up with the next detection.
+async fn update_area(State(state): State<HttpState>, Json(change): Json<AreaChange>) -> Result<Json<Area>> {
+    change.validate()?;
+
+    let area = state.db.area_get_or_create(&change.area).await?;
+    let parent_id = match &change.parent {
+        Some(parent) => {
+            let parent = state.db.area_get_or_create(parent).await?;
+
+            // A loop would cut the zones off from the rest of the tree
+            if ZoneTree::new(state.db.areas().await?).ancestors(parent.id).contains(&area.id) {
+                return Err(Error::InvalidConfig("parent must not be the area or one of its zones".to_string()));
+            }
+            Some(parent.id)
+        }
+        None => None,
+    };
+
+    let area = change.apply(&area, parent_id);
+    state.db.update_area(&area).await?;
+    tracing::info!(area = %area.slug, parent_id = ?area.parent_id, armed = area.armed, propagate = area.propagate, "Area changed");
+
+    Ok(Json(area))
+}
+
+
+// ---- Correlation rules
+
+#[derive(Deserialize)]
+struct AreaQuery {
+    area: String,
+}
+
+/// `GET /api/correlation`, the rules of all areas that have one
+async fn correlation_rules(State(state): State<HttpState>) -> Result<Json<Vec<CorrelationRule>>> {
+    let rules = state.db.correlation_rules().await?;
+
+    Ok(Json(rules))
+}
+
+/// `PUT /api/correlation` with `{"area": "laden", "min_detections": 2, "min_devices": 2, "window_s": 60}`
+/// or `{"area": "laden", "first_source": "door", "then_source": "motion", "window_s": 30}`, both parts can be combined
+async fn set_correlation_rule(State(state): State<HttpState>, Json(change): Json<RuleChange>) -> Result<Json<CorrelationRule>> {
+    change.validate()?;
+
+    let rule = state.db.set_correlation_rule(&change).await?;
+    tracing::info!(area = %rule.area, min_detections = ?rule.min_detections, min_devices = rule.min_devices, window_s = rule.window_s, "Correlation rule set");
+
+    Ok(Json(rule))
+}
+
+/// `DELETE /api/correlation?area=laden`, every detection of the area alarms again
+async fn delete_correlation_rule(State(state): State<HttpState>, Query(query): Query<AreaQuery>) -> Result<Json<CorrelationRule>> {
+    let rule = state.db.delete_correlation_rule(&query.area).await?
+        .ok_or_else(|| Error::NotFound(format!("correlation rule of {}", query.area)))?;
+    tracing::info!(area = %rule.area, "Correlation rule removed");
+
+    Ok(Json(rule))
+}
+
+/// `GET /api/pre-alarms?area=laden&from=<rfc3339>&to=<rfc3339>&limit=100`, newest first
+async fn pre_alarms(State(state): State<HttpState>, Query(query): Query<PreAlarmQuery>) -> Result<Json<Vec<PreAlarm>>> {
+    let pre_alarms = state.db.pre_alarms(&query).await?;
+
+    Ok(Json(pre_alarms))
+}
+
+
+// ---- Device approval
+
+/// Settings of the approved device, missing ones are taken from the request of the device.
+#[derive(Deserialize)]
+struct Approval {
+    description: Option<String>,
+    area: Option<String>,
+    /// Has to match the code shown by the device, so the admin approves the device in front of them.
+    pairing_code: Option<String>,
+}
+
+/// `GET /api/devices/pending`, oldest request first
+async fn pending_devices(State(state): State<HttpState>) -> Result<Json<Vec<PendingDevice>>> {
+    let devices = state.db.pending_devices().await?;
+
+    Ok(Json(devices))
+}
+
+/// `POST /api/devices/pending/<id>/approve` with `{"description": "Flur", "area": "laden", "pairing_code": "123456"}`
+async fn approve_device(
+    State(state): State<HttpState>,
+    Path(id): Path<i64>,
+    Json(approval): Json<Approval>,
+) -> Result<Json<Device>> {
+    let pending = state.db.pending_devices().await?
+        .into_iter()
+        .find(|pending| pending.id == id)
+        .ok_or_else(|| Error::NotFound(format!("pending device {}", id)))?;
+
+    if approval.pairing_code.as_deref().map(str::trim) != Some(pending.pairing_code.as_str()) {
+        return Err(Error::WrongPairingCode);
+    }
+
+    let description = approval.description.unwrap_or(pending.description);
+    let area = approval.area.unwrap_or(pending.area);
+
+    // Gone if it was approved or rejected in the meantime
+    let device = state.db.approve_device(id, &description, &area).await?
+        .ok_or_else(|| Error::NotFound(format!("pending device {}", id)))?;
+    tracing::info!(pending_id = id, id = device.id, uuid = %device.uuid, area = %device.area, "Device approved");
+
+    // The handler delivers the token, it knows the connection of the device
+    let action = MessageAction::Approved { pending_id: id, device: device.clone() };
+    if state.actions.send((action, tracing::Span::current())).is_err() {
+        tracing::error!("Message handler is not running, the device gets its token on the next registration");
+    }
+
+    Ok(Json(device))
+}
+
+/// `DELETE /api/devices/pending/<id>`, the device can request approval again
+async fn reject_device(State(state): State<HttpState>, Path(id): Path<i64>) -> Result<Json<PendingDevice>> {
+    let pending = state.db.reject_device(id).await?
+        .ok_or_else(|| Error::NotFound(format!("pending device {}", id)))?;
+    tracing::info!(pending_id = id, "Device rejected");
+
+    Ok(Json(pending))
+}
+
+
+// ---- Device configuration
+
+/// `GET /api/devices/config`, with the version each device applied
+async fn device_configs(State(state): State<HttpState>) -> Result<Json<Vec<DeviceConfig>>> {
+    let configs = state.db.device_configs().await?;
+
+    Ok(Json(configs))
+}
+
+/// `GET /api/devices/<id>/config`, not found until the config was edited the first time
+async fn device_config(State(state): State<HttpState>, Path(id): Path<i64>) -> Result<Json<DeviceConfig>> {
+    let config = state.db.device_config(id).await?
+        .ok_or_else(|| Error::NotFound(format!("config of device {}", id)))?;
+
+    Ok(Json(config))
+}
+
+/// `PUT /api/devices/<id>/config` with `{"description": "Tür", "area": "laden", "speaker_volume": 20, "motion_debounce_ms": 2000,
+/// "heartbeat_interval_s": 15, "server_address": "192.168.0.88:3000"}`, missing settings are left to the device
+async fn update_device_config(
+    State(state): State<HttpState>,
+    Path(id): Path<i64>,
+    Json(change): Json<ConfigChange>,
+) -> Result<Json<DeviceConfig>> {
+    change.validate()?;
+
+    let config = state.db.update_device_config(id, &change).await?
+        .ok_or_else(|| Error::NotFound(format!("device {}", id)))?;
+    tracing::info!(device_id = id, version = config.version, "Device config changed");
+
+    if state.actions.send((MessageAction::PushConfig(config.clone()), tracing::Span::current())).is_err() {
+        tracing::error!("Message handler is not running, the config is pushed when the device connects");
+    }
+
+    Ok(Json(config))
+}
+
+
+// ---- Device telemetry
+
+/// `GET /api/devices`, every device with the latest telemetry it sent
+async fn device_statuses(State(state): State<HttpState>) -> Result<Json<Vec<DeviceStatus>>> {
+    let statuses = state.db.device_statuses().await?;
+
+    Ok(Json(statuses))
+}
+
+/// `GET /api/devices/<id>/telemetry?from=2024-05-01T00:00:00Z&to=...&limit=500`, newest first
+async fn device_telemetry(
+    State(state): State<HttpState>,
+    Path(id): Path<i64>,
+    Query(query): Query<TelemetryQuery>,
+) -> Result<Json<Vec<Telemetry>>> {
+    let telemetry = state.db.telemetry_history(id, &query).await?;
+
+    Ok(Json(telemetry))
+}
+
+
+// ---- Device commands
+
+#[derive(Deserialize)]
+struct CommandRequest {
+    device: Uuid,
+    command: CommandKind,
+}
+
+#[derive(Deserialize)]
+struct CommandWait {
+    /// Seconds to wait for the answer of the device.
+    #[serde(default = "default_command_wait")]
+    wait: u64,
+}
+
+fn default_command_wait() -> u64 {
+    10
+}
+
+#[derive(Deserialize)]
+struct CommandsQuery {
+    device: Option<Uuid>,
+}
+
+/// `POST /api/commands?wait=10` with `{"device": "<uuid>", "command": "diagnostics"}`.
+/// Answers 200 with the answer of the device, or 202 if it did not answer in time.
+async fn send_command(
+    State(state): State<HttpState>,
+    Query(query): Query<CommandWait>,
+    Json(request): Json<CommandRequest>,
+) -> Result<(StatusCode, Json<CommandRecord>)> {
+    let device = state.db.device_by_uuid(request.device).await?
+        .ok_or_else(|| Error::NotFound(format!("device {}", request.device)))?;
+
+    // The handler knows the connection of the device
+    let (sent_tx, sent_rx) = oneshot::channel();
+    let action = MessageAction::Command { device, command: request.command, sent: sent_tx };
+    if state.actions.send((action, tracing::Span::current())).is_err() {
+        return Err(Error::DeviceOffline(request.device));
+    }
+
+    let record = sent_rx.await.ok().flatten().ok_or(Error::DeviceOffline(request.device))?;
+    let wait = Duration::from_secs(query.wait).min(MAX_COMMAND_WAIT);
+
+    match tokio::time::timeout(wait, state.commands.wait(record.request_id)).await {
+        Ok(Ok(answered)) => Ok((StatusCode::OK, Json(answered))),
+        _ => Ok((StatusCode::ACCEPTED, Json(state.commands.get(record.request_id).unwrap_or(record)))),
+    }
+}
+
+/// `GET /api/commands?device=<uuid>`, newest first
+async fn commands(State(state): State<HttpState>, Query(query): Query<CommandsQuery>) -> Json<Vec<CommandRecord>> {
+    Json(state.commands.recent(query.device))
+}
+
+/// `GET /api/commands/<request_id>`
+async fn command(State(state): State<HttpState>, Path(request_id): Path<Uuid>) -> Result<(StatusCode, Json<CommandRecord>)> {
+    let record = state.commands.get(request_id)
+        .ok_or_else(|| Error::NotFound(format!("command {}", request_id)))?;
+    let status = if record.status == CommandStatus::Sent { StatusCode::ACCEPTED } else { StatusCode::OK };
+
+    Ok((status, Json(record)))
+}
+
+
+// ---- Firmware updates
+
+#[derive(Deserialize)]
+struct FirmwareUpload {
+    version: String,
+    board: String,
+}
+
+/// `POST /api/firmware?version=1.1.0&board=d1_mini` with the image as body
+async fn upload_firmware(
+    State(state): State<HttpState>,
+    Query(upload): Query<FirmwareUpload>,
+    image: Bytes,
+) -> Result<Json<Firmware>> {
+    if upload.version.trim().is_empty() || upload.board.trim().is_empty() {
+        return Err(Error::InvalidFirmware("version and board are required".to_string()));
+    }
+    if image.is_empty() {
+        return Err(Error::InvalidFirmware("the image is empty".to_string()));
+    }
+
+    let firmware = NewFirmware::new(&upload.version, &upload.board, image.to_vec());
+    if state.db.firmwares().await?.iter().any(|known| known.board == firmware.board && known.version == firmware.version) {
+        return Err(Error::InvalidFirmware(format!("version {} for {} was uploaded before", firmware.version, firmware.board)));
+    }
+
+    let firmware = state.db.insert_firmware(&firmware).await?;
+    tracing::info!(id = firmware.id, version = %firmware.version, board = %firmware.board, size = firmware.size, "Firmware uploaded");
+
+    Ok(Json(firmware))
+}
+
+/// `GET /api/firmware`, newest first
+async fn firmwares(State(state): State<HttpState>) -> Result<Json<Vec<Firmware>>> {
+    let firmwares = state.db.firmwares().await?;
+
+    Ok(Json(firmwares))
+}
+
+/// `GET /api/firmware/<id>/image`, ESP8266httpUpdate checks the image against the `x-MD5` header
+async fn firmware_image(State(state): State<HttpState>, Path(id): Path<i64>) -> Result<impl IntoResponse> {
+    let (firmware, image) = state.db.firmware_image(id).await?
+        .ok_or_else(|| Error::NotFound(format!("firmware {}", id)))?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (HeaderName::from_static("x-md5"), firmware.md5),
+            (HeaderName::from_static("x-checksum-sha256"), firmware.sha256),
+        ],
+        image,
+    ))
+}
+
+#[derive(Deserialize)]
+struct RolloutRequest {
+    firmware_id: i64,
+    /// Devices by uuid, they are updated first.
+    #[serde(default)]
+    devices: Vec<Uuid>,
+    /// All devices of these areas.
+    #[serde(default)]
+    areas: Vec<String>,
+    /// Devices per stage, all in one stage if unset.
+    batch_size: Option<i32>,
+}
+
+/// `POST /api/firmware/rollouts` with `{"firmware_id": 4, "devices": ["<uuid>"], "areas": ["laden"], "batch_size": 2}`,
+/// the first stage is offered right away
+async fn create_rollout(State(state): State<HttpState>, Json(request): Json<RolloutRequest>) -> Result<Json<Rollout>> {
+    let devices: Vec<Device> = state.db.device_statuses().await?.into_iter().map(|status| status.device).collect();
+
+    let mut selected: Vec<&Device> = Vec::new();
+    for uuid in &request.devices {
+        let device = devices.iter().find(|device| device.uuid == *uuid)
+            .ok_or_else(|| Error::NotFound(format!("device {}", uuid)))?;
+        selected.push(device);
+    }
+    for area in request.areas.iter().map(|area| Area::slugify(area)) {
+        selected.extend(devices.iter().filter(|device| device.area == area));
+    }
+
+    let mut device_ids: Vec<i64> = Vec::new();
+    for device in selected {
+        if !device_ids.contains(&device.id) {
+            device_ids.push(device.id);
+        }
+    }
+    if device_ids.is_empty() {
+        return Err(Error::InvalidFirmware("the rollout has no devices".to_string()));
+    }
+
+    let batch_size = request.batch_size.unwrap_or(device_ids.len() as i32);
+    if batch_size < 1 {
+        return Err(Error::InvalidFirmware("batch_size has to be at least 1".to_string()));
+    }
+
+    let new_rollout = NewRollout { firmware_id: request.firmware_id, device_ids, batch_size };
+    let rollout = state.db.create_rollout(&new_rollout).await?
+        .ok_or_else(|| Error::NotFound(format!("firmware {}", request.firmware_id)))?;
+    tracing::info!(rollout_id = rollout.id, version = %rollout.firmware.version, devices = rollout.updates.len(), batch_size, "Rollout started");
+
+    if state.actions.send((MessageAction::OfferUpdates(rollout.clone()), tracing::Span::current())).is_err() {
+        tracing::error!("Message handler is not running, the update is offered when the devices connect");
+    }
+
+    Ok(Json(rollout))
+}
+
+/// `GET /api/firmware/rollouts`, newest first
+async fn rollouts(State(state): State<HttpState>) -> Result<Json<Vec<Rollout>>> {
+    let rollouts = state.db.rollouts().await?;
+
+    Ok(Json(rollouts))
+}
+
+/// `GET /api/firmware/rollouts/<id>`, with the status of each device
+async fn rollout(State(state): State<HttpState>, Path(id): Path<i64>) -> Result<Json<Rollout>> {
+    let rollout = state.db.rollout(id).await?
+        .ok_or_else(|| Error::NotFound(format!("rollout {}", id)))?;
+
+    Ok(Json(rollout))
+}
+
+/// `POST /api/firmware/rollouts/<id>/advance`, offers the next stage, also after a failed update
+async fn advance_rollout(State(state): State<HttpState>, Path(id): Path<i64>) -> Result<Json<Rollout>> {
+    let rollout = state.db.advance_rollout(id).await?
+        .ok_or_else(|| Error::NotFound(format!("rollout {}", id)))?;
+    tracing::info!(rollout_id = id, stage = rollout.stage, "Rollout advanced");
+
+    if state.actions.send((MessageAction::OfferUpdates(rollout.clone()), tracing::Span::current())).is_err() {
+        tracing::error!("Message handler is not running, the update is offered when the devices connect");
+    }
+
+    Ok(Json(rollout))
+}
+
+
+// ---- Export
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// `GET /api/export/detections?format=csv&areas=laden&from=<rfc3339>&to=<rfc3339>`, streamed as download
+async fn export_detections(
+    State(state): State<HttpState>,
+    Query(filter): Query<DetectionFilter>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    download("detections", query.format, export::detections(filter, query.format, state.db.clone()))
+}
+
+/// `GET /api/export/incidents` with the filters of `/api/export/detections`, the alarms that were raised
+async fn export_incidents(
+    State(state): State<HttpState>,
+    Query(filter): Query<DetectionFilter>,
+    Query(query): Query<ExportQuery>,
+) -> impl IntoResponse {
+    download("incidents", query.format, export::incidents(filter, query.format, state.db.clone()))
+}
+
+fn download(name: &str, format: ExportFormat, lines: impl Stream<Item = Result<String>> + Send + 'static) -> impl IntoResponse {
+    let disposition = format!("attachment; filename=\"{}.{}\"", name, format.extension());
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(lines),
+    )
+}
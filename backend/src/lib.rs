@@ -0,0 +1,15 @@
+pub mod client;
+pub mod command;
+pub mod common;
+pub mod correlation;
+pub mod database;
+pub mod error;
+pub mod export;
+pub mod flood;
+pub mod handler;
+pub mod http;
+pub mod message;
+pub mod metrics;
+pub mod provisioning;
+pub mod retention;
+pub mod telemetry;
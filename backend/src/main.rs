@@ -1,237 +1,509 @@
-mod message;
-mod common;
-mod database;
-
 use std::env;
-use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::net::SocketAddr;
 use std::sync::{Arc};
-use chrono::Utc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use clap::Parser;
 use dotenv::dotenv;
-use futures::executor::block_on;
 
-use futures_channel::mpsc::{unbounded, UnboundedSender};
-use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
-use ntfy::{Auth, Dispatcher, Payload, Priority};
+use futures_channel::mpsc::unbounded;
+use futures_util::{future, pin_mut, stream::TryStreamExt, Stream, StreamExt};
+use ntfy::{Dispatcher, Payload, Priority};
 
-use serde::{Deserialize, Serialize};
-use sqlx::{Acquire, Connection, Pool, Postgres};
-use sqlx::postgres::PgPoolOptions;
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio_tungstenite::tungstenite::protocol::Message;
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+use tracing::field::Empty;
+use tracing_subscriber::EnvFilter;
 use tungstenite::{handshake::server::{ErrorResponse, Request, Response}, http::Uri};
 use uuid::Uuid;
-use crate::common::models::device::Device;
-use crate::database::Database;
-use crate::message::receive::detection::DetectionMessage;
-use crate::message::send::alert::Alert;
-
-#[derive(Clone)]
-struct Client {
-    socket_addr: SocketAddr,
-    tx: Tx,
-    uri: Uri,
+use alert_net_server::client::{Client, ClientOut, PeerMap};
+use alert_net_server::command::CommandLog;
+use alert_net_server::common::models::device::NewDevice;
+use alert_net_server::common::route::Route;
+use alert_net_server::common::topic::{EventType, Topic};
+use alert_net_server::handler::{resolve_subscription, ActionSender, MessageAction, MessageHandler};
+use alert_net_server::message::receive::command::CommandResponseMessage;
+use alert_net_server::message::receive::config::ConfigAckMessage;
+use alert_net_server::message::receive::detection::DetectionMessage;
+use alert_net_server::message::receive::register::RegisterMessage;
+use alert_net_server::message::receive::subscription::SubscriptionMessage;
+use alert_net_server::message::receive::telemetry::TelemetryMessage;
+use alert_net_server::message::receive::update::UpdateStatusMessage;
+use alert_net_server::message::send::error::ErrorMessage;
+use alert_net_server::database::{self, Db};
+use alert_net_server::export;
+use alert_net_server::flood::{FloodProtection, Rate, RateLimiter};
+use alert_net_server::http::{self, HttpState};
+use alert_net_server::metrics::{METRICS, UNKNOWN_AREA};
+use alert_net_server::provisioning;
+use alert_net_server::retention::RetentionPolicy;
+use alert_net_server::telemetry::TelemetryThresholds;
+use alert_net_server::database::memory::MemoryStorage;
+use crate::cli::{Cli, Command, CommandArgs, ExportArgs, ExportRecords, StorageKind};
+
+mod cli;
+
+/// How long clients get to answer the close frame before the server stops anyway.
+const SHUTDOWN_PEER_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the message handler gets to work off its queue.
+const SHUTDOWN_HANDLER_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Numbers the connections, so their log lines can be told apart.
+static CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Sets up logging. The level is taken from `RUST_LOG` or `LOG_LEVEL` (default `info`),
+/// `LOG_FORMAT=json` writes one JSON object per line including the current spans.
+fn init_tracing() {
+    let filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string())))
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+
+    // Logs go to stderr, so exports can be written to stdout
+    let builder = tracing_subscriber::fmt().with_env_filter(filter).with_writer(io::stderr);
+
+    match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().with_current_span(true).with_span_list(true).init(),
+        _ => builder.init(),
+    }
 }
 
-#[derive(Clone, Deserialize, Serialize)]
-struct ClientOut {
-    socket_addr: String,
-    uri: String,
-}
+/// Completes on Ctrl-C or, on unix, SIGTERM.
+async fn shutdown_signal() {
+    // Without a listener the other signal still stops the server, so a failure is only logged
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!(%err, "Failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
 
-enum MessageAction {
-    Register((Device, SocketAddr)),
-    Detection(DetectionMessage),
-    Test(String),
-    CloseConnection(String, String),
-    OpenConnection(String, String),
-}
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!(%err, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<Vec<Client>>>;
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
 
-async fn handle_connection(peer_map: PeerMap, raw_stream: TcpStream, addr: SocketAddr, tx_test: tokio::sync::mpsc::UnboundedSender<MessageAction>) {
-    println!("Incoming TCP connection from: {}", addr);
+#[allow(clippy::result_large_err)]
+async fn handle_connection(peer_map: PeerMap, db: Db, raw_stream: TcpStream, addr: SocketAddr, tx_test: ActionSender, flood: FloodProtection) {
+    info!("Incoming TCP connection");
 
     let mut test: Option<Uri> = None;
     let copy_headers_callback = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
         let temp = Request::uri(request);
-        println!("URI: {}", temp);
+        debug!(uri = %temp, "Handshake request");
 
         test = Some(temp.clone());
 
         for (name, value) in request.headers().iter() {
-            println!("Name: {}, value: {}", name.to_string(), value.to_str().expect("expected a value"));
+            debug!(header = %name, value = value.to_str().unwrap_or("<binary>"));
         }
 
         Ok(response)
     };
 
-    let ws_stream = tokio_tungstenite::accept_hdr_async(raw_stream, copy_headers_callback)
-        .await
-        .expect("Error during the websocket handshake occurred");
-    println!("WebSocket connection established: {}", addr);
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(raw_stream, copy_headers_callback).await {
+        Ok(ws_stream) => ws_stream,
+        Err(err) => {
+            warn!(%err, "Error during the websocket handshake occurred");
+            return;
+        }
+    };
+    let Some(uri) = test else {
+        warn!("No URI received");
+        return;
+    };
+
+    // Actions carry the current span, so the handler logs within the connection and message they came from
+    let queue = |action: MessageAction| {
+        if tx_test.send((action, Span::current())).is_err() {
+            error!("Message handler is not running, dropped message");
+        }
+    };
 
 
     // Insert the write part of this peer to the peer map.
     let (tx, rx) = unbounded();
 
+    let route = Route::parse(&uri.to_string());
+    let subscription = match resolve_subscription(&route, db.as_ref()).await {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            error!(?route, %err, "Could not resolve area for route");
+            None
+        }
+    };
+
+    let span = Span::current();
+    span.record("route", route.kind());
+    span.record("area", route.slug());
+    if let Some(area_id) = subscription.and_then(|s| s.area_id()) {
+        span.record("area_id", area_id);
+    }
+    info!(%uri, "WebSocket connection established");
+
+    // The slug is only a label once its area was resolved, a path that couldn't be resolved is unknown
+    let area_label = route.slug().filter(|_| subscription.and_then(|s| s.area_id()).is_some()).unwrap_or(UNKNOWN_AREA);
+    let connections = METRICS.connections.with_label_values(&[area_label, route.kind()]);
+    connections.inc();
+    let dropped = METRICS.messages_dropped.with_label_values(&[area_label]);
+    let mut rate_limiter = RateLimiter::new(&flood, Instant::now());
+
     let client = Client {
         socket_addr: addr,
-        tx: tx,
-        uri: test.unwrap(),
+        tx,
+        uri,
+        route,
+        area_id: subscription.and_then(|s| s.area_id()),
+        // Until a client subscribes itself, it hears the alerts of the area it connected to
+        topics: subscription.into_iter().map(|subscription| Topic { event: EventType::Detection, subscription }).collect(),
+        device_id: None,
+        flooding: false,
     };
 
     let temp_client = client.clone();
-    let all_uri = Uri::from_static("all");
 
-    tx_test.send(MessageAction::OpenConnection(temp_client.uri.to_string(), temp_client.socket_addr.to_string()));
+    queue(MessageAction::OpenConnection(temp_client.uri.to_string(), temp_client.socket_addr.to_string(), temp_client.area_id));
 
     peer_map.lock().await.push(client);
 
-    let (outgoing, incoming) = ws_stream.split();
+    let (outgoing, mut incoming) = ws_stream.split();
+
+    let broadcast_incoming = async {
+        while let Some(msg) = incoming.try_next().await? {
+            // Instrumented instead of entered, the span guard must not be held across the lock
+            let message_span = info_span!("message", kind = Empty, device_uuid = Empty);
+
+            async {
+
+                match msg {
+                    Message::Ping(_ping) => {
+                        debug!("Ping");
+                    },
+                    Message::Pong(_pong) => {
+                        debug!("Pong");
+                    },
+                    Message::Binary(_binary) => {
+                        debug!("Binary");
+                    },
+                    Message::Text(text) => {
+                        debug!(%text, "Text message");
+
+                        // A flood is dropped before it reaches the dashboards or the message handler
+                        match rate_limiter.check(Instant::now()) {
+                            Rate::Allowed => {}
+                            rate => {
+                                dropped.inc();
+
+                                if rate == Rate::Exceeded {
+                                    warn!("Rate limit exceeded, dropping messages");
+                                    queue(MessageAction::Flooding(temp_client.socket_addr));
+
+                                    let message = ErrorMessage {
+                                        error: "Zu viele Nachrichten".to_string(),
+                                    };
+                                    if let Err(err) = temp_client.send_json(&message) {
+                                        warn!(%err, "Could not report rate limit");
+                                    }
+                                }
+
+                                return;
+                            }
+                        }
 
-    let broadcast_incoming = incoming.try_for_each(|msg| {
-        let timestamp = Utc::now();
-        print!("{} - {} - URI: {}: ", timestamp.format("%Y-%m-%d - %H:%M:%S"), temp_client.socket_addr, temp_client.uri);
+                        let peers = peer_map.lock().await;
+                        let ui_recipients: Vec<&Client> = peers.iter().filter(|c| c.route == Route::Ui).collect();
 
-        match msg {
-            Message::Ping(_ping) => {
-                println!("Ping");
-            },
-            Message::Pong(_pong) => {
-                println!("Pong");
-            },
-            Message::Binary(_binary) => {
-                println!("Binary");
-            },
-            Message::Text(text) => {
-                println!("Client: {}, URI: {}, Message: {}", temp_client.socket_addr.clone(), temp_client.uri.clone(), text);
+                        let mes = Message::text(text.clone());
+                        for recipient in ui_recipients {
+                            if let Err(err) = recipient.send(mes.clone()) {
+                                warn!(%err, "Could not forward message to dashboard");
+                            }
+                        }
 
-                let ui_uri = Uri::from_static("/ui");
+                        let mut handled = false;
 
-                let peers = block_on(peer_map.lock());
-                let ui_recipients: Vec<&Client> = peers.iter().filter(|c| (c.uri == ui_uri)).collect();
 
-                let mes = Message::text(text.clone());
-                for recipient in ui_recipients {
-                    recipient.tx.unbounded_send(mes.clone()).unwrap();
-                }
+                        // ---- Device register block
+                        let temp: Result<RegisterMessage, _> = serde_json::from_str(&text);
+                        if let Ok(register) = temp {
+                            message_span.record("kind", "register");
+                            info!(description = %register.description, area = %register.area, "Device registration");
+                            let device = NewDevice {
+                                uuid: Uuid::new_v4(),
+                                hardware_id: register.hardware_id(),
+                                request_id: register.request_id(),
+                                description: register.description,
+                                area: register.area,
+                            };
 
+                            queue(MessageAction::Register((device, temp_client.socket_addr)));
+                            handled = true;
+                        }
 
-                // ---- Device register block
-                let temp: Result<Device, _> = serde_json::from_str(&*text);
-                match temp {
-                    Ok(dev) => {
-                        println!("register code ausführen");
-                        let d = Device {
-                          uuid: Uuid::new_v4(),
-                            ..dev
-                        };
 
-                        tx_test.send(MessageAction::Register((d.clone(), temp_client.socket_addr.clone())));
-                    }
-                    Err(_) => {}
-                }
+                        // ---- Detection message block
+                        let temp: Result<DetectionMessage, _> = serde_json::from_str(&text);
+                        if let Ok(detection_message) = temp {
+                            message_span.record("kind", "detection");
+                            message_span.record("device_uuid", tracing::field::display(detection_message.device.uuid));
+                            info!(source = %detection_message.source, "Detection");
+
+                            // Storing, alerting and notifying depend on the area settings, so the message handler takes care of it
+                            queue(MessageAction::Detection {
+                                message: detection_message,
+                                area_id: temp_client.area_id,
+                                socket_addr: temp_client.socket_addr,
+                                received: Instant::now(),
+                            });
+                            handled = true;
+                        }
 
 
-                // ---- Detection message block
-                let temp: Result<DetectionMessage, _> = serde_json::from_str(&*text);
-                match temp {
-                    Ok(detection_message) => {
-                        println!("Detection");
+                        // ---- Config acknowledgement block
+                        let temp: Result<ConfigAckMessage, _> = serde_json::from_str(&text);
+                        if let Ok(config_ack) = temp {
+                            message_span.record("kind", "config_ack");
+                            message_span.record("device_uuid", tracing::field::display(config_ack.device.uuid));
+                            debug!(version = config_ack.config_version, "Config acknowledgement");
 
-                        tx_test.send(MessageAction::Detection(detection_message));
+                            queue(MessageAction::ConfigAck(config_ack, temp_client.socket_addr));
+                            handled = true;
+                        }
 
-                        let broadcast_recipients: Vec<&Client> = peers.iter().filter(|c| (c.uri == temp_client.uri || c.uri == all_uri)).collect();
 
-                        let alert = Alert {
-                            led: true,
-                            speaker: true,
-                        };
+                        // ---- Telemetry block
+                        let temp: Result<TelemetryMessage, _> = serde_json::from_str(&text);
+                        if let Ok(telemetry_message) = temp {
+                            message_span.record("kind", "telemetry");
+                            message_span.record("device_uuid", tracing::field::display(telemetry_message.device.uuid));
+                            debug!(telemetry = ?telemetry_message.telemetry, "Telemetry");
+
+                            queue(MessageAction::Telemetry(telemetry_message, temp_client.socket_addr));
+                            handled = true;
+                        }
 
-                        let temp_msg = Message::text(serde_json::to_string(&alert).unwrap());
 
-                        for recipient in broadcast_recipients {
-                            recipient.tx.unbounded_send(temp_msg.clone()).unwrap();
+                        // ---- Firmware update status block
+                        let temp: Result<UpdateStatusMessage, _> = serde_json::from_str(&text);
+                        if let Ok(update_status) = temp {
+                            message_span.record("kind", "update_status");
+                            message_span.record("device_uuid", tracing::field::display(update_status.device.uuid));
+                            debug!(rollout_id = update_status.rollout_id, status = ?update_status.status, "Update status");
+
+                            queue(MessageAction::UpdateStatus(update_status, temp_client.socket_addr));
+                            handled = true;
                         }
-                    }
-                    Err(_) => {}
-                }
 
 
-                // ---- UI Messages
-                if text.eq("Get Clients") {
-                    let clients: Vec<&Client> = peers.iter().filter(|_c| true).collect();
+                        // ---- Command response block
+                        let temp: Result<CommandResponseMessage, _> = serde_json::from_str(&text);
+                        if let Ok(response) = temp {
+                            message_span.record("kind", "command_response");
+                            debug!(request_id = %response.request_id, ok = response.ok, "Command response");
 
-                    let mut clients_out: Vec<ClientOut> = Vec::new();
+                            queue(MessageAction::CommandResponse(response, temp_client.socket_addr));
+                            handled = true;
+                        }
 
-                    for client in clients {
-                        clients_out.push(ClientOut {
-                            socket_addr: client.socket_addr.to_string(),
-                            uri: client.uri.to_string(),
-                        });
-                    }
 
-                    let clients_message = Message::text(serde_json::to_string(&clients_out).unwrap());
-                    temp_client.tx.unbounded_send(clients_message).unwrap();
-                }
+                        // ---- Subscription block
+                        let temp: Result<SubscriptionMessage, _> = serde_json::from_str(&text);
+                        if let Ok(subscription_message) = temp {
+                            message_span.record("kind", "subscription");
+                            queue(MessageAction::Subscribe(subscription_message, temp_client.socket_addr));
+                            handled = true;
+                        }
 
 
-                // ---- Add new custom message down below
+                        // ---- UI Messages
+                        if text.eq("Get Clients") {
+                            message_span.record("kind", "get_clients");
+                            let clients_out: Vec<ClientOut> = peers.iter().map(ClientOut::from).collect();
 
-            },
-            Message::Close(_close) => {
-                println!("Close");
-                tx_test.send(MessageAction::CloseConnection(temp_client.uri.to_string(), temp_client.socket_addr.to_string()));
-            },
-            Message::Frame(_frame) => {
-                println!("Frame");
-            }
-        }
+                            if let Err(err) = temp_client.send_json(&clients_out) {
+                                warn!(%err, "Could not send client list");
+                            }
+                            handled = true;
+                        }
 
 
-        future::ok(())
-    });
+                        // ---- Add new custom message down below
+
+
+                        // Plain text like "Connected" is informational, but JSON nobody understood is reported back
+                        if !handled && text.trim_start().starts_with('{') {
+                            let message = ErrorMessage {
+                                error: "Unbekannte Nachricht".to_string(),
+                            };
+
+                            warn!("Unknown JSON message");
+                            if let Err(err) = temp_client.send_json(&message) {
+                                warn!(%err, "Could not report unknown message");
+                            }
+                        }
+                    },
+                    Message::Close(close) => {
+                        debug!(?close, "Close");
+                    },
+                    Message::Frame(_frame) => {
+                        debug!("Frame");
+                    }
+                }
+            }.instrument(message_span.clone()).await;
+        }
+
+        Ok::<(), tungstenite::Error>(())
+    };
 
     let receive_from_others = rx.map(Ok).forward(outgoing);
 
     pin_mut!(broadcast_incoming, receive_from_others);
     future::select(broadcast_incoming, receive_from_others).await;
 
-    println!("{} disconnected", &addr);
-    let temp_addr = addr.clone();
-    let index = peer_map.lock().await.iter().position(|c| c.socket_addr == temp_addr).unwrap();
-    peer_map.lock().await.remove(index);
+    info!("Disconnected");
+    connections.dec();
+    queue(MessageAction::CloseConnection(temp_client.uri.to_string(), temp_client.socket_addr.to_string(), temp_client.area_id));
+
+    let mut peers = peer_map.lock().await;
+    if let Some(index) = peers.iter().position(|c| c.socket_addr == addr) {
+        peers.remove(index);
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
+    init_tracing();
+
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Export(args)) => run_export(args, cli.storage).await,
+        Some(Command::Send(args)) => run_command(args).await,
+        Some(Command::Serve) | None => serve(cli.storage).await,
+    }
+}
 
+/// Opens the storage selected on the command line.
+async fn open_storage(storage: StorageKind) -> Result<Db, Box<dyn std::error::Error>> {
+    match storage {
+        StorageKind::Database => {
+            let database = env::var("DATABASE_URL").expect("DATABASE is not set in .env file");
+            Ok(database::connect(&database).await?)
+        }
+        StorageKind::Memory => Ok(Arc::new(MemoryStorage::new())),
+    }
+}
+
+/// Writes the export to the output file or stdout.
+async fn run_export(args: ExportArgs, storage: StorageKind) -> Result<(), Box<dyn std::error::Error>> {
+    let db = open_storage(storage).await?;
+
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    match args.records {
+        ExportRecords::Detections => write_lines(export::detections(args.filter(), args.format, db.clone()), &mut output).await?,
+        ExportRecords::Incidents => write_lines(export::incidents(args.filter(), args.format, db.clone()), &mut output).await?,
+    }
+
+    output.flush()?;
+    db.close().await;
+
+    Ok(())
+}
+
+/// Writes the lines of an export, a failed query ends it with the error.
+async fn write_lines(lines: impl Stream<Item = alert_net_server::error::Result<String>>, output: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    pin_mut!(lines);
+
+    while let Some(line) = lines.next().await {
+        output.write_all(line?.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Sends the command through the HTTP API of the running server, only it knows the connections.
+async fn run_command(args: CommandArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let server = match args.server {
+        Some(server) => server,
+        None => {
+            let address = env::var("SERVER_ADDRESS").expect("SERVER_ADDRESS is not set in .env file");
+            let port = env::var("HTTP_PORT").unwrap_or_else(|_| "3001".to_string());
+            format!("http://{}:{}", address, port)
+        }
+    };
+
+    let token = env::var("ADMIN_TOKEN").expect("ADMIN_TOKEN is not set in .env file");
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/commands?wait={}", server.trim_end_matches('/'), args.wait))
+        .bearer_auth(token.trim())
+        .json(&serde_json::json!({ "device": args.device, "command": args.command }))
+        .send().await?;
+    let status = response.status();
+    let body: serde_json::Value = response.json().await?;
+
+    if !status.is_success() {
+        return Err(body["error"].as_str().unwrap_or(status.as_str()).into());
+    }
+
+    println!("{}", serde_json::to_string_pretty(&body)?);
+    if status == reqwest::StatusCode::ACCEPTED {
+        eprintln!("No answer within {}s", args.wait);
+    }
+
+    Ok(())
+}
+
+async fn serve(storage: StorageKind) -> Result<(), Box<dyn std::error::Error>> {
     let server_address = env::var("SERVER_ADDRESS").expect("SERVER_ADDRESS is not set in .env file");
     let server_port = env::var("SERVER_PORT").expect("SERVER_PORT is not set in .env file");
-    let database = env::var("DATABASE_URL").expect("DATABASE is not set in .env file");
     let ntfy_url = env::var("NTFY_URL").expect("NTFY URL is not set in .env file");
+    let http_port = env::var("HTTP_PORT").unwrap_or_else(|_| "3001".to_string());
+    let http_address = format!("{}:{}", server_address, http_port);
+    // The devices download firmware from here, SERVER_ADDRESS may be an address they can't reach
+    let public_url = env::var("PUBLIC_URL").unwrap_or_else(|_| format!("http://{}", http_address));
+    let admin_token = env::var("ADMIN_TOKEN").ok().filter(|token| !token.trim().is_empty());
+    if admin_token.is_none() {
+        warn!("ADMIN_TOKEN is not set, the HTTP API rejects every request");
+    }
 
     let mut server_address = server_address;
-    server_address.push_str(":");
-    server_address.push_str(&*server_port);
-
-    let db_url = database.as_str();
-    let pool = PgPoolOptions::new()
-        .min_connections(10)
-        .connect(db_url).await?;
+    server_address.push(':');
+    server_address.push_str(&server_port);
 
-    sqlx::migrate!("./../migrations").run(&pool).await?;
+    let db = open_storage(storage).await?;
+    info!(backend = db.backend(), "Database ready");
 
 
     // ---------- Global used variables
-    let p: Arc<Mutex<Pool<Postgres>>> = Arc::new(Mutex::new(pool));
     let state = PeerMap::new(Mutex::new(Vec::new()));
     let ntfy_dispatcher = Arc::new(
-        Dispatcher::builder(ntfy_url)
+        Dispatcher::builder(&ntfy_url)
             //.credentials(Auth::new("username", "password"))
             .build()?
     );
@@ -243,78 +515,65 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .message("Alert Net server gestartet")
         .priority(Priority::Default);
 
-    let ntfy_result = ntfy_dispatcher.send(&payload).await;
-    println!("NTFY: {:#?}", ntfy_result);
+    if let Err(err) = ntfy_dispatcher.send(&payload).await {
+        warn!(%err, "Could not send startup notification");
+    }
 
 
     // ---------- Internal message handler section
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-    let peer_map = state.clone();
-
-    println!("Starting internal listener");
-    tokio::spawn(async move {
-        loop {
-            let received = rx.recv().await.unwrap();
-
-            match received {
-                MessageAction::Register((device, socket_addr)) => {
-                    println!("Register new device in database");
-
-                    let t = p.lock().await;
-                    let res = device.insert(&t).await;
-                    println!("RES: {:#?}", res);
-                    let dev = res.unwrap();
-
-                    let peers = block_on(peer_map.lock());
-                    let receiver_devices: Vec<&Client > = peers.iter().filter(|c| (c.socket_addr == socket_addr)).collect();
-                    let receiver_device: &Client = receiver_devices.first().unwrap();
-
-                    let clients_message = Message::text(serde_json::to_string(&dev).unwrap());
-                    receiver_device.tx.unbounded_send(clients_message).unwrap();
-                },
-                MessageAction::Detection(detection_message) => {
-                    let pool = p.lock().await;
-                    let result = detection_message.insert(&pool).await;
-
-                    let room = detection_message.device.area;
-                    println!("RES: {:#?}", result);
-
-                    let topic = format!("Alert-Net-{}", &room);
-                    println!("Topic: {}", &topic);
-
-                    let payload = Payload::new(topic)
-                        .title(format!("Bereich {}", room))
-                        .message(format!("Gerät: {}, Auslöser: {}", detection_message.device.description, detection_message.source))
-                        .priority(Priority::Default);
-
-                    let ntfy_result = ntfy_dispatcher.send(&payload).await;
-                    println!("NTFY: {:#?}", ntfy_result);
-                }
-                MessageAction::Test(msg) => {
-                    //println!("Test msg to message handler. MSG: {}", msg);
-                },
-                MessageAction::CloseConnection(uri, socket) => {
-                    let payload = Payload::new("Alert-Net-Status")
-                        .title("Verbindung geschlossen")
-                        .message(format!("Bereich: {}, Adresse: {}", uri, socket))
-                        .priority(Priority::High);
-
-                    let ntfy_result = ntfy_dispatcher.send(&payload).await;
-                    println!("NTFY: {:#?}", ntfy_result);
-                }
-                MessageAction::OpenConnection(uri, socket) => {
-                    let payload = Payload::new("Alert-Net-Status")
-                        .title("Verbindung gestartet")
-                        .message(format!("Bereich: {}, Adresse: {}", uri, socket))
-                        .priority(Priority::Default);
-
-                    let ntfy_result = ntfy_dispatcher.send(&payload).await;
-                    println!("NTFY: {:#?}", ntfy_result);
-                }
-            }
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    info!("Starting internal listener");
+    let require_approval = provisioning::approval_required();
+    if require_approval {
+        info!("Devices require approval");
+    }
+    let thresholds = TelemetryThresholds::from_env();
+    info!(?thresholds, "Telemetry thresholds");
+    let flood = FloodProtection::from_env();
+    info!(?flood, "Flood protection");
+    let commands = CommandLog::default();
+    let handler = MessageHandler::new(db.clone(), state.clone(), ntfy_dispatcher.clone())
+        .require_approval(require_approval)
+        .commands(commands.clone())
+        .telemetry_thresholds(thresholds)
+        .public_url(public_url)
+        .debounce(flood.debounce)
+        .spawn(rx);
+
+
+    // ---------- Background jobs
+    let retention = match RetentionPolicy::from_env()? {
+        Some(policy) => {
+            let interval_hours = policy.interval.as_secs() / 3600;
+            info!(days = policy.keep.num_days(), archive = policy.archive, export_dir = ?policy.export_dir, interval_hours, "Detection retention enabled");
+            Some(policy.spawn(db.clone()))
         }
-    });
+        None => {
+            info!("Detection retention disabled, detections are kept forever");
+            None
+        }
+    };
+
+
+    // ---------- HTTP endpoints (metrics, health)
+    let http_listener = TcpListener::bind(&http_address).await.expect("Failed to bind HTTP listener");
+    let (http_stop_tx, http_stop_rx) = oneshot::channel::<()>();
+    let http_state = HttpState {
+        db: db.clone(),
+        actions: tx.clone(),
+        ntfy_url: ntfy_url.clone(),
+        client: reqwest::Client::new(),
+        commands,
+        admin_token,
+    };
 
+    info!(%http_address, "HTTP listening");
+    let http_server = tokio::spawn(async move {
+        axum::serve(http_listener, http::router(http_state))
+            .with_graceful_shutdown(async move { let _ = http_stop_rx.await; })
+            .await
+    });
 
 
     // ---------- Websocket starting and loop
@@ -322,14 +581,78 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let try_socket = TcpListener::bind(&server_address).await;
     let listener = try_socket.expect("Failed to bind");
 
-    println!("Listening on: {}", server_address);
+    info!(%server_address, "Listening");
+
+    let shutdown = shutdown_signal();
+    pin_mut!(shutdown);
 
     // Let's spawn the handling of each connection in a separate task.
-    while let Ok((stream, socket_address)) = listener.accept().await {
-        tokio::spawn(handle_connection(state.clone(), stream, socket_address, tx.clone()));
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, socket_address)) => {
+                    let span = info_span!(
+                        "connection",
+                        id = CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+                        addr = %socket_address,
+                        route = Empty,
+                        area = Empty,
+                        area_id = Empty,
+                    );
+
+                    tokio::spawn(handle_connection(state.clone(), db.clone(), stream, socket_address, tx.clone(), flood).instrument(span));
+                }
+                Err(err) => warn!(%err, "Failed to accept connection"),
+            },
+            _ = &mut shutdown => break,
+        }
+    }
+
+
+    // ---------- Shutdown
+    info!("Shutting down, no longer accepting connections");
+    drop(listener);
+
+    let (done_tx, done_rx) = oneshot::channel();
+    let _ = tx.send((MessageAction::Stopping(done_tx), Span::current()));
+    let _ = tokio::time::timeout(SHUTDOWN_HANDLER_TIMEOUT, done_rx).await;
+
+    // Wait for the clients to answer the close frame, their disconnects are queued for the handler
+    let disconnected = tokio::time::timeout(SHUTDOWN_PEER_TIMEOUT, async {
+        while !state.lock().await.is_empty() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }).await;
+
+    if disconnected.is_err() {
+        warn!(clients = state.lock().await.len(), "Clients did not close their connection in time");
+    }
+
+    // Queued after every pending detection and notification, so the handler works them off first
+    let (done_tx, done_rx) = oneshot::channel();
+    let _ = tx.send((MessageAction::Shutdown(done_tx), Span::current()));
+
+    match tokio::time::timeout(SHUTDOWN_HANDLER_TIMEOUT, done_rx).await {
+        Ok(_) => info!("Message handler finished"),
+        Err(_) => {
+            warn!("Message handler did not finish in time");
+            handler.abort().await;
+        }
+    }
+
+    let _ = http_stop_tx.send(());
+    match http_server.await {
+        Ok(Err(err)) => warn!(%err, "HTTP server failed"),
+        Err(err) => warn!(%err, "HTTP server task failed"),
+        Ok(Ok(())) => {}
     }
 
+    if let Some(retention) = retention {
+        retention.abort();
+    }
 
+    db.close().await;
+    info!("Alert Net server stopped");
 
     Ok(())
 }
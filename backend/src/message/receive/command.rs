@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::common::models::command::Diagnostics;
+
+/// Answer of a device to a `CommandMessage`, e.g. `{"request_id": "<uuid>", "ok": true, "diagnostics": {...}}`.
+/// A reboot is answered before the device restarts.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct CommandResponseMessage {
+    pub request_id: Uuid,
+    pub ok: bool,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub diagnostics: Option<Diagnostics>,
+}
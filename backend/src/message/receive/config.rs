@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+use crate::message::receive::device::DeviceRef;
+
+/// Sent by a registered device when it connects and after it applied a `ConfigUpdate`,
+/// with the config version it runs (0 before its first update). An older version gets the current config pushed,
+/// firmware updates offered while the device was offline are sent again.
+#[derive(Deserialize, Serialize)]
+pub struct ConfigAckMessage {
+    pub device: DeviceRef,
+    pub config_version: i64,
+    /// Required while `DEVICE_APPROVAL` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
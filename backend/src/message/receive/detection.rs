@@ -1,8 +1,15 @@
 use serde::{Deserialize, Serialize};
-use crate::common::models::device::Device;
+use crate::message::receive::device::DeviceRef;
 
 #[derive(Deserialize, Serialize)]
 pub struct DetectionMessage {
-    pub device: Device,
+    pub device: DeviceRef,
+    /// Kind of sensor like `motion` or `door`, unknown sources are accepted as well. See `SensorKind::of`.
     pub source: String,
+    /// Payload of the sensor, `open` or `closed` for contacts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    /// Issued on approval, required while `DEVICE_APPROVAL` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
 }
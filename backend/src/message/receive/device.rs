@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::common::models::device::Device;
+
+/// How a registered device names itself in its messages. Devices send the whole `Device` they got on registration,
+/// only id, uuid and area are read, the handler takes the rest from the storage.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct DeviceRef {
+    pub id: i64,
+    pub uuid: Uuid,
+    /// Slug or name of the area the device was configured with.
+    pub area: String,
+}
+
+/// A device the storage doesn't know, it is shown by its uuid.
+impl From<DeviceRef> for Device {
+    fn from(device: DeviceRef) -> Self {
+        Device {
+            id: device.id,
+            uuid: device.uuid,
+            description: device.uuid.to_string(),
+            area: device.area,
+            area_id: 0,
+        }
+    }
+}
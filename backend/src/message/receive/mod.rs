@@ -1 +1,8 @@
+pub mod command;
+pub mod config;
+pub mod device;
 pub mod detection;
+pub mod register;
+pub mod subscription;
+pub mod telemetry;
+pub mod update;
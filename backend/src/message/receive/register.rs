@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+/// Sent by a device that has no uuid yet. Devices also send the `id` and `uuid` fields of a `Device`,
+/// they are ignored since the server assigns both.
+///
+/// With a `hardware_id` (MAC or chip id) or a `request_id` the registration is idempotent,
+/// a device that registers again gets the device it was registered as before.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RegisterMessage {
+    pub description: String,
+    pub area: String,
+    #[serde(default)]
+    pub hardware_id: Option<String>,
+    /// Generated by the device once per registration and kept for its retries.
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
+
+impl RegisterMessage {
+    /// The hardware id without surrounding whitespace and in lowercase, `AA:BB` and `aa:bb` are the same device.
+    pub fn hardware_id(&self) -> Option<String> {
+        self.hardware_id.as_deref()
+            .map(|id| id.trim().to_lowercase())
+            .filter(|id| !id.is_empty())
+    }
+
+    pub fn request_id(&self) -> Option<String> {
+        self.request_id.as_deref()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+    }
+}
@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use crate::common::topic::EventType;
+
+/// Sent by clients to change the topics they listen to, e.g.
+/// `{"subscribe": {"areas": ["laden", "zone/eg"], "events": ["detection", "device"]}}`.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriptionMessage {
+    Subscribe(TopicSelection),
+    Unsubscribe(TopicSelection),
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct TopicSelection {
+    /// Area paths as used in connection URIs (`laden`, `zone/eg`, `all`). Empty selects the whole tree.
+    #[serde(default)]
+    pub areas: Vec<String>,
+    #[serde(default = "EventType::all")]
+    pub events: Vec<EventType>,
+}
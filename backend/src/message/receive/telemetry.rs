@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+use crate::common::models::telemetry::TelemetryReport;
+use crate::message::receive::device::DeviceRef;
+
+/// Sent by a registered device periodically, e.g. `{"device": {...}, "telemetry": {"rssi": -67, "uptime_s": 3600}}`.
+#[derive(Deserialize, Serialize)]
+pub struct TelemetryMessage {
+    pub device: DeviceRef,
+    pub telemetry: TelemetryReport,
+    /// Required while `DEVICE_APPROVAL` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
@@ -0,0 +1,17 @@
+use serde::{Deserialize, Serialize};
+use crate::common::models::firmware::UpdateStatus;
+use crate::message::receive::device::DeviceRef;
+
+/// Progress of a firmware update, e.g. `{"device": {...}, "rollout_id": 2, "status": "downloading"}`.
+/// A device reports `succeeded` after it restarted with the new firmware.
+#[derive(Deserialize, Serialize)]
+pub struct UpdateStatusMessage {
+    pub device: DeviceRef,
+    pub rollout_id: i64,
+    pub status: UpdateStatus,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// Required while `DEVICE_APPROVAL` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
@@ -0,0 +1,12 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::common::models::command::CommandKind;
+
+/// Asks a device to do something, e.g. `{"type": "command", "request_id": "<uuid>", "command": "diagnostics"}`.
+/// The device answers with a `CommandResponseMessage` carrying the same request id.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename = "command")]
+pub struct CommandMessage {
+    pub request_id: Uuid,
+    pub command: CommandKind,
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use crate::common::models::config::DeviceConfig;
+
+/// Pushed to a device when it connects with an older config version and after each edit, e.g.
+/// `{"type": "config_update", "version": 3, "description": "Tür", "area": "laden", "speaker_volume": 20}`.
+/// Settings that are missing are left to the device.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename = "config_update")]
+pub struct ConfigUpdate {
+    pub version: i64,
+    pub description: String,
+    pub area: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speaker_volume: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub motion_debounce_ms: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat_interval_s: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_address: Option<String>,
+}
+
+impl From<&DeviceConfig> for ConfigUpdate {
+    fn from(config: &DeviceConfig) -> Self {
+        ConfigUpdate {
+            version: config.version,
+            description: config.description.clone(),
+            area: config.area.clone(),
+            speaker_volume: config.speaker_volume,
+            motion_debounce_ms: config.motion_debounce_ms,
+            heartbeat_interval_s: config.heartbeat_interval_s,
+            server_address: config.server_address.clone(),
+        }
+    }
+}
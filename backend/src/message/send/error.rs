@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+/// Reported back to a client when one of its messages couldn't be processed.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ErrorMessage {
+    pub error: String,
+}
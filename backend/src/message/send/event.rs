@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use crate::common::models::command::CommandRecord;
+use crate::common::models::correlation::PreAlarm;
+use crate::common::models::device::PendingDevice;
+use crate::common::models::firmware::Rollout;
+use crate::common::models::telemetry::Telemetry;
+use crate::common::topic::Topic;
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    Status {
+        message: String,
+    },
+    Device {
+        online: bool,
+        uri: String,
+        socket_addr: String,
+        area_id: Option<i64>,
+    },
+    /// A device waits for approval.
+    Pending {
+        device: PendingDevice,
+    },
+    /// A command was sent to a device or answered by it.
+    Command {
+        record: CommandRecord,
+    },
+    /// A rollout started or advanced, or one of its devices reported progress.
+    Rollout {
+        rollout: Rollout,
+    },
+    /// A detection that didn't satisfy the correlation rule of its area.
+    PreAlarm {
+        pre_alarm: PreAlarm,
+    },
+    /// A device reported its state.
+    Telemetry {
+        telemetry: Telemetry,
+    },
+}
+
+/// Confirms the topics of a client after it changed its subscriptions.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Subscriptions {
+    pub subscriptions: Vec<Topic>,
+}
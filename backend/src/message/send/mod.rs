@@ -1 +1,7 @@
-pub mod alert;
\ No newline at end of file
+pub mod alert;
+pub mod command;
+pub mod config;
+pub mod error;
+pub mod event;
+pub mod provisioning;
+pub mod update;
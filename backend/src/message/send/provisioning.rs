@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use crate::common::models::device::Device;
+
+/// Answer to a registration while approval is required, see `DEVICE_APPROVAL`.
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ProvisioningMessage {
+    /// The device waits for an admin, it shows the code on its serial console.
+    Pending {
+        pairing_code: String,
+    },
+    /// Sent on approval or to a registration of an approved device. The token is only sent once,
+    /// the device keeps it and sends it with each detection.
+    Approved {
+        #[serde(flatten)]
+        device: Device,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+}
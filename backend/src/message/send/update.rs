@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use crate::common::models::firmware::UpdateOffer;
+
+/// Offers a firmware update to a device, e.g. `{"type": "update_available", "rollout_id": 2, "version": "1.1.0",
+/// "url": "http://192.168.0.88:3001/api/firmware/4/image", "md5": "...", ...}`.
+/// The device reports its progress with an `UpdateStatusMessage`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "type", rename = "update_available")]
+pub struct UpdateAvailable {
+    pub rollout_id: i64,
+    pub firmware_id: i64,
+    pub version: String,
+    pub board: String,
+    pub url: String,
+    pub size: i64,
+    pub sha256: String,
+    pub md5: String,
+}
+
+impl UpdateAvailable {
+    /// `public_url` is where the devices reach the HTTP endpoints of the server.
+    pub fn new(offer: &UpdateOffer, public_url: &str) -> Self {
+        let firmware = &offer.firmware;
+
+        UpdateAvailable {
+            rollout_id: offer.rollout_id,
+            firmware_id: firmware.id,
+            version: firmware.version.clone(),
+            board: firmware.board.clone(),
+            url: format!("{}/api/firmware/{}/image", public_url.trim_end_matches('/'), firmware.id),
+            size: firmware.size,
+            sha256: firmware.sha256.clone(),
+            md5: firmware.md5.clone(),
+        }
+    }
+}
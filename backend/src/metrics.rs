@@ -0,0 +1,115 @@
+use std::sync::LazyLock;
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Area label for anything that isn't a known area. Labels never take values from messages,
+/// so a misbehaving client can't create new series.
+pub const UNKNOWN_AREA: &str = "unknown";
+
+pub struct Metrics {
+    registry: Registry,
+    /// Open WebSocket connections by area and route kind.
+    pub connections: IntGaugeVec,
+    /// Received detections by area and sensor kind.
+    pub detections: IntCounterVec,
+    /// Repeated detections counted on the detection of their debounce window, by area.
+    pub detections_coalesced: IntCounterVec,
+    /// Messages dropped by the rate limit of their connection, by area.
+    pub messages_dropped: IntCounterVec,
+    /// Detections short of the correlation rule of their area, by area.
+    pub pre_alarms: IntCounterVec,
+    /// Alerts published to sirens and observers by area.
+    pub alerts_sent: IntCounterVec,
+    /// Failed ntfy notifications.
+    pub ntfy_failures: IntCounter,
+    /// Duration of database inserts by table.
+    pub db_insert_seconds: HistogramVec,
+    /// Time from receiving a detection until its alert is published.
+    pub detection_to_alert_seconds: Histogram,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let connections = IntGaugeVec::new(
+            Opts::new("alert_net_connections", "Open WebSocket connections"),
+            &["area", "route"],
+        ).expect("valid metric");
+
+        let detections = IntCounterVec::new(
+            Opts::new("alert_net_detections_total", "Received detections"),
+            &["area", "kind"],
+        ).expect("valid metric");
+
+        let detections_coalesced = IntCounterVec::new(
+            Opts::new("alert_net_detections_coalesced_total", "Repeated detections counted on the detection of their debounce window"),
+            &["area"],
+        ).expect("valid metric");
+
+        let messages_dropped = IntCounterVec::new(
+            Opts::new("alert_net_messages_dropped_total", "Messages dropped by the rate limit of their connection"),
+            &["area"],
+        ).expect("valid metric");
+
+        let pre_alarms = IntCounterVec::new(
+            Opts::new("alert_net_pre_alarms_total", "Detections short of the correlation rule of their area"),
+            &["area"],
+        ).expect("valid metric");
+
+        let alerts_sent = IntCounterVec::new(
+            Opts::new("alert_net_alerts_sent_total", "Alerts published to sirens and observers"),
+            &["area"],
+        ).expect("valid metric");
+
+        let ntfy_failures = IntCounter::new(
+            "alert_net_ntfy_failures_total",
+            "Failed ntfy notifications",
+        ).expect("valid metric");
+
+        let db_insert_seconds = HistogramVec::new(
+            HistogramOpts::new("alert_net_db_insert_seconds", "Duration of database inserts")
+                .buckets(vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]),
+            &["table"],
+        ).expect("valid metric");
+
+        let detection_to_alert_seconds = Histogram::with_opts(
+            HistogramOpts::new("alert_net_detection_to_alert_seconds", "Time from receiving a detection until its alert is published")
+                .buckets(vec![0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+        ).expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(connections.clone())).expect("metric registered once");
+        registry.register(Box::new(detections.clone())).expect("metric registered once");
+        registry.register(Box::new(detections_coalesced.clone())).expect("metric registered once");
+        registry.register(Box::new(messages_dropped.clone())).expect("metric registered once");
+        registry.register(Box::new(pre_alarms.clone())).expect("metric registered once");
+        registry.register(Box::new(alerts_sent.clone())).expect("metric registered once");
+        registry.register(Box::new(ntfy_failures.clone())).expect("metric registered once");
+        registry.register(Box::new(db_insert_seconds.clone())).expect("metric registered once");
+        registry.register(Box::new(detection_to_alert_seconds.clone())).expect("metric registered once");
+
+        Metrics {
+            registry,
+            connections,
+            detections,
+            detections_coalesced,
+            messages_dropped,
+            pre_alarms,
+            alerts_sent,
+            ntfy_failures,
+            db_insert_seconds,
+            detection_to_alert_seconds,
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!(%err, "Could not encode metrics");
+        }
+
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
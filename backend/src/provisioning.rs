@@ -0,0 +1,25 @@
+use std::env;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Reads `DEVICE_APPROVAL`. With `true` unknown devices wait for an admin to approve them
+/// and detections have to carry the token issued on approval.
+pub fn approval_required() -> bool {
+    env::var("DEVICE_APPROVAL").is_ok_and(|value| value == "true")
+}
+
+/// A random token for an approved device, 64 hex characters.
+pub fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Only the hash of a token is stored, a leaked database doesn't leak the tokens.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Six digits the device prints on its serial console, so the admin can tell which request belongs to it.
+pub fn pairing_code() -> String {
+    format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000)
+}
+
@@ -0,0 +1,171 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use chrono::{DateTime, TimeDelta, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tokio::task::JoinHandle;
+use tracing::{error, info, info_span, Instrument};
+use crate::common::models::detection::Detection;
+use crate::database::{ArchiveSink, Db, Storage};
+use crate::error::{Error, Result};
+
+/// How long raw detections are kept and what happens to older ones.
+/// Their daily counts are kept forever.
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+    pub keep: TimeDelta,
+    /// Move old detections to the archive table instead of deleting them.
+    pub archive: bool,
+    /// Export old detections to a compressed JSONL file in this directory before they are removed.
+    pub export_dir: Option<PathBuf>,
+    pub interval: Duration,
+}
+
+impl RetentionPolicy {
+    /// Reads `RETENTION_DAYS`, `RETENTION_ARCHIVE`, `RETENTION_EXPORT_DIR` and `RETENTION_INTERVAL_HOURS`.
+    /// Without `RETENTION_DAYS` detections are kept forever.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(days) = env::var("RETENTION_DAYS") else {
+            return Ok(None);
+        };
+        // 0 would prune everything before today, a negative value the whole table
+        let days = positive("RETENTION_DAYS", &days)?;
+        let hours = match env::var("RETENTION_INTERVAL_HOURS") {
+            Ok(hours) => positive("RETENTION_INTERVAL_HOURS", &hours)?,
+            Err(_) => 24,
+        };
+
+        Ok(Some(RetentionPolicy {
+            keep: TimeDelta::days(days),
+            archive: env::var("RETENTION_ARCHIVE").is_ok_and(|value| value == "true"),
+            export_dir: env::var("RETENTION_EXPORT_DIR").ok().map(PathBuf::from),
+            interval: Duration::from_secs(hours as u64 * 60 * 60),
+        }))
+    }
+
+    /// Detections before the start of this day are pruned.
+    pub fn cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let cutoff = now - self.keep;
+        cutoff.date_naive().and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()
+    }
+
+    /// Runs the retention job in the background, first right away and then every interval.
+    pub fn spawn(self, db: Db) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(err) = self.prune(db.as_ref()).instrument(info_span!("retention")).await {
+                    error!(%err, "Retention run failed");
+                }
+            }
+        })
+    }
+
+    /// Exports, aggregates and removes the detections before the cutoff in one transaction,
+    /// so a failed export leaves them in place.
+    pub async fn prune(&self, db: &dyn Storage) -> Result<u64> {
+        let cutoff = self.cutoff(Utc::now());
+
+        let mut archive = match &self.export_dir {
+            Some(dir) => Some(GzArchive::create(dir, cutoff)?),
+            None => None,
+        };
+
+        let sink = archive.as_mut().map(|archive| archive as &mut dyn ArchiveSink);
+        let pruned = db.prune_detections(cutoff, self.archive, sink).await;
+
+        // The archive only gets its name once the removal is committed, a failed commit would export the detections again
+        match (&pruned, &archive) {
+            (Err(_), Some(archive)) => archive.discard(),
+            (Ok(_), Some(archive)) => archive.publish()?,
+            _ => {}
+        }
+
+        let pruned = pruned?;
+        info!(%cutoff, removed = pruned.removed, days = pruned.days, archived = self.archive, "Old detections pruned");
+
+        Ok(pruned.removed)
+    }
+}
+
+fn positive(name: &str, value: &str) -> Result<i64> {
+    match value.trim().parse() {
+        Ok(number) if number >= 1 => Ok(number),
+        Ok(_) => Err(Error::InvalidConfig(format!("{} must be at least 1", name))),
+        Err(err) => Err(Error::InvalidConfig(format!("{} is not a number: {}", name, err))),
+    }
+}
+
+/// Writes the pruned detections as JSON lines into a gzip file. It is written under a temporary name
+/// and only renamed once the removal is committed.
+struct GzArchive {
+    encoder: Option<GzEncoder<BufWriter<File>>>,
+    partial: PathBuf,
+    path: PathBuf,
+    exported: u64,
+}
+
+impl GzArchive {
+    fn create(dir: &Path, cutoff: DateTime<Utc>) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let name = format!("detections-before-{}-{}.jsonl.gz", cutoff.format("%Y-%m-%d"), Utc::now().timestamp());
+        let partial = dir.join(format!("{}.part", name));
+        let encoder = GzEncoder::new(BufWriter::new(File::create(&partial)?), Compression::default());
+
+        Ok(GzArchive {
+            encoder: Some(encoder),
+            partial,
+            path: dir.join(name),
+            exported: 0,
+        })
+    }
+
+    fn discard(&self) {
+        let _ = fs::remove_file(&self.partial);
+    }
+
+    /// Renames the finished archive, an empty one is removed.
+    fn publish(&self) -> io::Result<()> {
+        if self.exported == 0 {
+            return fs::remove_file(&self.partial);
+        }
+
+        fs::rename(&self.partial, &self.path).inspect_err(|err| {
+            // The detections are already removed, the temporary file is all that is left of them
+            error!(%err, partial = %self.partial.display(), "Could not rename the archive");
+        })?;
+        info!(path = %self.path.display(), exported = self.exported, "Old detections exported");
+
+        Ok(())
+    }
+}
+
+impl ArchiveSink for GzArchive {
+    fn write(&mut self, detection: &Detection) -> io::Result<()> {
+        let encoder = self.encoder.as_mut().ok_or_else(|| io::Error::other("Archive already finished"))?;
+
+        serde_json::to_writer(&mut *encoder, detection)?;
+        encoder.write_all(b"\n")?;
+        self.exported += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // Synced, so the archive is complete on disk before the detections are removed
+        if let Some(encoder) = self.encoder.take() {
+            let mut file = encoder.finish()?;
+            file.flush()?;
+            file.get_ref().sync_all()?;
+        }
+
+        Ok(())
+    }
+}
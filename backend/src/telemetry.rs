@@ -0,0 +1,97 @@
+use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+use crate::common::models::telemetry::Telemetry;
+
+/// Lower limits for the telemetry of the devices. A device that drops below one is reported once,
+/// again only after the value recovered in between.
+#[derive(Clone, Copy, Debug)]
+pub struct TelemetryThresholds {
+    /// In dBm.
+    pub min_rssi: Option<i32>,
+    /// In bytes.
+    pub min_free_heap: Option<i64>,
+    /// In volts.
+    pub min_supply_voltage: Option<f64>,
+}
+
+impl Default for TelemetryThresholds {
+    fn default() -> Self {
+        TelemetryThresholds {
+            min_rssi: Some(-80),
+            min_free_heap: Some(8192),
+            min_supply_voltage: None,
+        }
+    }
+}
+
+impl TelemetryThresholds {
+    /// Reads `TELEMETRY_MIN_RSSI`, `TELEMETRY_MIN_FREE_HEAP` and `TELEMETRY_MIN_VOLTAGE`.
+    /// Unset ones keep their default, an empty value disables the check.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+
+        TelemetryThresholds {
+            min_rssi: threshold("TELEMETRY_MIN_RSSI", defaults.min_rssi),
+            min_free_heap: threshold("TELEMETRY_MIN_FREE_HEAP", defaults.min_free_heap),
+            min_supply_voltage: threshold("TELEMETRY_MIN_VOLTAGE", defaults.min_supply_voltage),
+        }
+    }
+
+    /// The values of `current` that dropped below their threshold since `previous`, as text for the notification.
+    pub fn crossed(&self, previous: Option<&Telemetry>, current: &Telemetry) -> Vec<String> {
+        let previous_below = |check: fn(&Self, &Telemetry) -> bool| previous.is_some_and(|previous| check(self, previous));
+        let mut crossed = Vec::new();
+
+        if self.weak_signal(current) && !previous_below(Self::weak_signal) {
+            crossed.push(format!("Schwaches WLAN-Signal: {} dBm", current.rssi.unwrap_or_default()));
+        }
+        if self.low_heap(current) && !previous_below(Self::low_heap) {
+            crossed.push(format!("Wenig freier Speicher: {} Bytes", current.free_heap.unwrap_or_default()));
+        }
+        if self.low_voltage(current) && !previous_below(Self::low_voltage) {
+            crossed.push(format!("Niedrige Versorgungsspannung: {:.2} V", current.supply_voltage.unwrap_or_default()));
+        }
+
+        crossed
+    }
+
+    fn weak_signal(&self, telemetry: &Telemetry) -> bool {
+        below(telemetry.rssi, self.min_rssi)
+    }
+
+    fn low_heap(&self, telemetry: &Telemetry) -> bool {
+        below(telemetry.free_heap, self.min_free_heap)
+    }
+
+    fn low_voltage(&self, telemetry: &Telemetry) -> bool {
+        below(telemetry.supply_voltage, self.min_supply_voltage)
+    }
+}
+
+/// The reset reason if the device restarted since `previous` because of a crash, a watchdog or an exception.
+/// A restart is recognized by the uptime going down.
+pub fn crash(previous: Option<&Telemetry>, current: &Telemetry) -> Option<String> {
+    let restarted = matches!((previous.and_then(|p| p.uptime_s), current.uptime_s), (Some(before), Some(now)) if now < before);
+    let reason = current.reset_reason.as_deref()?;
+    let lowercase = reason.to_lowercase();
+
+    let crashed = ["watchdog", "wdt", "exception", "panic"].iter().any(|cause| lowercase.contains(cause));
+    (restarted && crashed).then(|| reason.to_string())
+}
+
+fn below<T: PartialOrd>(value: Option<T>, min: Option<T>) -> bool {
+    matches!((value, min), (Some(value), Some(min)) if value < min)
+}
+
+/// Reads a limit from the environment, an empty value disables it.
+pub(crate) fn threshold<T: FromStr>(name: &str, default: Option<T>) -> Option<T>
+where
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) if value.trim().is_empty() => None,
+        Ok(value) => Some(value.trim().parse().unwrap_or_else(|err| panic!("{} is not a number: {}", name, err))),
+        Err(_) => default,
+    }
+}
@@ -0,0 +1,154 @@
+// Each test binary uses a part of the helpers
+#![allow(dead_code)]
+
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use sqlx::{Connection, PgConnection};
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Storage the server runs with, every scenario runs against each of them.
+#[derive(Clone, Copy, Debug)]
+pub enum Backend {
+    Memory,
+    /// A temporary database file, removed with the server.
+    Sqlite,
+    /// A temporary database on the server of `TEST_DATABASE_URL`, dropped with the server.
+    /// Skipped without `TEST_DATABASE_URL`, so the checked Postgres queries only run where a server is at hand.
+    Postgres,
+}
+
+impl Backend {
+    /// As reported by `/readyz`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Memory => "memory",
+            Backend::Sqlite => "sqlite",
+            Backend::Postgres => "postgres",
+        }
+    }
+
+    pub fn available(&self) -> bool {
+        match self {
+            Backend::Postgres => postgres_url().is_some(),
+            _ => true,
+        }
+    }
+}
+
+/// A Postgres server the tests may create databases on, e.g. `postgres://postgres@localhost:5432/postgres`.
+fn postgres_url() -> Option<String> {
+    std::env::var("TEST_DATABASE_URL").ok().filter(|url| !url.trim().is_empty())
+}
+
+/// Database of one test, removed when dropped. `None` for the memory backend.
+pub struct TempDatabase {
+    kind: TempKind,
+}
+
+enum TempKind {
+    File(PathBuf),
+    Postgres {
+        /// The database the test database was created from.
+        server: String,
+        name: String,
+    },
+}
+
+impl TempDatabase {
+    pub async fn create(backend: Backend) -> Option<TempDatabase> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!("alert-net-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        match backend {
+            Backend::Memory => None,
+            Backend::Sqlite => Some(TempDatabase { kind: TempKind::File(std::env::temp_dir().join(format!("{}.db", name))) }),
+            Backend::Postgres => {
+                let server = postgres_url().expect("TEST_DATABASE_URL is not set");
+                let name = name.replace('-', "_");
+
+                let mut con = PgConnection::connect(&server).await.expect("TEST_DATABASE_URL should be reachable");
+                sqlx::query(&format!("CREATE DATABASE {}", name)).execute(&mut con).await.unwrap();
+                con.close().await.unwrap();
+
+                Some(TempDatabase { kind: TempKind::Postgres { server, name } })
+            }
+        }
+    }
+
+    pub fn url(&self) -> String {
+        match &self.kind {
+            TempKind::File(path) => format!("sqlite://{}", path.display()),
+            TempKind::Postgres { server, name } => {
+                // Same server and credentials, only the database differs
+                let (base, query) = server.split_once('?').map_or((server.as_str(), None), |(base, query)| (base, Some(query)));
+                let (host, _) = base.rsplit_once('/').expect("TEST_DATABASE_URL should name a database");
+
+                match query {
+                    Some(query) => format!("{}/{}?{}", host, name, query),
+                    None => format!("{}/{}", host, name),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        match &self.kind {
+            TempKind::File(path) => {
+                for suffix in ["", "-wal", "-shm"] {
+                    let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+                }
+            }
+            TempKind::Postgres { server, name } => {
+                // Drop can't await, and the runtime of the test may already be shutting down
+                let statement = format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", name);
+                let server = server.clone();
+                let dropped = std::thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+                    runtime.block_on(async {
+                        let mut con = PgConnection::connect(&server).await?;
+                        sqlx::query(&statement).execute(&mut con).await?;
+                        con.close().await
+                    })
+                }).join();
+
+                if !matches!(dropped, Ok(Ok(()))) {
+                    eprintln!("Could not drop the test database {}", name);
+                }
+            }
+        }
+    }
+}
+
+/// Runs each scenario once per backend, as `<scenario>::memory`, `<scenario>::sqlite` and `<scenario>::postgres`.
+macro_rules! scenarios {
+    ($($scenario:ident),* $(,)?) => {
+        $(
+            mod $scenario {
+                #[tokio::test]
+                async fn memory() {
+                    super::$scenario(super::Backend::Memory).await;
+                }
+
+                #[tokio::test]
+                async fn sqlite() {
+                    super::$scenario(super::Backend::Sqlite).await;
+                }
+
+                #[tokio::test]
+                async fn postgres() {
+                    if !super::Backend::Postgres.available() {
+                        eprintln!("TEST_DATABASE_URL is not set, skipped");
+                        return;
+                    }
+                    super::$scenario(super::Backend::Postgres).await;
+                }
+            }
+        )*
+    };
+}
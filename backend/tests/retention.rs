@@ -0,0 +1,257 @@
+#[macro_use]
+mod common;
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use chrono::{TimeDelta, Utc};
+use flate2::read::GzDecoder;
+use futures_util::StreamExt;
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqliteConnectOptions;
+use uuid::Uuid;
+use alert_net_server::common::models::detection::{Detection, NewDetection};
+use alert_net_server::common::models::device::NewDevice;
+use alert_net_server::common::models::history::{AreaCount, Bucket, DetectionFilter, PageRequest};
+use alert_net_server::database::{self, ArchiveSink, Db};
+use alert_net_server::database::memory::MemoryStorage;
+use alert_net_server::export::{self, ExportFormat};
+use alert_net_server::retention::RetentionPolicy;
+use common::{Backend, TempDatabase};
+
+scenarios!(
+    old_detections_are_removed_and_counted,
+    old_detections_are_moved_to_the_archive,
+    old_detections_are_exported_before_removal,
+    failing_export_keeps_the_detections,
+);
+
+/// Storage of one test with three detections from ten days ago and one from now.
+struct Fixture {
+    db: Db,
+    old: Vec<i64>,
+    recent: i64,
+    /// Removed with the fixture.
+    database: Option<TempDatabase>,
+}
+
+impl Fixture {
+    async fn create(backend: Backend) -> Fixture {
+        let database = TempDatabase::create(backend).await;
+        let db: Db = match &database {
+            Some(database) => database::connect(&database.url()).await.unwrap(),
+            None => Arc::new(MemoryStorage::new()),
+        };
+
+        let device = db.register_device(&NewDevice {
+            uuid: Uuid::new_v4(),
+            description: "Tür".to_string(),
+            area: "laden".to_string(),
+            hardware_id: None,
+            request_id: None,
+        }).await.unwrap().device;
+
+        let long_ago = Utc::now() - TimeDelta::days(10);
+        let mut ids = Vec::new();
+        for (source, timestamp) in [("motion", long_ago), ("motion", long_ago), ("door", long_ago), ("motion", Utc::now())] {
+            let detection = NewDetection { device_id: device.id, source: source.to_string(), state: None, timestamp };
+            ids.push(db.insert_detection(&detection).await.unwrap().id);
+        }
+        let recent = ids.pop().unwrap();
+
+        Fixture { db, old: ids, recent, database }
+    }
+
+    async fn stored(&self) -> Vec<i64> {
+        let page = self.db.detection_history(&DetectionFilter::default(), PageRequest::default()).await.unwrap();
+        let mut ids: Vec<i64> = page.detections.iter().map(|detection| detection.id).collect();
+        ids.sort();
+
+        ids
+    }
+
+    async fn daily_counts(&self) -> Vec<AreaCount> {
+        let mut counts = self.db.detection_counts(&DetectionFilter::default(), Bucket::Day).await.unwrap();
+        counts.sort_by_key(|count| count.bucket);
+
+        counts
+    }
+}
+
+fn keep_three_days() -> RetentionPolicy {
+    RetentionPolicy {
+        keep: TimeDelta::days(3),
+        archive: false,
+        export_dir: None,
+        interval: Duration::from_secs(60 * 60),
+    }
+}
+
+/// Directory for the exports of one test, removed when dropped.
+struct ExportDir {
+    path: PathBuf,
+}
+
+impl ExportDir {
+    fn new() -> ExportDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!("alert-net-export-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        ExportDir { path: std::env::temp_dir().join(name) }
+    }
+
+    fn files(&self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.path) else { return Vec::new() };
+
+        entries.map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect()
+    }
+}
+
+impl Drop for ExportDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+async fn old_detections_are_removed_and_counted(backend: Backend) {
+    let fixture = Fixture::create(backend).await;
+
+    let removed = keep_three_days().prune(fixture.db.as_ref()).await.unwrap();
+    assert_eq!(removed, 3);
+    assert_eq!(fixture.stored().await, [fixture.recent]);
+
+    // The removed detections are still counted, by the day they happened
+    let counts: Vec<i64> = fixture.daily_counts().await.iter().map(|count| count.count).collect();
+    assert_eq!(counts, [3, 1]);
+
+    // A second run finds nothing and doesn't count them again
+    assert_eq!(keep_three_days().prune(fixture.db.as_ref()).await.unwrap(), 0);
+    let counts: Vec<i64> = fixture.daily_counts().await.iter().map(|count| count.count).collect();
+    assert_eq!(counts, [3, 1]);
+}
+
+async fn old_detections_are_moved_to_the_archive(backend: Backend) {
+    let fixture = Fixture::create(backend).await;
+
+    let policy = RetentionPolicy { archive: true, ..keep_three_days() };
+    assert_eq!(policy.prune(fixture.db.as_ref()).await.unwrap(), 3);
+    assert_eq!(fixture.stored().await, [fixture.recent]);
+
+    // The export still finds the archived detections
+    let lines: Vec<String> = export::detections(DetectionFilter::default(), ExportFormat::Jsonl, fixture.db.clone())
+        .map(|line| line.unwrap())
+        .collect().await;
+    let mut exported: Vec<i64> = lines.iter()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].as_i64().unwrap())
+        .collect();
+    exported.sort();
+    assert_eq!(exported, [fixture.old.clone(), vec![fixture.recent]].concat());
+}
+
+async fn old_detections_are_exported_before_removal(backend: Backend) {
+    let fixture = Fixture::create(backend).await;
+    let dir = ExportDir::new();
+
+    let policy = RetentionPolicy { export_dir: Some(dir.path.clone()), ..keep_three_days() };
+    assert_eq!(policy.prune(fixture.db.as_ref()).await.unwrap(), 3);
+    assert_eq!(fixture.stored().await, [fixture.recent]);
+
+    // Only the finished archive is left, under its final name
+    let files = dir.files();
+    assert_eq!(files.len(), 1, "{:?}", files);
+    assert!(files[0].starts_with("detections-before-") && files[0].ends_with(".jsonl.gz"), "{:?}", files);
+
+    let archive = BufReader::new(GzDecoder::new(File::open(dir.path.join(&files[0])).unwrap()));
+    let exported: Vec<Detection> = archive.lines().map(|line| serde_json::from_str(&line.unwrap()).unwrap()).collect();
+    assert_eq!(exported.iter().map(|detection| detection.id).collect::<Vec<_>>(), fixture.old);
+    assert_eq!(exported[2].source, "door");
+
+    // A run without old detections leaves no empty archive behind
+    assert_eq!(policy.prune(fixture.db.as_ref()).await.unwrap(), 0);
+    assert_eq!(dir.files().len(), 1);
+}
+
+/// Accepts the detections, but can't complete the export.
+struct FailingSink {
+    written: usize,
+}
+
+impl ArchiveSink for FailingSink {
+    fn write(&mut self, _detection: &Detection) -> io::Result<()> {
+        self.written += 1;
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Err(io::Error::other("disk full"))
+    }
+}
+
+async fn failing_export_keeps_the_detections(backend: Backend) {
+    let fixture = Fixture::create(backend).await;
+    let counts_before: Vec<i64> = fixture.daily_counts().await.iter().map(|count| count.count).collect();
+
+    let mut sink = FailingSink { written: 0 };
+    let cutoff = keep_three_days().cutoff(Utc::now());
+    assert!(fixture.db.prune_detections(cutoff, true, Some(&mut sink)).await.is_err());
+    assert_eq!(sink.written, 3);
+
+    // Neither removed nor counted twice
+    assert_eq!(fixture.stored().await, [fixture.old.clone(), vec![fixture.recent]].concat());
+    let counts: Vec<i64> = fixture.daily_counts().await.iter().map(|count| count.count).collect();
+    assert_eq!(counts, counts_before);
+
+    // Nor when the export directory can't be created
+    let dir = ExportDir::new();
+    fs::write(&dir.path, "").unwrap();
+    let policy = RetentionPolicy { export_dir: Some(dir.path.join("archive")), ..keep_three_days() };
+    assert!(policy.prune(fixture.db.as_ref()).await.is_err());
+    fs::remove_file(&dir.path).unwrap();
+    assert_eq!(fixture.stored().await.len(), 4);
+}
+
+#[tokio::test]
+async fn archive_is_only_published_after_the_removal_is_committed() {
+    let fixture = Fixture::create(Backend::Sqlite).await;
+    let dir = ExportDir::new();
+
+    // Breaks the run after the export is written, when the detections are moved
+    let url = fixture.database.as_ref().unwrap().url();
+    let pool = SqlitePool::connect_with(SqliteConnectOptions::from_str(&url).unwrap()).await.unwrap();
+    sqlx::query("DROP TABLE detection_archive").execute(&pool).await.unwrap();
+    pool.close().await;
+
+    let policy = RetentionPolicy { archive: true, export_dir: Some(dir.path.clone()), ..keep_three_days() };
+    assert!(policy.prune(fixture.db.as_ref()).await.is_err());
+
+    // Neither the archive nor its temporary file is left, the next run exports the detections again
+    assert_eq!(dir.files(), Vec::<String>::new());
+    assert_eq!(fixture.stored().await.len(), 4);
+}
+
+#[test]
+fn policy_rejects_invalid_settings() {
+    // The only test of this binary that reads the environment
+    std::env::remove_var("RETENTION_DAYS");
+    assert!(RetentionPolicy::from_env().unwrap().is_none());
+
+    std::env::set_var("RETENTION_DAYS", "30");
+    std::env::set_var("RETENTION_INTERVAL_HOURS", "6");
+    let policy = RetentionPolicy::from_env().unwrap().unwrap();
+    assert_eq!(policy.keep, TimeDelta::days(30));
+    assert_eq!(policy.interval, Duration::from_secs(6 * 60 * 60));
+
+    for (days, hours) in [("0", "6"), ("-1", "6"), ("dreißig", "6"), ("30", "0"), ("30", "sechs")] {
+        std::env::set_var("RETENTION_DAYS", days);
+        std::env::set_var("RETENTION_INTERVAL_HOURS", hours);
+        assert!(RetentionPolicy::from_env().is_err(), "{} days every {} hours", days, hours);
+    }
+
+    std::env::remove_var("RETENTION_DAYS");
+    std::env::remove_var("RETENTION_INTERVAL_HOURS");
+}
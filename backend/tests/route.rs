@@ -0,0 +1,139 @@
+use alert_net_server::common::models::area::{Area, ZoneKind};
+use alert_net_server::common::route::Route;
+use alert_net_server::common::topic::{EventType, Topic};
+use alert_net_server::common::zone::{Subscription, ZoneTree};
+
+fn area(id: i64, slug: &str, parent_id: Option<i64>, kind: ZoneKind, propagate: bool) -> Area {
+    Area {
+        id,
+        slug: slug.to_string(),
+        name: slug.to_string(),
+        parent_id,
+        armed: true,
+        notify: true,
+        notification_topic: None,
+        notification_priority: 3,
+        kind,
+        propagate,
+    }
+}
+
+/// haus > eg > laden (propagates to eg), eg > kino (doesn't propagate)
+fn zones() -> ZoneTree {
+    ZoneTree::new(vec![
+        area(1, "haus", None, ZoneKind::Building, false),
+        area(2, "eg", Some(1), ZoneKind::Floor, false),
+        area(3, "laden", Some(2), ZoneKind::Room, true),
+        area(4, "kino", Some(2), ZoneKind::Room, false),
+    ])
+}
+
+#[test]
+fn parses_all_route() {
+    for path in ["/all", "all", "/all/", "/ALL", "/All?token=abc", "//all//", "/all#top"] {
+        assert_eq!(Route::parse(path), Route::All, "{}", path);
+    }
+}
+
+#[test]
+fn parses_ui_route() {
+    for path in ["/ui", "/UI/", "/ui?client=dashboard"] {
+        assert_eq!(Route::parse(path), Route::Ui, "{}", path);
+    }
+}
+
+#[test]
+fn parses_area_route() {
+    for path in ["/laden", "laden", "/Laden/", "/LADEN?id=3&x=1", " /laden "] {
+        assert_eq!(Route::parse(path), Route::Area("laden".to_string()), "{}", path);
+    }
+
+    assert_eq!(Route::parse("/K%C3%BCche"), Route::Area("küche".to_string()));
+    assert_eq!(Route::parse("/allgemein"), Route::Area("allgemein".to_string()));
+    assert_eq!(Route::parse("/all/laden"), Route::Area("all/laden".to_string()));
+}
+
+#[test]
+fn parses_zone_route() {
+    assert_eq!(Route::parse("/zone/eg"), Route::Zone("eg".to_string()));
+    assert_eq!(Route::parse("/Zone/EG/?x=1"), Route::Zone("eg".to_string()));
+    assert_eq!(Route::parse("/zone/haus/eg"), Route::Zone("haus/eg".to_string()));
+
+    // Without a zone name it is a plain area
+    assert_eq!(Route::parse("/zone/"), Route::Area("zone".to_string()));
+}
+
+#[test]
+fn parses_root_route() {
+    for path in ["", "/", "//", "/?x=1"] {
+        assert_eq!(Route::parse(path), Route::Root, "{}", path);
+    }
+}
+
+#[test]
+fn area_slug_matches_route() {
+    assert_eq!(Area::slugify("/Laden/"), "laden");
+    assert_eq!(Route::parse("/Laden/").slug(), Some(Area::slugify("Laden").as_str()));
+}
+
+#[test]
+fn all_subscription_receives_every_alert() {
+    let zones = zones();
+    let topic = Topic { event: EventType::Detection, subscription: Subscription::Subtree(None) };
+
+    for origin in [Some(1), Some(3), Some(4), Some(99), None] {
+        assert!(topic.matches(EventType::Detection, origin, &zones), "{:?}", origin);
+    }
+}
+
+#[test]
+fn area_subscription_receives_own_and_propagated_alerts() {
+    let zones = zones();
+    let laden = Topic { event: EventType::Detection, subscription: Subscription::Node(3) };
+    let eg = Topic { event: EventType::Detection, subscription: Subscription::Node(2) };
+    let haus = Topic { event: EventType::Detection, subscription: Subscription::Node(1) };
+
+    assert!(laden.matches(EventType::Detection, Some(3), &zones));
+    assert!(!laden.matches(EventType::Detection, Some(4), &zones));
+    assert!(!laden.matches(EventType::Detection, None, &zones));
+
+    // laden propagates to eg, eg doesn't propagate to haus
+    assert!(eg.matches(EventType::Detection, Some(3), &zones));
+    assert!(!eg.matches(EventType::Detection, Some(4), &zones));
+    assert!(!haus.matches(EventType::Detection, Some(3), &zones));
+}
+
+#[test]
+fn zone_subscription_receives_subtree_alerts() {
+    let zones = zones();
+    let eg = Topic { event: EventType::Detection, subscription: Subscription::Subtree(Some(2)) };
+
+    assert!(eg.matches(EventType::Detection, Some(2), &zones));
+    assert!(eg.matches(EventType::Detection, Some(3), &zones));
+    assert!(eg.matches(EventType::Detection, Some(4), &zones));
+    assert!(!eg.matches(EventType::Detection, Some(1), &zones));
+}
+
+#[test]
+fn topics_filter_by_event_type() {
+    let zones = zones();
+    let topic = Topic { event: EventType::Device, subscription: Subscription::Node(3) };
+
+    assert!(topic.matches(EventType::Device, Some(3), &zones));
+    assert!(!topic.matches(EventType::Detection, Some(3), &zones));
+
+    // Status events aren't bound to an area
+    let status = Topic { event: EventType::Status, subscription: Subscription::Node(4) };
+    assert!(status.matches(EventType::Status, None, &zones));
+}
+
+#[test]
+fn parent_loops_do_not_hang_routing() {
+    let zones = ZoneTree::new(vec![
+        area(1, "a", Some(2), ZoneKind::Room, true),
+        area(2, "b", Some(1), ZoneKind::Room, true),
+    ]);
+
+    assert_eq!(zones.ancestors(1), vec![1, 2]);
+    assert_eq!(zones.alert_targets(1), vec![1, 2]);
+}
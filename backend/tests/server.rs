@@ -0,0 +1,1331 @@
+#[macro_use]
+mod common;
+
+use std::process::{Child, Command, ExitStatus};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use axum::Router;
+use axum::routing::get;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use sqlx::SqlitePool;
+use sqlx::sqlite::SqliteConnectOptions;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use common::{free_port, Backend, TempDatabase};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Stands in for ntfy and keeps the bodies of the published notifications.
+#[derive(Clone, Default)]
+struct Ntfy {
+    published: Arc<Mutex<Vec<Value>>>,
+}
+
+impl Ntfy {
+    async fn start(&self) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let published = self.published.clone();
+        let router = Router::new()
+            .route("/v1/health", get(|| async { axum::Json(json!({ "healthy": true })) }))
+            .fallback(move |body: String| async move {
+                if let Ok(payload) = serde_json::from_str(&body) {
+                    published.lock().unwrap().push(payload);
+                }
+                axum::Json(json!({}))
+            });
+
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        url
+    }
+
+    /// Waits for a notification on `topic`.
+    async fn notification(&self, topic: &str) -> Option<Value> {
+        self.notification_where(|payload| payload["topic"] == topic).await
+    }
+
+    async fn notification_where(&self, matches: impl Fn(&Value) -> bool) -> Option<Value> {
+        for _ in 0..50 {
+            let published = self.published.lock().unwrap().clone();
+            if let Some(payload) = published.into_iter().find(|payload| matches(payload)) {
+                return Some(payload);
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        None
+    }
+
+    fn count_where(&self, matches: impl Fn(&Value) -> bool) -> usize {
+        self.published.lock().unwrap().iter().filter(|payload| matches(payload)).count()
+    }
+}
+
+scenarios!(
+    registration_assigns_id_uuid_and_area,
+    registration_is_idempotent_by_hardware_and_request_id,
+    detection_alerts_area_stores_and_notifies,
+    sensor_kinds_are_normalized_and_decide_the_alarm,
+    repeated_detections_are_debounced_and_floods_dropped,
+    correlation_rules_hold_back_the_alarm,
+    repeated_detections_count_towards_correlation_rules,
+    detection_history_is_paged_and_aggregated,
+    detections_and_incidents_are_exported,
+    detection_does_not_alert_other_areas,
+    alerts_reach_the_clients_of_each_route,
+    subscriptions_select_topics_and_areas,
+    areas_and_zones_are_managed_by_the_api,
+    devices_need_approval_and_token,
+    config_is_pushed_on_edit_and_connect,
+    commands_are_answered_by_request_id,
+    telemetry_is_stored_and_warns_once_per_crossing,
+    firmware_is_rolled_out_in_stages,
+    health_reports_storage_backend,
+    api_requires_admin_token,
+    metric_labels_ignore_areas_claimed_by_devices,
+    shutdown_closes_clients_and_stops_http,
+);
+
+/// Sent by the helpers with every request, the API rejects requests without it.
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Runs the server binary on free ports with its own ntfy and stops it when dropped.
+struct Server {
+    process: Child,
+    port: u16,
+    http_port: u16,
+    ntfy: Ntfy,
+    /// Dropped after the process, see `Drop`.
+    database: Option<TempDatabase>,
+}
+
+impl Server {
+    async fn start(backend: Backend) -> Server {
+        Self::start_with(backend, &[]).await
+    }
+
+    async fn start_with(backend: Backend, env: &[(&str, &str)]) -> Server {
+        Self::launch(TempDatabase::create(backend).await, env).await
+    }
+
+    /// Runs the server on a database the test prepared, e.g. with the data of an older version.
+    async fn start_on(database: TempDatabase) -> Server {
+        Self::launch(Some(database), &[]).await
+    }
+
+    async fn launch(database: Option<TempDatabase>, env: &[(&str, &str)]) -> Server {
+        let port = free_port();
+        let http_port = free_port();
+        let ntfy = Ntfy::default();
+        let ntfy_url = ntfy.start().await;
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_alert_net_server"));
+        match &database {
+            Some(database) => command.arg("--storage").arg("database").env("DATABASE_URL", database.url()),
+            None => command.arg("--storage").arg("memory"),
+        };
+
+        let process = command
+            .env("ADMIN_TOKEN", ADMIN_TOKEN)
+            .envs(env.iter().copied())
+            .env("SERVER_ADDRESS", "127.0.0.1")
+            .env("SERVER_PORT", port.to_string())
+            .env("HTTP_PORT", http_port.to_string())
+            .env("NTFY_URL", ntfy_url)
+            .env("LOG_LEVEL", "warn")
+            .env_remove("RUST_LOG")
+            .spawn()
+            .expect("server binary should start");
+
+        Server { process, port, http_port, ntfy, database }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.http_port, path)
+    }
+
+    /// Waits until the HTTP endpoints answer, for scenarios that start with a request.
+    async fn ready(&self) {
+        for _ in 0..50 {
+            if reqwest::get(self.url("/healthz")).await.is_ok() {
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("server did not start");
+    }
+
+    async fn connect(&self, path: &str) -> Socket {
+        let url = format!("ws://127.0.0.1:{}{}", self.port, path);
+
+        for _ in 0..50 {
+            if let Ok((socket, _)) = connect_async(url.as_str()).await {
+                // Give the server time to add the client to the peer map
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                return socket;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("could not connect to {}", url);
+    }
+
+    /// Connects to `path` and registers a device there.
+    async fn sensor(&self, path: &str, description: &str, area: &str) -> Sensor {
+        let mut socket = self.connect(path).await;
+        let device = register(&mut socket, description, area).await;
+
+        Sensor { socket, device }
+    }
+
+    async fn get(&self, path: &str) -> (u16, Value) {
+        let response = reqwest::Client::new()
+            .get(self.url(path))
+            .bearer_auth(ADMIN_TOKEN)
+            .send().await.unwrap();
+        let status = response.status().as_u16();
+
+        (status, response.json().await.unwrap())
+    }
+
+    /// For the downloads, which aren't JSON.
+    async fn get_text(&self, path: &str) -> (u16, String) {
+        let response = reqwest::Client::new()
+            .get(self.url(path))
+            .bearer_auth(ADMIN_TOKEN)
+            .send().await.unwrap();
+        let status = response.status().as_u16();
+
+        (status, response.text().await.unwrap())
+    }
+
+    /// Polls `path` until the response matches, for state the server updates after answering a message.
+    async fn get_until(&self, path: &str, matches: impl Fn(&Value) -> bool) -> Value {
+        for _ in 0..50 {
+            let (_, value) = self.get(path).await;
+            if matches(&value) {
+                return value;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("{} did not reach the expected state", path);
+    }
+
+    async fn post(&self, path: &str, body: Value) -> (u16, Value) {
+        self.send(reqwest::Method::POST, path, body).await
+    }
+
+    async fn put(&self, path: &str, body: Value) -> (u16, Value) {
+        self.send(reqwest::Method::PUT, path, body).await
+    }
+
+    async fn send(&self, method: reqwest::Method, path: &str, body: Value) -> (u16, Value) {
+        let response = reqwest::Client::new()
+            .request(method, self.url(path))
+            .bearer_auth(ADMIN_TOKEN)
+            .json(&body)
+            .send().await.unwrap();
+        let status = response.status().as_u16();
+
+        (status, response.json().await.unwrap())
+    }
+}
+
+impl Server {
+    /// Stops the server like Ctrl-C does and waits until it exited.
+    async fn interrupt(&mut self) -> ExitStatus {
+        let sent = Command::new("kill").arg("-INT").arg(self.process.id().to_string()).status().unwrap();
+        assert!(sent.success(), "could not interrupt the server");
+
+        for _ in 0..100 {
+            if let Some(status) = self.process.try_wait().unwrap() {
+                return status;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("server did not stop");
+    }
+
+    /// Stops the server and starts it again on the same database.
+    async fn restart(mut self) -> Server {
+        self.interrupt().await;
+        let database = self.database.take();
+        drop(self);
+
+        Self::launch(database, &[]).await
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// A connection with the device registered on it.
+struct Sensor {
+    socket: Socket,
+    device: Value,
+}
+
+impl Sensor {
+    /// Sends a detection of `source` as the registered device.
+    async fn detect(&mut self, source: &str) {
+        self.send(json!({ "device": self.device, "source": source })).await;
+    }
+
+    async fn send(&mut self, message: Value) {
+        send_json(&mut self.socket, &message).await;
+    }
+
+    async fn receive(&mut self) -> Option<Value> {
+        receive_json(&mut self.socket).await
+    }
+}
+
+async fn send_json(socket: &mut Socket, message: &Value) {
+    socket.send(Message::text(message.to_string())).await.unwrap();
+}
+
+/// Next JSON message on the socket, skipping plain text.
+async fn receive_json(socket: &mut Socket) -> Option<Value> {
+    loop {
+        match tokio::time::timeout(Duration::from_secs(2), socket.next()).await {
+            Ok(Some(Ok(Message::Text(text)))) => {
+                if let Ok(value) = serde_json::from_str(&text) {
+                    return Some(value);
+                }
+            }
+            Ok(Some(Ok(_))) => continue,
+            _ => return None,
+        }
+    }
+}
+
+/// Every JSON message until the socket stays quiet.
+async fn receive_all(socket: &mut Socket) -> Vec<Value> {
+    let mut messages = Vec::new();
+    while let Some(message) = receive_json(socket).await {
+        messages.push(message);
+    }
+
+    messages
+}
+
+/// Next JSON message that matches, skipping the others.
+async fn receive_where(socket: &mut Socket, matches: impl Fn(&Value) -> bool) -> Option<Value> {
+    while let Some(message) = receive_json(socket).await {
+        if matches(&message) {
+            return Some(message);
+        }
+    }
+
+    None
+}
+
+/// Registers a device on `socket` and returns it as the server stored it.
+async fn register(socket: &mut Socket, description: &str, area: &str) -> Value {
+    let device = json!({
+        "id": 0,
+        "uuid": "00000000-0000-0000-0000-000000000000",
+        "description": description,
+        "area": area,
+    });
+
+    send_registration(socket, device).await
+}
+
+async fn send_registration(socket: &mut Socket, registration: Value) -> Value {
+    send_json(socket, &registration).await;
+
+    receive_json(socket).await.expect("registration should be answered")
+}
+
+async fn registration_assigns_id_uuid_and_area(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let sensor = server.sensor("/laden", "Tür", "Laden").await;
+
+    assert_eq!(sensor.device["id"], 1);
+    assert_eq!(sensor.device["description"], "Tür");
+    assert_eq!(sensor.device["area"], "laden");
+    assert_ne!(sensor.device["uuid"], "00000000-0000-0000-0000-000000000000");
+}
+
+async fn registration_is_idempotent_by_hardware_and_request_id(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.connect("/laden").await;
+
+    let first = send_registration(&mut sensor, json!({ "description": "Tür", "area": "laden", "hardware_id": "AA:BB:CC:DD:EE:FF" })).await;
+    let again = send_registration(&mut sensor, json!({ "description": "Tür", "area": "laden", "hardware_id": "aa:bb:cc:dd:ee:ff" })).await;
+    assert_eq!(first, again);
+
+    let requested = send_registration(&mut sensor, json!({ "description": "Fenster", "area": "laden", "request_id": "7f1c" })).await;
+    let retried = send_registration(&mut sensor, json!({ "description": "Fenster", "area": "laden", "request_id": "7f1c" })).await;
+    assert_eq!(requested, retried);
+    assert_ne!(first["id"], requested["id"]);
+
+    // Without either id every registration is a new device
+    let plain = register(&mut sensor, "Tür", "laden").await;
+    let other = register(&mut sensor, "Tür", "laden").await;
+    assert_ne!(plain["id"], other["id"]);
+}
+
+async fn detection_alerts_area_stores_and_notifies(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/laden", "Tür", "laden").await;
+    let mut siren = server.connect("/laden/?device=2").await;
+
+    // The stored description is shown, not the one the device was configured with
+    let mut sent = sensor.device.clone();
+    sent["description"] = json!("Alt");
+    sensor.send(json!({ "device": sent, "source": "pir" })).await;
+
+    let alert = receive_json(&mut siren).await.expect("siren should be alerted");
+    assert_eq!(alert, json!({ "led": true, "speaker": true }));
+
+    let notification = server.ntfy.notification("Alert-Net-laden").await.expect("detection should be published to ntfy");
+    assert_eq!(notification["message"], "Gerät: Tür, Auslöser: Bewegung");
+
+    let (status, history) = server.get("/api/detections?areas=laden").await;
+    assert_eq!(status, 200);
+    assert_eq!(history["detections"].as_array().unwrap().len(), 1);
+    assert_eq!(history["detections"][0]["source"], "motion");
+    assert_eq!(history["detections"][0]["device"]["uuid"], sensor.device["uuid"]);
+}
+
+async fn sensor_kinds_are_normalized_and_decide_the_alarm(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/laden", "Eingang", "laden").await;
+    let mut siren = server.connect("/laden/?device=2").await;
+
+    // A contact closing again is only stored, opening it alarms
+    for state in ["closed", "open"] {
+        sensor.send(json!({ "device": sensor.device, "source": "Türkontakt", "state": state })).await;
+    }
+    assert!(receive_json(&mut siren).await.is_some(), "opening the contact should alarm");
+    assert!(receive_json(&mut siren).await.is_none(), "closing the contact should not alarm");
+
+    let notification = server.ntfy.notification("Alert-Net-laden").await.expect("opening the contact should be published to ntfy");
+    assert_eq!(notification["message"], "Gerät: Eingang, Auslöser: Tür/Fenster geöffnet");
+
+    // Smoke alarms regardless of the correlation rule and with the highest priority
+    let (status, _) = server.put("/api/correlation", json!({ "area": "laden", "min_detections": 3 })).await;
+    assert_eq!(status, 200);
+    for source in ["Rauchmelder", "Vibration Sensor"] {
+        sensor.detect(source).await;
+    }
+
+    let smoke = server.ntfy.notification_where(|payload| payload["title"] == "Rauchalarm im Bereich laden").await
+        .expect("smoke should skip the correlation rule");
+    assert_eq!(smoke["priority"], 5);
+    assert_eq!(smoke["message"], "Gerät: Eingang, Auslöser: Rauch");
+
+    let (_, history) = server.get("/api/detections?areas=laden").await;
+    let stored: Vec<(&Value, &Value, &Value)> = history["detections"].as_array().unwrap().iter()
+        .map(|detection| (&detection["source"], &detection["kind"], &detection["state"]))
+        .collect();
+    // The vibration only raised a pre-alarm, so it is stored as one
+    assert_eq!(stored, [
+        (&json!("smoke"), &json!("smoke"), &Value::Null),
+        (&json!("contact"), &json!("contact"), &json!("open")),
+        (&json!("contact"), &json!("contact"), &json!("closed")),
+    ]);
+
+    let (_, pre_alarms) = server.get("/api/pre-alarms?area=laden").await;
+    assert_eq!(pre_alarms[0]["source"], "vibration_sensor");
+
+    let (_, contacts) = server.get("/api/detections?source=Fenster").await;
+    assert_eq!(contacts["detections"].as_array().unwrap().len(), 2);
+}
+
+async fn repeated_detections_are_debounced_and_floods_dropped(backend: Backend) {
+    let server = Server::start_with(backend, &[("RATE_LIMIT_MESSAGES", "5"), ("RATE_LIMIT_WINDOW_SECONDS", "60")]).await;
+
+    let mut sensor = server.sensor("/laden", "Tür", "laden").await;
+    let mut siren = server.connect("/laden/?device=2").await;
+
+    // Together with the registration these reach the limit
+    for _ in 0..4 {
+        sensor.detect("pir").await;
+    }
+
+    assert!(receive_json(&mut siren).await.is_some(), "siren should be alerted");
+    assert!(receive_json(&mut siren).await.is_none(), "repeats within the debounce window should not alert again");
+
+    sensor.detect("pir").await;
+    let flood = server.ntfy.notification_where(|payload| payload["title"] == "Zu viele Nachrichten").await
+        .expect("the flood should be notified");
+    assert!(flood["message"].as_str().unwrap().starts_with("Bereich: /laden"));
+    assert_eq!(server.ntfy.count_where(|payload| payload["topic"] == "Alert-Net-laden"), 1);
+
+    let (_, history) = server.get("/api/detections?areas=laden").await;
+    assert_eq!(history["detections"].as_array().unwrap().len(), 1);
+    assert_eq!(history["detections"][0]["count"], 4);
+}
+
+async fn correlation_rules_hold_back_the_alarm(backend: Backend) {
+    let server = Server::start_with(backend, &[("DETECTION_DEBOUNCE_SECONDS", "")]).await;
+
+    let mut hall = server.sensor("/laden", "Flur", "laden").await;
+    let mut window = server.sensor("/laden", "Fenster", "laden").await;
+    let mut siren = server.connect("/laden/?device=2").await;
+
+    let (status, _) = server.put("/api/correlation", json!({ "area": "laden", "min_detections": 2, "min_devices": 2 })).await;
+    assert_eq!(status, 200);
+    let (status, _) = server.put("/api/correlation", json!({ "area": "lager", "first_source": "door", "then_source": "motion" })).await;
+    assert_eq!(status, 200);
+    let (status, _) = server.put("/api/correlation", json!({ "area": "kino", "min_detections": 1, "min_devices": 2 })).await;
+    assert_eq!(status, 400);
+
+    // Two detections of the same device are not enough
+    for source in ["pir", "vibration"] {
+        hall.detect(source).await;
+    }
+    assert!(receive_json(&mut siren).await.is_none(), "one device should only raise pre-alarms");
+
+    window.detect("pir").await;
+    assert!(receive_json(&mut siren).await.is_some(), "the second device should raise the alarm");
+
+    let (_, history) = server.get("/api/detections?areas=laden").await;
+    assert_eq!(history["detections"].as_array().unwrap().len(), 1, "pre-alarms should not be in the detection history");
+
+    let (_, pre_alarms) = server.get("/api/pre-alarms?area=laden").await;
+    let progress: Vec<(&Value, &Value)> = pre_alarms.as_array().unwrap().iter().map(|p| (&p["detections"], &p["devices"])).collect();
+    assert_eq!(progress, [(&json!(2), &json!(1)), (&json!(1), &json!(1))]);
+
+    // Motion alone is a pre-alarm, after the door contact it alarms
+    let mut door = server.sensor("/lager", "Tor", "lager").await;
+    let mut motion = server.sensor("/lager", "Halle", "lager").await;
+
+    // Sent one after the other, the order of the sequence matters
+    for (from_door, source) in [(false, "Motion"), (true, "door"), (false, "motion")] {
+        let sensor = if from_door { &mut door } else { &mut motion };
+        sensor.detect(source).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    server.ntfy.notification("Alert-Net-lager").await.expect("motion after the door contact should alarm");
+    let (_, pre_alarms) = server.get("/api/pre-alarms?area=lager").await;
+    assert_eq!(pre_alarms.as_array().unwrap().len(), 2);
+}
+
+async fn repeated_detections_count_towards_correlation_rules(backend: Backend) {
+    // Debounced as by default
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/laden", "Flur", "laden").await;
+    let mut siren = server.connect("/laden/?device=2").await;
+
+    let (status, _) = server.put("/api/correlation", json!({ "area": "laden", "min_detections": 3, "min_devices": 1 })).await;
+    assert_eq!(status, 200);
+
+    for _ in 0..2 {
+        sensor.detect("pir").await;
+    }
+    assert!(receive_json(&mut siren).await.is_none(), "two detections should only raise pre-alarms");
+
+    sensor.detect("pir").await;
+    assert!(receive_json(&mut siren).await.is_some(), "the third detection of the device should raise the alarm");
+
+    // Once it alarmed, the repeats are debounced
+    sensor.detect("pir").await;
+    assert!(receive_json(&mut siren).await.is_none(), "repeats after the alarm should not alert again");
+
+    let (_, pre_alarms) = server.get("/api/pre-alarms?area=laden").await;
+    assert_eq!(pre_alarms.as_array().unwrap().len(), 2);
+    // Only the detection that alarmed is in the history, with the repeat after it
+    let (_, history) = server.get("/api/detections?areas=laden").await;
+    assert_eq!(history["detections"].as_array().unwrap().len(), 1);
+    assert_eq!(history["detections"][0]["count"], 2);
+}
+
+async fn detection_history_is_paged_and_aggregated(backend: Backend) {
+    let server = Server::start_with(backend, &[("DETECTION_DEBOUNCE_SECONDS", ""), ("RATE_LIMIT_MESSAGES", "")]).await;
+
+    let mut door = server.sensor("/laden", "Tür", "laden").await;
+    let mut hall = server.sensor("/lager", "Halle", "lager").await;
+    for source in ["pir", "door", "pir", "vibration", "pir"] {
+        door.detect(source).await;
+    }
+    for _ in 0..2 {
+        hall.detect("pir").await;
+    }
+
+    let all = server.get_until("/api/detections", |page| page["detections"].as_array().unwrap().len() == 7).await;
+    let expected: Vec<i64> = all["detections"].as_array().unwrap().iter().map(|detection| detection["id"].as_i64().unwrap()).collect();
+    assert!(expected.windows(2).all(|pair| pair[0] > pair[1]), "newest first: {:?}", expected);
+    assert_eq!(all["next_cursor"], Value::Null);
+
+    // Following the cursors neither repeats nor skips a detection, also when the last page is full
+    for limit in [2, 7] {
+        let mut paged = Vec::new();
+        let mut cursor = None;
+        loop {
+            let path = match cursor {
+                Some(cursor) => format!("/api/detections?limit={}&cursor={}", limit, cursor),
+                None => format!("/api/detections?limit={}", limit),
+            };
+            let (status, page) = server.get(&path).await;
+            assert_eq!(status, 200);
+            let detections = page["detections"].as_array().unwrap();
+            assert!(detections.len() <= limit);
+            paged.extend(detections.iter().map(|detection| detection["id"].as_i64().unwrap()));
+
+            match page["next_cursor"].as_i64() {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(paged, expected, "limit {}", limit);
+    }
+
+    // Filters apply to every page
+    let (_, first) = server.get("/api/detections?areas=laden&source=Bewegung&limit=2").await;
+    assert_eq!(first["detections"].as_array().unwrap().len(), 2);
+    let (_, rest) = server.get(&format!("/api/detections?areas=laden&source=Bewegung&limit=2&cursor={}", first["next_cursor"])).await;
+    assert_eq!(rest["detections"].as_array().unwrap().len(), 1);
+    assert!(rest["detections"][0]["id"].as_i64() < first["detections"][1]["id"].as_i64());
+    let (_, future) = server.get("/api/detections?from=2999-01-01T00:00:00Z").await;
+    assert_eq!(future["detections"], json!([]));
+
+    // Buckets start at the full hour or day, the detections may straddle one
+    for (bucket, start) in [("hour", ":00:00Z"), ("day", "T00:00:00Z")] {
+        let (status, counts) = server.get(&format!("/api/detections/counts?bucket={}", bucket)).await;
+        assert_eq!(status, 200);
+        let counts = counts.as_array().unwrap();
+        assert!(counts.iter().all(|count| count["bucket"].as_str().unwrap().ends_with(start)), "{:?}", counts);
+
+        let total = |area: &str| counts.iter().filter(|count| count["area"] == area).map(|count| count["count"].as_i64().unwrap()).sum::<i64>();
+        assert_eq!((total("laden"), total("lager")), (5, 2), "{}", bucket);
+    }
+    let (_, counts) = server.get("/api/detections/counts?areas=lager&source=pir").await;
+    assert!(counts.as_array().unwrap().iter().all(|count| count["area"] == "lager"));
+
+    // The noisiest devices first, cut off at the limit
+    let (status, noisy) = server.get("/api/devices/noisy").await;
+    assert_eq!(status, 200);
+    let ranking: Vec<(&Value, &Value)> = noisy.as_array().unwrap().iter().map(|ranked| (&ranked["device"]["uuid"], &ranked["count"])).collect();
+    assert_eq!(ranking, [(&door.device["uuid"], &json!(5)), (&hall.device["uuid"], &json!(2))]);
+
+    let (_, noisiest) = server.get("/api/devices/noisy?limit=1").await;
+    assert_eq!(noisiest.as_array().unwrap().len(), 1);
+    assert_eq!(noisiest[0]["device"]["uuid"], door.device["uuid"]);
+    let (_, quiet) = server.get("/api/devices/noisy?source=vibration").await;
+    assert_eq!(quiet[0]["count"], 1);
+}
+
+async fn detections_and_incidents_are_exported(backend: Backend) {
+    let server = Server::start_with(backend, &[("DETECTION_DEBOUNCE_SECONDS", "")]).await;
+
+    let mut door = server.sensor("/laden", "Tür, Eingang", "laden").await;
+    let mut hall = server.sensor("/lager", "Halle", "lager").await;
+    let (status, _) = server.put("/api/areas", json!({ "area": "laden", "name": "Laden \"Mitte\"" })).await;
+    assert_eq!(status, 200);
+    let (status, _) = server.put("/api/areas", json!({ "area": "lager", "armed": false })).await;
+    assert_eq!(status, 200);
+
+    door.detect("pir").await;
+    door.send(json!({ "device": door.device, "source": "Türkontakt", "state": "open" })).await;
+    hall.detect("pir").await;
+    server.get_until("/api/detections", |page| page["detections"].as_array().unwrap().len() == 3).await;
+
+    // Names are quoted where they contain a separator or a quote
+    let (status, csv) = server.get_text("/api/export/detections?format=csv").await;
+    assert_eq!(status, 200);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "id,timestamp,source,device_uuid,device_description,area,area_name,count,state");
+    assert_eq!(lines.len(), 4, "{}", csv);
+    let contact = lines.iter().find(|line| line.contains(",contact,")).expect("the contact should be exported");
+    assert!(contact.ends_with(r#","Tür, Eingang",laden,"Laden ""Mitte""",1,open"#), "{}", contact);
+
+    let (_, jsonl) = server.get_text("/api/export/detections?format=jsonl&areas=lager").await;
+    let records: Vec<Value> = jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!((&records[0]["area"], &records[0]["device_description"]), (&json!("lager"), &json!("Halle")));
+
+    // Start inclusive, end exclusive
+    for (range, expected) in [
+        ("from=2000-01-01T00:00:00Z&to=2999-01-01T00:00:00Z", 3),
+        ("from=2999-01-01T00:00:00Z", 0),
+        ("to=2000-01-01T00:00:00Z", 0),
+    ] {
+        let (_, jsonl) = server.get_text(&format!("/api/export/detections?format=jsonl&{}", range)).await;
+        assert_eq!(jsonl.lines().count(), expected, "{}", range);
+    }
+
+    // Only the armed area alarmed, so only its detections are incidents
+    let (status, jsonl) = server.get_text("/api/export/incidents?format=jsonl").await;
+    assert_eq!(status, 200);
+    let incidents: Vec<Value> = jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let alarms: Vec<(&Value, &Value, &Value)> = incidents.iter().map(|incident| (&incident["area"], &incident["source"], &incident["state"])).collect();
+    assert_eq!(alarms, [(&json!("laden"), &json!("motion"), &Value::Null), (&json!("laden"), &json!("contact"), &json!("open"))]);
+    assert!(incidents.iter().all(|incident| incident["recipients"].as_i64().unwrap() >= 1 && incident["device_uuid"] == door.device["uuid"]));
+
+    let (_, csv) = server.get_text("/api/export/incidents?areas=laden&source=Tür&from=2000-01-01T00:00:00Z").await;
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "id,timestamp,source,device_uuid,device_description,area,area_name,recipients,state");
+    assert_eq!(lines.len(), 2, "{}", csv);
+    assert!(lines[1].ends_with(",open"), "{}", lines[1]);
+    let (_, csv) = server.get_text("/api/export/incidents?areas=lager").await;
+    assert_eq!(csv.lines().count(), 1, "{}", csv);
+}
+
+async fn detection_does_not_alert_other_areas(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/laden", "Tür", "laden").await;
+    let mut other = server.connect("/kino").await;
+
+    sensor.detect("pir").await;
+
+    assert!(receive_json(&mut other).await.is_none());
+}
+
+async fn alerts_reach_the_clients_of_each_route(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/K%C3%BCche", "Tür", "küche").await;
+    let mut siren = server.connect("/k%c3%bcche/?device=2").await;
+    let mut other_siren = server.connect("/kino").await;
+    let mut all = server.connect("/ALL").await;
+    let mut zone = server.connect("/zone/k%C3%BCche").await;
+    let mut other_zone = server.connect("/zone/kino").await;
+    let mut ui = server.connect("/ui").await;
+
+    sensor.detect("pir").await;
+
+    let alert = json!({ "led": true, "speaker": true });
+    assert_eq!(receive_json(&mut siren).await, Some(alert.clone()), "the percent-encoded path should be the same area");
+    assert_eq!(receive_json(&mut all).await, Some(alert.clone()));
+    assert_eq!(receive_json(&mut zone).await, Some(alert));
+    assert!(receive_json(&mut other_siren).await.is_none());
+    assert!(receive_json(&mut other_zone).await.is_none());
+
+    // The dashboard sees the messages of the devices, but no siren alert
+    let forwarded = receive_json(&mut ui).await.expect("the detection should be forwarded to the dashboard");
+    assert_eq!(forwarded["source"], "pir");
+    assert_eq!(forwarded["device"]["uuid"], sensor.device["uuid"]);
+    assert!(receive_json(&mut ui).await.is_none());
+}
+
+async fn subscriptions_select_topics_and_areas(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut laden = server.sensor("/laden", "Tür", "laden").await;
+    let mut kino = server.sensor("/kino", "Saal", "kino").await;
+    let mut ui = server.connect("/ui").await;
+
+    let (status, _) = server.put("/api/correlation", json!({ "area": "laden", "min_detections": 2 })).await;
+    assert_eq!(status, 200);
+
+    let selection = json!({ "areas": ["laden"], "events": ["detection"] });
+    send_json(&mut ui, &json!({ "subscribe": selection })).await;
+    // The dashboard also sees its own message forwarded
+    let confirmation = receive_where(&mut ui, |message| message.get("subscriptions").is_some()).await
+        .expect("the subscription should be confirmed");
+    let topics = confirmation["subscriptions"].as_array().unwrap();
+    assert_eq!(topics.len(), 1);
+    assert_eq!(topics[0]["event"], "detection");
+    assert_eq!(topics[0]["subscription"]["type"], "node");
+
+    // The pre-alarm and the alert of the other area are not asked for
+    laden.detect("pir").await;
+    kino.detect("pir").await;
+    laden.detect("vibration").await;
+
+    let alert = json!({ "led": true, "speaker": true });
+    let received = receive_all(&mut ui).await;
+    assert_eq!(received.iter().filter(|message| **message == alert).count(), 1, "{:?}", received);
+    assert!(!received.iter().any(|message| message["event"] == "prealarm"), "{:?}", received);
+
+    send_json(&mut ui, &json!({ "unsubscribe": selection })).await;
+    let confirmation = receive_where(&mut ui, |message| message.get("subscriptions").is_some()).await
+        .expect("the unsubscription should be confirmed");
+    assert_eq!(confirmation["subscriptions"], json!([]));
+
+    let mut other = server.sensor("/laden", "Fenster", "laden").await;
+    for source in ["pir", "vibration"] {
+        other.detect(source).await;
+    }
+    let received = receive_all(&mut ui).await;
+    assert!(!received.contains(&alert), "{:?}", received);
+}
+
+async fn areas_and_zones_are_managed_by_the_api(backend: Backend) {
+    let server = Server::start(backend).await;
+    server.ready().await;
+
+    let (status, floor) = server.put("/api/areas", json!({ "area": "EG", "name": "Erdgeschoss", "kind": "floor" })).await;
+    assert_eq!(status, 200);
+    assert_eq!(floor["slug"], "eg");
+    assert_eq!(floor["kind"], "floor");
+
+    let (status, kitchen) = server.put("/api/areas", json!({ "area": "küche", "parent": "eg", "propagate": true, "notification_priority": 4 })).await;
+    assert_eq!(status, 200);
+    assert_eq!(kitchen["parent_id"], floor["id"]);
+    assert_eq!(kitchen["propagate"], true);
+
+    // The tree stays a tree
+    let (status, _) = server.put("/api/areas", json!({ "area": "eg", "parent": "küche" })).await;
+    assert_eq!(status, 400);
+    let (status, _) = server.put("/api/areas", json!({ "area": "eg", "parent": "/EG/" })).await;
+    assert_eq!(status, 400);
+    let (status, _) = server.put("/api/areas", json!({ "area": "küche", "notification_priority": 6 })).await;
+    assert_eq!(status, 400);
+
+    // Propagated up to the sirens of the floor
+    let mut sensor = server.sensor("/k%C3%BCche", "Herd", "küche").await;
+    let mut floor_siren = server.connect("/eg").await;
+    let mut floor_observer = server.connect("/zone/eg").await;
+
+    sensor.detect("pir").await;
+    let alert = json!({ "led": true, "speaker": true });
+    assert_eq!(receive_json(&mut floor_siren).await, Some(alert.clone()));
+    assert_eq!(receive_json(&mut floor_observer).await, Some(alert));
+
+    // Disarmed, only the status is published
+    let (status, kitchen) = server.put("/api/areas", json!({ "area": "küche", "parent": "eg", "armed": false })).await;
+    assert_eq!(status, 200);
+    assert_eq!(kitchen["armed"], false);
+    assert_eq!(kitchen["propagate"], true, "missing switches should be kept");
+    assert_eq!(kitchen["notification_priority"], 4);
+
+    sensor.detect("glass").await;
+    assert!(receive_json(&mut floor_siren).await.is_none());
+
+    let (status, areas) = server.get("/api/areas").await;
+    assert_eq!(status, 200);
+    let slugs: Vec<&Value> = areas.as_array().unwrap().iter().map(|area| &area["slug"]).collect();
+    assert_eq!(slugs, ["eg", "küche"]);
+    assert_eq!(areas[1]["armed"], false);
+}
+
+async fn devices_need_approval_and_token(backend: Backend) {
+    let server = Server::start_with(backend, &[("DEVICE_APPROVAL", "true")]).await;
+
+    let mut sensor = server.connect("/laden").await;
+    let registration = json!({ "description": "Tür", "area": "laden", "hardware_id": "AA:BB:CC:DD:EE:FF" });
+
+    let pending = send_registration(&mut sensor, registration.clone()).await;
+    assert_eq!(pending["status"], "pending");
+    let pairing_code = pending["pairing_code"].as_str().unwrap().to_string();
+    assert_eq!(pairing_code.len(), 6);
+
+    // A retry keeps its place in the queue
+    assert_eq!(send_registration(&mut sensor, registration.clone()).await, pending);
+
+    let (status, queue) = server.get("/api/devices/pending").await;
+    assert_eq!(status, 200);
+    assert_eq!(queue.as_array().unwrap().len(), 1);
+    assert_eq!(queue[0]["hardware_id"], "aa:bb:cc:dd:ee:ff");
+    assert!(queue[0].get("pairing_code").is_none());
+    let id = queue[0]["id"].as_i64().unwrap();
+
+    let (status, _) = server.post(&format!("/api/devices/pending/{}/approve", id), json!({ "pairing_code": "wrong" })).await;
+    assert_eq!(status, 403);
+    let (status, _) = server.post(&format!("/api/devices/pending/{}/approve", id), json!({ "area": "kasse" })).await;
+    assert_eq!(status, 403);
+
+    let (status, device) = server.post(
+        &format!("/api/devices/pending/{}/approve", id),
+        json!({ "description": "Eingang", "area": "kasse", "pairing_code": pairing_code }),
+    ).await;
+    assert_eq!(status, 200);
+    assert_eq!(device["description"], "Eingang");
+    assert_eq!(device["area"], "kasse");
+
+    // The connection of the device gets its token
+    let approved = receive_json(&mut sensor).await.expect("approval should be sent to the device");
+    assert_eq!(approved["status"], "approved");
+    assert_eq!(approved["id"], device["id"]);
+    let token = approved["token"].as_str().unwrap();
+
+    send_json(&mut sensor, &json!({ "device": device, "source": "pir", "token": "forged" })).await;
+    let error = receive_json(&mut sensor).await.expect("a wrong token should be reported");
+    assert_eq!(error["error"], format!("Device {} is not approved", device["id"]));
+
+    send_json(&mut sensor, &json!({ "device": device, "source": "pir", "token": token })).await;
+    // The sensor hears the alarms of the area it connected to
+    let alert = receive_json(&mut sensor).await.expect("an approved device should raise alarms");
+    assert_eq!(alert, json!({ "led": true, "speaker": true }));
+
+    // The token is only handed out once
+    let again = send_registration(&mut sensor, registration).await;
+    assert_eq!(again["status"], "approved");
+    assert!(again.get("token").is_none());
+
+    let (_, queue) = server.get("/api/devices/pending").await;
+    assert!(queue.as_array().unwrap().is_empty());
+}
+
+async fn config_is_pushed_on_edit_and_connect(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/laden", "Tür", "laden").await;
+    let device = sensor.device.clone();
+    let id = device["id"].as_i64().unwrap();
+
+    let (status, _) = server.get(&format!("/api/devices/{}/config", id)).await;
+    assert_eq!(status, 404);
+
+    let (status, _) = server.put(&format!("/api/devices/{}/config", id), json!({ "speaker_volume": 31 })).await;
+    assert_eq!(status, 400);
+
+    // The registered connection gets the edit right away
+    let (status, config) = server.put(&format!("/api/devices/{}/config", id), json!({ "description": "Eingang", "speaker_volume": 20 })).await;
+    assert_eq!(status, 200);
+    assert_eq!(config["version"], 1);
+    assert_eq!(config["applied_version"], Value::Null);
+
+    let update = sensor.receive().await.expect("config should be pushed");
+    assert_eq!(update, json!({ "type": "config_update", "version": 1, "description": "Eingang", "area": "laden", "speaker_volume": 20 }));
+
+    let ack = json!({ "device": device, "config_version": 1 });
+    sensor.send(ack.clone()).await;
+    server.get_until(&format!("/api/devices/{}/config", id), |config| config["applied_version"] == 1).await;
+
+    // An edit while the device is offline is pushed when it reconnects with the older version
+    drop(sensor);
+    let (_, config) = server.put(&format!("/api/devices/{}/config", id), json!({ "heartbeat_interval_s": 30 })).await;
+    assert_eq!(config["version"], 2);
+    assert_eq!(config["applied_version"], 1);
+    assert_eq!(config["description"], "Eingang");
+
+    let mut socket = server.connect("/laden").await;
+    send_json(&mut socket, &ack).await;
+    let update = receive_json(&mut socket).await.expect("config should be pushed on connect");
+    assert_eq!(update["version"], 2);
+    assert_eq!(update["heartbeat_interval_s"], 30);
+    assert!(update.get("speaker_volume").is_none());
+
+    send_json(&mut socket, &json!({ "device": device, "config_version": 2 })).await;
+    assert!(receive_json(&mut socket).await.is_none());
+
+    server.get_until("/api/devices/config", |configs| configs[0]["applied_version"] == 2).await;
+}
+
+async fn commands_are_answered_by_request_id(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/laden", "Tür", "laden").await;
+    let device = sensor.device.clone();
+
+    let device_side = async {
+        let command = sensor.receive().await.expect("command should be sent to the device");
+        assert_eq!(command["type"], "command");
+        assert_eq!(command["command"], "diagnostics");
+
+        sensor.send(json!({
+            "request_id": command["request_id"],
+            "ok": true,
+            "diagnostics": { "free_heap": 21000, "rssi": -61, "uptime_s": 3600, "firmware_version": "1.4.0" },
+        })).await;
+        command["request_id"].clone()
+    };
+    let request = server.post("/api/commands?wait=5", json!({ "device": device["uuid"], "command": "diagnostics" }));
+
+    let (request_id, (status, record)) = tokio::join!(device_side, request);
+    assert_eq!(status, 200);
+    assert_eq!(record["request_id"], request_id);
+    assert_eq!(record["status"], "succeeded");
+    assert_eq!(record["diagnostics"]["rssi"], -61);
+
+    let (status, listed) = server.get(&format!("/api/commands?device={}", device["uuid"].as_str().unwrap())).await;
+    assert_eq!(status, 200);
+    assert_eq!(listed[0]["request_id"], request_id);
+
+    // An unanswered command is reported as sent
+    let (status, record) = server.post("/api/commands?wait=0", json!({ "device": device["uuid"], "command": "identify" })).await;
+    assert_eq!(status, 202);
+    assert_eq!(record["status"], "sent");
+    let request_id = record["request_id"].as_str().unwrap().to_string();
+
+    // Neither an unregistered connection nor another device can answer it
+    let mut stranger = server.connect("/laden").await;
+    let mut other = server.sensor("/laden", "Fenster", "laden").await;
+    for socket in [&mut stranger, &mut other.socket] {
+        send_json(socket, &json!({ "request_id": request_id, "ok": false, "error": "forged" })).await;
+        let error = receive_json(socket).await.expect("a foreign answer should be reported");
+        assert_eq!(error["error"], format!("Not found: command {}", request_id));
+    }
+    let (_, record) = server.get(&format!("/api/commands/{}", request_id)).await;
+    assert_eq!(record["status"], "sent");
+
+    drop(sensor);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (status, _) = server.post("/api/commands", json!({ "device": device["uuid"], "command": "reboot" })).await;
+    assert_eq!(status, 409);
+}
+
+async fn telemetry_is_stored_and_warns_once_per_crossing(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut sensor = server.sensor("/laden", "Tür", "laden").await;
+
+    let reports = [
+        json!({ "rssi": -70, "free_heap": 30000, "uptime_s": 100, "reset_reason": "Power On", "firmware_version": "1.4.0" }),
+        json!({ "rssi": -90, "free_heap": 30000, "uptime_s": 160 }),
+        json!({ "rssi": -91, "free_heap": 30000, "uptime_s": 220 }),
+        json!({ "rssi": -60, "free_heap": 30000, "uptime_s": 5, "reset_reason": "Hardware Watchdog", "supply_voltage": 3.3 }),
+    ];
+    for report in reports {
+        sensor.send(json!({ "device": sensor.device, "telemetry": report })).await;
+    }
+
+    let from_device = |payload: &Value| payload["topic"] == "Alert-Net-Status" && payload["title"] == "Gerät Tür";
+    let crash = server.ntfy.notification_where(|payload| from_device(payload) && payload["message"].as_str().unwrap().contains("Absturz")).await
+        .expect("the crash should be notified");
+    assert_eq!(crash["message"], "Bereich: laden\nNeustart nach Absturz: Hardware Watchdog");
+
+    // The weak signal is reported when it drops below the threshold, not again while it stays there
+    let weak = server.ntfy.notification_where(|payload| from_device(payload) && payload["message"].as_str().unwrap().contains("WLAN")).await.unwrap();
+    assert_eq!(weak["message"], "Bereich: laden\nSchwaches WLAN-Signal: -90 dBm");
+    assert_eq!(server.ntfy.count_where(from_device), 2);
+
+    let (status, devices) = server.get("/api/devices").await;
+    assert_eq!(status, 200);
+    assert_eq!(devices[0]["uuid"], sensor.device["uuid"]);
+    assert_eq!(devices[0]["telemetry"]["rssi"], -60);
+    assert_eq!(devices[0]["telemetry"]["supply_voltage"], 3.3);
+
+    let (status, history) = server.get(&format!("/api/devices/{}/telemetry?limit=3", sensor.device["id"])).await;
+    assert_eq!(status, 200);
+    let rssi: Vec<&Value> = history.as_array().unwrap().iter().map(|telemetry| &telemetry["rssi"]).collect();
+    assert_eq!(rssi, [-60, -91, -90]);
+}
+
+async fn firmware_is_rolled_out_in_stages(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    let mut first = server.sensor("/laden", "Tür", "laden").await;
+    let mut second = server.sensor("/laden", "Fenster", "laden").await;
+
+    let image = vec![0xE9; 1024];
+    let upload = || reqwest::Client::new()
+        .post(server.url("/api/firmware?version=1.1.0&board=d1_mini"))
+        .bearer_auth(ADMIN_TOKEN)
+        .body(image.clone())
+        .send();
+    let response = upload().await.unwrap();
+    assert_eq!(response.status(), 200);
+    let firmware: Value = response.json().await.unwrap();
+    assert_eq!(firmware["size"], 1024);
+    assert_eq!(upload().await.unwrap().status(), 400);
+
+    let request = json!({ "firmware_id": firmware["id"], "devices": [first.device["uuid"], second.device["uuid"]], "batch_size": 1 });
+    let (status, rollout) = server.post("/api/firmware/rollouts", request).await;
+    assert_eq!(status, 200);
+    assert_eq!(rollout["updates"][0]["status"], "offered");
+    assert_eq!(rollout["updates"][1]["status"], "waiting");
+
+    let offer = first.receive().await.expect("the first stage should be offered the update");
+    assert_eq!(offer["type"], "update_available");
+    assert_eq!(offer["version"], "1.1.0");
+
+    let response = reqwest::get(offer["url"].as_str().unwrap()).await.unwrap();
+    assert_eq!(response.headers()["x-md5"], offer["md5"].as_str().unwrap());
+    assert_eq!(response.bytes().await.unwrap().as_ref(), image.as_slice());
+
+    for status in ["downloading", "succeeded"] {
+        first.send(json!({ "device": first.device, "rollout_id": rollout["id"], "status": status })).await;
+    }
+
+    let offer = second.receive().await.expect("the second stage should start when the first succeeded");
+    assert_eq!(offer["rollout_id"], rollout["id"]);
+
+    second.send(json!({ "device": second.device, "rollout_id": rollout["id"], "status": "failed", "error": "Prüfsumme falsch" })).await;
+
+    let failed = server.ntfy.notification_where(|payload| payload["title"] == "Firmware-Update fehlgeschlagen").await
+        .expect("a failed update should be notified");
+    assert_eq!(failed["message"], "Gerät: Fenster, Version: 1.1.0, Fehler: Prüfsumme falsch. Der Rollout ist angehalten.");
+
+    let (_, rollout) = server.get(&format!("/api/firmware/rollouts/{}", rollout["id"])).await;
+    assert_eq!(rollout["stage"], 2);
+    let statuses: Vec<&Value> = rollout["updates"].as_array().unwrap().iter().map(|update| &update["status"]).collect();
+    assert_eq!(statuses, ["succeeded", "failed"]);
+}
+
+async fn health_reports_storage_backend(backend: Backend) {
+    let server = Server::start(backend).await;
+    server.ready().await;
+
+    let (status, health) = server.get("/healthz").await;
+    assert_eq!(status, 200);
+    assert_eq!(health["status"], "ok");
+
+    let (status, ready) = server.get("/readyz").await;
+    assert_eq!(status, 200);
+    assert_eq!(ready["backend"], backend.name());
+}
+
+async fn api_requires_admin_token(backend: Backend) {
+    let server = Server::start(backend).await;
+    server.ready().await;
+
+    let client = reqwest::Client::new();
+
+    // Every route of the API, including the ones that were open before the token was required
+    let routes = [
+        ("GET", "/api/detections"),
+        ("GET", "/api/detections/counts"),
+        ("GET", "/api/areas"),
+        ("PUT", "/api/areas"),
+        ("GET", "/api/correlation"),
+        ("PUT", "/api/correlation"),
+        ("DELETE", "/api/correlation"),
+        ("GET", "/api/pre-alarms"),
+        ("GET", "/api/devices"),
+        ("GET", "/api/devices/noisy"),
+        ("GET", "/api/devices/config"),
+        ("GET", "/api/devices/1/config"),
+        ("PUT", "/api/devices/1/config"),
+        ("GET", "/api/devices/1/telemetry"),
+        ("GET", "/api/devices/pending"),
+        ("DELETE", "/api/devices/pending/1"),
+        ("POST", "/api/devices/pending/1/approve"),
+        ("GET", "/api/commands"),
+        ("POST", "/api/commands"),
+        ("GET", "/api/commands/1"),
+        ("GET", "/api/firmware"),
+        ("POST", "/api/firmware?version=1.1.0&board=d1_mini"),
+        ("GET", "/api/firmware/rollouts"),
+        ("POST", "/api/firmware/rollouts"),
+        ("GET", "/api/firmware/rollouts/1"),
+        ("POST", "/api/firmware/rollouts/1/advance"),
+        ("GET", "/api/export/detections"),
+        ("GET", "/api/export/incidents"),
+    ];
+    for (method, path) in routes {
+        let method = reqwest::Method::from_bytes(method.as_bytes()).unwrap();
+
+        let response = client.request(method.clone(), server.url(path)).json(&json!({})).send().await.unwrap();
+        assert_eq!(response.status(), 401, "{} {} without a token", method, path);
+        let response = client.request(method.clone(), server.url(path)).bearer_auth("wrong").json(&json!({})).send().await.unwrap();
+        assert_eq!(response.status(), 401, "{} {} with a wrong token", method, path);
+    }
+
+    let (status, _) = server.get("/api/devices").await;
+    assert_eq!(status, 200);
+
+    // Probes and the firmware download of the devices stay open
+    assert_eq!(client.get(server.url("/healthz")).send().await.unwrap().status(), 200);
+    assert_eq!(client.get(server.url("/api/firmware/1/image")).send().await.unwrap().status(), 404);
+}
+
+async fn metric_labels_ignore_areas_claimed_by_devices(backend: Backend) {
+    let server = Server::start(backend).await;
+
+    // The device claims an area the connection doesn't belong to
+    let mut socket = server.connect("/").await;
+    let device = json!({ "id": 1, "uuid": "00000000-0000-0000-0000-000000000000", "description": "Tür", "area": "erfunden" });
+    send_json(&mut socket, &json!({ "device": device, "source": "Bewegung" })).await;
+    server.ntfy.notification("Alert-Net-erfunden").await.expect("detection of an unknown area should alert anyway");
+
+    let metrics = reqwest::get(server.url("/metrics")).await.unwrap().text().await.unwrap();
+    assert!(metrics.contains(r#"alert_net_detections_total{area="unknown",kind="motion"} 1"#), "{}", metrics);
+    assert!(!metrics.contains("erfunden"));
+}
+
+async fn shutdown_closes_clients_and_stops_http(backend: Backend) {
+    let mut server = Server::start(backend).await;
+
+    let mut ui = server.connect("/ui").await;
+    send_json(&mut ui, &json!({ "subscribe": { "events": ["status"] } })).await;
+    receive_where(&mut ui, |message| message.get("subscriptions").is_some()).await.expect("the subscription should be confirmed");
+    let mut siren = server.connect("/laden").await;
+
+    let status = server.interrupt().await;
+    assert!(status.success(), "{}", status);
+
+    let stopping = receive_where(&mut ui, |message| message["event"] == "status").await.expect("the shutdown should be announced");
+    assert_eq!(stopping["message"], "Alert Net server wird beendet");
+
+    // Every client is asked to close its connection, also the ones without a topic
+    for socket in [&mut ui, &mut siren] {
+        let close = loop {
+            match tokio::time::timeout(Duration::from_secs(2), socket.next()).await {
+                Ok(Some(Ok(Message::Close(close)))) => break close,
+                Ok(Some(Ok(_))) => continue,
+                other => panic!("expected a close frame, got {:?}", other),
+            }
+        };
+        assert_eq!(close.unwrap().reason, "Server wird beendet");
+    }
+
+    server.ntfy.notification_where(|payload| payload["message"] == "Alert Net server beendet").await
+        .expect("the shutdown should be notified");
+    assert!(reqwest::get(server.url("/healthz")).await.is_err(), "the HTTP server should be stopped");
+}
+
+#[tokio::test]
+async fn readiness_fails_with_a_pending_migration() {
+    let server = Server::start(Backend::Sqlite).await;
+    server.ready().await;
+
+    let (status, _) = server.get("/readyz").await;
+    assert_eq!(status, 200);
+
+    // As if the database had been restored from a backup taken before the last migration
+    let url = server.database.as_ref().unwrap().url();
+    let pool = SqlitePool::connect(&url).await.unwrap();
+    let latest: i64 = sqlx::query_scalar("SELECT max(version) FROM _sqlx_migrations").fetch_one(&pool).await.unwrap();
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = ?").bind(latest).execute(&pool).await.unwrap();
+    pool.close().await;
+
+    let (status, ready) = server.get("/readyz").await;
+    assert_eq!(status, 503);
+    assert_eq!(ready["status"], "unavailable");
+    assert_eq!(ready["database"]["ok"], true);
+    assert_eq!(ready["migrations"]["ok"], false);
+    assert_eq!(ready["migrations"]["error"], format!("Pending migrations: [{}]", latest));
+}
+
+#[tokio::test]
+async fn export_command_writes_detections_and_incidents() {
+    let server = Server::start_with(Backend::Sqlite, &[("DETECTION_DEBOUNCE_SECONDS", "")]).await;
+
+    let mut door = server.sensor("/laden", "Tür", "laden").await;
+    let mut hall = server.sensor("/lager", "Halle", "lager").await;
+    door.detect("pir").await;
+    hall.detect("Glasbruch").await;
+    server.get_until("/api/detections", |page| page["detections"].as_array().unwrap().len() == 2).await;
+
+    // Reads the database of the running server, like an admin on the same host
+    let url = server.database.as_ref().unwrap().url();
+    let export = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_alert_net_server"))
+            .arg("export")
+            .args(args)
+            .env("DATABASE_URL", &url)
+            .env("LOG_LEVEL", "warn")
+            .env_remove("RUST_LOG")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let csv = export(&["--areas", "Laden", "--from", "2000-01-01T00:00:00Z"]);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", csv);
+    assert!(lines[1].contains(",motion,") && lines[1].contains(",Tür,laden,"), "{}", lines[1]);
+
+    let header = export(&["--to", "2000-01-01T00:00:00Z"]);
+    assert_eq!(header, "id,timestamp,source,device_uuid,device_description,area,area_name,count,state\n");
+
+    let path = std::env::temp_dir().join(format!("alert-net-incidents-{}.jsonl", std::process::id()));
+    let stdout = export(&["--records", "incidents", "--format", "jsonl", "--output", path.to_str().unwrap()]);
+    assert_eq!(stdout, "");
+    let jsonl = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let mut alarms: Vec<(String, String)> = jsonl.lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .map(|incident| (incident["area"].as_str().unwrap().to_string(), incident["source"].as_str().unwrap().to_string()))
+        .collect();
+    alarms.sort();
+    assert_eq!(alarms, [("laden".to_string(), "motion".to_string()), ("lager".to_string(), "glass_break".to_string())]);
+
+    // A storage that can't be opened fails the command instead of writing an empty export
+    let output = Command::new(env!("CARGO_BIN_EXE_alert_net_server"))
+        .args(["export", "--records", "incidents"])
+        .env("DATABASE_URL", "mysql://localhost/alertnet")
+        .env("LOG_LEVEL", "warn")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[tokio::test]
+async fn area_slugs_of_old_devices_are_normalized_once() {
+    let database = TempDatabase::create(Backend::Sqlite).await.unwrap();
+
+    // Devices of the version before the area table, their area was free text
+    let options = SqliteConnectOptions::from_str(&database.url()).unwrap().create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await.unwrap();
+    let mut migrator = sqlx::migrate!("../migrations_sqlite");
+    migrator.migrations = migrator.migrations.iter().filter(|migration| migration.version == 1).cloned().collect();
+    migrator.run(&pool).await.unwrap();
+
+    for (description, area) in [("Alt", "KÜCHE"), ("Herd", "küche"), ("Treppe", "/Flur/")] {
+        sqlx::query("INSERT INTO device (uuid, area, description) VALUES (?1, ?2, ?3)")
+            .bind(uuid::Uuid::new_v4())
+            .bind(area)
+            .bind(description)
+            .execute(&pool).await.unwrap();
+    }
+
+    let server = Server::start_on(database).await;
+    let sensor = server.sensor("/K%C3%BCche", "Spüle", "Küche").await;
+    assert_eq!(sensor.device["area"], "küche");
+
+    // The SQL backfill made `kÜche` of the first device, now every device of the kitchen is in one area
+    let areas: Vec<(i64, String)> = sqlx::query_as("SELECT id, slug FROM area ORDER BY slug").fetch_all(&pool).await.unwrap();
+    let slugs: Vec<&str> = areas.iter().map(|(_, slug)| slug.as_str()).collect();
+    assert_eq!(slugs, ["flur", "küche"]);
+
+    let kitchen: Vec<i64> = sqlx::query_scalar("SELECT DISTINCT area_id FROM device WHERE description <> 'Treppe'").fetch_all(&pool).await.unwrap();
+    assert_eq!(kitchen, [areas[1].0]);
+
+    let steps: Vec<String> = sqlx::query_scalar("SELECT name FROM data_migration").fetch_all(&pool).await.unwrap();
+    assert!(steps.contains(&"area_slugs".to_string()), "{:?}", steps);
+    pool.close().await;
+}
+
+#[tokio::test]
+async fn sources_of_old_detections_are_normalized_once() {
+    let database = TempDatabase::create(Backend::Sqlite).await.unwrap();
+
+    // Migration 0013 left `tür` as it was, `lower()` of SQLite only folds ASCII
+    let options = SqliteConnectOptions::from_str(&database.url()).unwrap().create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await.unwrap();
+    let mut migrator = sqlx::migrate!("../migrations_sqlite");
+    migrator.migrations = migrator.migrations.iter().filter(|migration| migration.version <= 13).cloned().collect();
+    migrator.run(&pool).await.unwrap();
+
+    let insert_detection = |source: &'static str| {
+        sqlx::query("INSERT INTO detection (device_id, source, timestamp) VALUES (1, ?1, '2026-10-01T12:00:00+00:00')")
+            .bind(source)
+            .execute(&pool)
+    };
+    sqlx::query("INSERT INTO area (slug, name) VALUES ('laden', 'Laden')").execute(&pool).await.unwrap();
+    sqlx::query("INSERT INTO device (uuid, description, area_id) VALUES (?1, 'Tür', 1)")
+        .bind(uuid::Uuid::new_v4())
+        .execute(&pool).await.unwrap();
+    insert_detection("TÜR").await.unwrap();
+
+    let server = Server::start_on(database).await;
+    server.ready().await;
+    let (_, history) = server.get("/api/detections").await;
+    assert_eq!(history["detections"][0]["source"], "contact");
+
+    // Recorded as done, a later start doesn't scan the sources again
+    let steps: Vec<String> = sqlx::query_scalar("SELECT name FROM data_migration ORDER BY name").fetch_all(&pool).await.unwrap();
+    assert_eq!(steps, ["area_slugs", "sources"]);
+
+    insert_detection("TÜR").await.unwrap();
+    let server = server.restart().await;
+    server.ready().await;
+    let sources: Vec<String> = sqlx::query_scalar("SELECT source FROM detection ORDER BY id").fetch_all(&pool).await.unwrap();
+    assert_eq!(sources, ["contact", "TÜR"]);
+    pool.close().await;
+}
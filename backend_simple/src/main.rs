@@ -1,9 +1,10 @@
+mod route;
+
 use std::{env,};
 use std::error::Error;
 use std::net::SocketAddr;
 use std::sync::{Arc};
 use dotenv::dotenv;
-use futures::executor::block_on;
 use chrono::Utc;
 
 use futures_channel::mpsc::{unbounded, UnboundedSender};
@@ -13,17 +14,20 @@ use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex;
 use tokio_tungstenite::tungstenite::protocol::Message;
 use tungstenite::{handshake::server::{ErrorResponse, Request, Response}, http::Uri};
+use crate::route::Route;
 
 #[derive(Clone)]
 struct Client {
     socket_addr: SocketAddr,
     tx: Tx,
     uri: Uri,
+    route: Route,
 }
 
 type Tx = UnboundedSender<Message>;
 type PeerMap = Arc<Mutex<Vec<Client>>>;
 
+#[allow(clippy::result_large_err)]
 async fn handle_connection(peer_map: PeerMap, raw_stream: TcpStream, addr: SocketAddr) {
     println!("Incoming TCP connection from: {}", addr);
 
@@ -35,71 +39,90 @@ async fn handle_connection(peer_map: PeerMap, raw_stream: TcpStream, addr: Socke
         device_uri = Some(uri.clone());
 
         for (name, value) in request.headers().iter() {
-            println!("Name: {}, value: {}", name, value.to_str().expect("expected a value"));
+            println!("Name: {}, value: {}", name, value.to_str().unwrap_or("<binary>"));
         }
 
         Ok(response)
     };
 
-    let ws_stream = tokio_tungstenite::accept_hdr_async(raw_stream, copy_headers_callback)
-        .await
-        .expect("Error during the websocket handshake occurred");
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(raw_stream, copy_headers_callback).await {
+        Ok(ws_stream) => ws_stream,
+        Err(err) => {
+            println!("Error during the websocket handshake with {}: {}", addr, err);
+            return;
+        }
+    };
     println!("WebSocket connection established: {}", addr);
 
+    // Set by the handshake callback, which runs for every accepted handshake
+    let Some(uri) = device_uri else {
+        println!("No URI in the handshake of {}", addr);
+        return;
+    };
+
 
     // Insert the write part of this peer to the peer map.
     let (tx, rx) = unbounded();
 
+    let route = Route::parse(&uri.to_string());
+
     let client = Client {
         socket_addr: addr,
         tx,
-        uri: device_uri.unwrap(),
+        uri,
+        route,
     };
 
     let temp_client = client.clone();
-    let all_uri = Uri::from_static("all");
 
     peer_map.lock().await.push(client);
 
     let (outgoing, incoming) = ws_stream.split();
 
     let broadcast_incoming = incoming.try_for_each(|msg| {
-        let timestamp = Utc::now();
-        print!("{} - {} - URI: {}: ", timestamp.format("%Y-%m-%d - %H:%M:%S"), temp_client.socket_addr, temp_client.uri);
-
-        match msg {
-            Message::Ping(_ping) => {
-                println!("Ping");
-            },
-            Message::Pong(_pong) => {
-                println!("Pong");
-            },
-            Message::Binary(_binary) => {
-                println!("Binary");
-            },
-            Message::Text(text) => {
-                println!("Client: {}, URI: {}, Message: {}", temp_client.socket_addr.clone(), temp_client.uri.clone(), text);
-
-                let peers = block_on(peer_map.lock());
-
-                if text.clone().eq("alert") {
-                    let broadcast_recipients: Vec<&Client> = peers.iter().filter(|c| (c.uri == temp_client.uri || c.uri == all_uri)).collect();
-
-                    let mes = Message::text(text.clone());
-                    for recipient in broadcast_recipients {
-                        recipient.tx.unbounded_send(mes.clone()).unwrap();
+        let peer_map = peer_map.clone();
+        let temp_client = temp_client.clone();
+
+        async move {
+            let timestamp = Utc::now();
+            print!("{} - {} - URI: {}: ", timestamp.format("%Y-%m-%d - %H:%M:%S"), temp_client.socket_addr, temp_client.uri);
+
+            match msg {
+                Message::Ping(_ping) => {
+                    println!("Ping");
+                },
+                Message::Pong(_pong) => {
+                    println!("Pong");
+                },
+                Message::Binary(_binary) => {
+                    println!("Binary");
+                },
+                Message::Text(text) => {
+                    println!("Client: {}, URI: {}, Message: {}", temp_client.socket_addr.clone(), temp_client.uri.clone(), text);
+
+                    let peers = peer_map.lock().await;
+
+                    if text.clone().eq("alert") {
+                        let broadcast_recipients: Vec<&Client> = peers.iter().filter(|c| c.route.receives(&temp_client.route)).collect();
+
+                        let mes = Message::text(text.clone());
+                        for recipient in broadcast_recipients {
+                            if let Err(err) = recipient.tx.unbounded_send(mes.clone()) {
+                                println!("Could not send to {}: {}", recipient.socket_addr, err);
+                            }
+                        }
                     }
+                },
+                Message::Close(_close) => {
+                    println!("Close");
+                },
+                Message::Frame(_frame) => {
+                    println!("Frame");
                 }
-            },
-            Message::Close(_close) => {
-                println!("Close");
-            },
-            Message::Frame(_frame) => {
-                println!("Frame");
             }
-        }
 
-        future::ok(())
+            Ok(())
+        }
     });
 
     let receive_from_others = rx.map(Ok).forward(outgoing);
@@ -108,8 +131,10 @@ async fn handle_connection(peer_map: PeerMap, raw_stream: TcpStream, addr: Socke
     future::select(broadcast_incoming, receive_from_others).await;
 
     println!("{} disconnected", &addr);
-    let index = peer_map.lock().await.iter().position(|c| c.socket_addr == addr).unwrap();
-    peer_map.lock().await.remove(index);
+    let mut peers = peer_map.lock().await;
+    if let Some(index) = peers.iter().position(|c| c.socket_addr == addr) {
+        peers.remove(index);
+    }
 }
 
 #[tokio::main]
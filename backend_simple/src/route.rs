@@ -0,0 +1,62 @@
+/// The alert group of a client, derived from the path it connected with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// `/all`, receives the alerts of every area.
+    All,
+    /// Any other path, receives the alerts of that area.
+    Area(String),
+}
+
+impl Route {
+    /// Parses a connection path like the main server does. Query strings, empty segments and trailing slashes
+    /// are ignored, percent-encoded characters are decoded and the result is lowercase, so `/Laden/?id=1` is `/laden`.
+    pub fn parse(path: &str) -> Route {
+        let segments = normalized_segments(path);
+
+        match segments.as_slice() {
+            [all] if all == "all" => Route::All,
+            _ => Route::Area(segments.join("/")),
+        }
+    }
+
+    /// Checks if a client on this route hears an alert sent from the origin route.
+    pub fn receives(&self, origin: &Route) -> bool {
+        self == origin || *self == Route::All
+    }
+}
+
+/// Splits a path into lowercase, percent-decoded segments, dropping the query string and empty segments.
+/// Kept in line with `normalized_segments` of the main server, so both agree on the area of a path.
+fn normalized_segments(path: &str) -> Vec<String> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+
+    path.split('/')
+        .map(|segment| percent_decode(segment.trim()).to_lowercase())
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let hex = bytes.get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
@@ -0,0 +1,131 @@
+use std::net::TcpListener;
+use std::process::{Child, Command};
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Runs the server binary on a free port and stops it when dropped.
+struct Server {
+    process: Child,
+    port: u16,
+}
+
+impl Server {
+    fn start() -> Server {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+
+        let process = Command::new(env!("CARGO_BIN_EXE_alert_net_server_simple"))
+            .env("SERVER_ADDRESS", "127.0.0.1")
+            .env("SERVER_PORT", port.to_string())
+            .spawn()
+            .expect("server binary should start");
+
+        Server { process, port }
+    }
+
+    async fn connect(&self, path: &str) -> Socket {
+        let url = format!("ws://127.0.0.1:{}{}", self.port, path);
+
+        for _ in 0..50 {
+            if let Ok((socket, _)) = connect_async(url.as_str()).await {
+                // Give the server time to add the client to the peer map
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                return socket;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!("could not connect to {}", url);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+async fn receives_alert(socket: &mut Socket) -> bool {
+    match tokio::time::timeout(Duration::from_millis(500), socket.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => text == "alert",
+        _ => false,
+    }
+}
+
+#[tokio::test]
+async fn alerts_reach_same_area_and_all_routes() {
+    let server = Server::start();
+
+    let mut sender = server.connect("/laden").await;
+    let mut same_area = server.connect("/Laden/?device=2").await;
+    let mut all = server.connect("/all").await;
+    let mut all_variant = server.connect("/ALL/?token=abc").await;
+    let mut other_area = server.connect("/kino").await;
+
+    sender.send(Message::text("alert")).await.unwrap();
+
+    assert!(receives_alert(&mut sender).await);
+    assert!(receives_alert(&mut same_area).await);
+    assert!(receives_alert(&mut all).await);
+    assert!(receives_alert(&mut all_variant).await);
+    assert!(!receives_alert(&mut other_area).await);
+}
+
+#[tokio::test]
+async fn alerts_from_all_route_stay_with_all_clients() {
+    let server = Server::start();
+
+    let mut sender = server.connect("/all").await;
+    let mut area = server.connect("/laden").await;
+
+    sender.send(Message::text("alert")).await.unwrap();
+
+    assert!(receives_alert(&mut sender).await);
+    assert!(!receives_alert(&mut area).await);
+}
+
+#[tokio::test]
+async fn alerts_reach_root_route_clients() {
+    let server = Server::start();
+
+    let mut sender = server.connect("/").await;
+    let mut root = server.connect("//?x=1").await;
+    let mut area = server.connect("/laden").await;
+
+    sender.send(Message::text("alert")).await.unwrap();
+
+    assert!(receives_alert(&mut root).await);
+    assert!(!receives_alert(&mut area).await);
+}
+
+#[tokio::test]
+async fn other_messages_are_not_broadcast() {
+    let server = Server::start();
+
+    let mut sender = server.connect("/laden").await;
+    let mut all = server.connect("/all").await;
+
+    sender.send(Message::text("Connected")).await.unwrap();
+
+    assert!(!receives_alert(&mut all).await);
+}
+
+#[tokio::test]
+async fn percent_encoded_paths_are_the_same_area() {
+    let server = Server::start();
+
+    let mut sender = server.connect("/K%C3%9CCHE").await;
+    let mut decoded_area = server.connect("/k%c3%bcche/?device=2").await;
+    let mut space = server.connect("/lager%20nord").await;
+
+    sender.send(Message::text("alert")).await.unwrap();
+
+    assert!(receives_alert(&mut decoded_area).await);
+    assert!(!receives_alert(&mut space).await);
+}
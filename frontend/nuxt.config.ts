@@ -10,6 +10,13 @@ export default defineNuxtConfig({
 
   ssr: false,
 
+  // HTTP API of the backend, served on its HTTP_PORT and forwarded by server/api/[...path].ts.
+  // Set at runtime with NUXT_API_URL and NUXT_ADMIN_TOKEN, the token never reaches the browser
+  runtimeConfig: {
+    apiUrl: 'http://192.168.0.76:3001',
+    adminToken: '',
+  },
+
   devtools: { enabled: true },
 
   // typescripts
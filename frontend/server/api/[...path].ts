@@ -0,0 +1,8 @@
+// Forwards /api to the backend with its ADMIN_TOKEN
+export default defineEventHandler((event) => {
+  const config = useRuntimeConfig(event)
+
+  return proxyRequest(event, config.apiUrl + event.path, {
+    headers: { Authorization: `Bearer ${config.adminToken}` },
+  })
+})
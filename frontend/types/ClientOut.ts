@@ -1,9 +1,39 @@
+export class Subscription {
+  type: "node" | "subtree";
+  area_id: number | null;
+
+  constructor(type: "node" | "subtree", area_id: number | null) {
+    this.type = type;
+    this.area_id = area_id;
+  }
+}
+
+export type EventType = "detection" | "status" | "device" | "prealarm";
+
+export class Topic {
+  event: EventType;
+  subscription: Subscription;
+
+  constructor(event: EventType, subscription: Subscription) {
+    this.event = event;
+    this.subscription = subscription;
+  }
+}
+
 export class ClientOut {
   socket_addr: string;
   uri: string;
+  area_id: number | null;
+  topics: Array<Topic>;
+  device_id: number | null;
+  flooding: boolean;
 
-  constructor(socket_addr: string, uri: string) {
+  constructor(socket_addr: string, uri: string, area_id: number | null, topics: Array<Topic>, device_id: number | null = null, flooding: boolean = false) {
     this.socket_addr = socket_addr;
     this.uri = uri;
+    this.area_id = area_id;
+    this.topics = topics;
+    this.device_id = device_id;
+    this.flooding = flooding;
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,42 @@
+export type CommandKind = "reboot" | "identify" | "self_test" | "diagnostics";
+
+export type CommandStatus = "sent" | "succeeded" | "failed";
+
+export class Diagnostics {
+  free_heap: number;
+  rssi: number;
+  uptime_s: number;
+  firmware_version: string;
+
+  constructor(free_heap: number, rssi: number, uptime_s: number, firmware_version: string) {
+    this.free_heap = free_heap;
+    this.rssi = rssi;
+    this.uptime_s = uptime_s;
+    this.firmware_version = firmware_version;
+  }
+}
+
+export class CommandRecord {
+  request_id: string;
+  device_id: number;
+  device_uuid: string;
+  command: CommandKind;
+  status: CommandStatus;
+  sent_at: string;
+  answered_at: string | null;
+  error: string | null;
+  diagnostics: Diagnostics | null;
+
+  constructor(request_id: string, device_id: number, device_uuid: string, command: CommandKind, status: CommandStatus,
+              sent_at: string, answered_at: string | null, error: string | null, diagnostics: Diagnostics | null) {
+    this.request_id = request_id;
+    this.device_id = device_id;
+    this.device_uuid = device_uuid;
+    this.command = command;
+    this.status = status;
+    this.sent_at = sent_at;
+    this.answered_at = answered_at;
+    this.error = error;
+    this.diagnostics = diagnostics;
+  }
+}
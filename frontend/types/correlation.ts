@@ -0,0 +1,46 @@
+export class CorrelationRule {
+  area_id: number;
+  area: string;
+  min_detections: number | null;
+  min_devices: number;
+  window_s: number;
+  first_source: string | null;
+  then_source: string | null;
+  updated_at: string;
+
+  constructor(area_id: number, area: string, min_detections: number | null, min_devices: number, window_s: number,
+              first_source: string | null, then_source: string | null, updated_at: string) {
+    this.area_id = area_id;
+    this.area = area;
+    this.min_detections = min_detections;
+    this.min_devices = min_devices;
+    this.window_s = window_s;
+    this.first_source = first_source;
+    this.then_source = then_source;
+    this.updated_at = updated_at;
+  }
+}
+
+// A detection that didn't satisfy the correlation rule of its area yet.
+export class PreAlarm {
+  id: number;
+  area_id: number;
+  area: string;
+  device_id: number;
+  source: string;
+  timestamp: string;
+  detections: number;
+  devices: number;
+
+  constructor(id: number, area_id: number, area: string, device_id: number, source: string, timestamp: string,
+              detections: number, devices: number) {
+    this.id = id;
+    this.area_id = area_id;
+    this.area = area;
+    this.device_id = device_id;
+    this.source = source;
+    this.timestamp = timestamp;
+    this.detections = detections;
+    this.devices = devices;
+  }
+}
@@ -1,15 +1,27 @@
 import {Device} from "~/types/device";
 
+export type SensorKind = "motion" | "contact" | "glass_break" | "smoke" | "water_leak" | "panic_button" | "tamper" | "unknown";
+
+export type ContactState = "open" | "closed";
+
 export class Detection {
   id: number;
   device: Device;
+  // Normalized by the server, unknown sources are kept in lower case.
   source: string;
+  kind: SensorKind;
+  state: ContactState | null;
   timestamp: string;
+  count: number;
 
-  constructor(id: number, device: Device, source: string, timestamp: string) {
+  constructor(id: number, device: Device, source: string, timestamp: string, count: number = 1,
+              kind: SensorKind = "unknown", state: ContactState | null = null) {
     this.id = id;
     this.device = device;
     this.source = source;
+    this.kind = kind;
+    this.state = state;
     this.timestamp = timestamp;
+    this.count = count;
   }
 }
@@ -1,11 +1,16 @@
 import {Device} from "~/types/device";
+import {ContactState} from "~/types/detection";
 
 export class DetectionMessage {
   device: Device;
   source: string;
+  state?: ContactState;
+  token?: string;
 
-  constructor(device: Device, source: string) {
+  constructor(device: Device, source: string, token?: string, state?: ContactState) {
     this.device = device;
     this.source = source;
+    this.token = token;
+    this.state = state;
   }
 }
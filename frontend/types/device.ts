@@ -3,11 +3,13 @@ export class Device {
   uuid: string;
   description: string;
   area: string;
+  area_id: number;
 
-  constructor(id: number, uuid: string, description: string, area: string) {
+  constructor(id: number, uuid: string, description: string, area: string, area_id: number = 0) {
     this.id = id;
     this.uuid = uuid;
     this.description = description;
     this.area = area;
+    this.area_id = area_id;
   }
 }
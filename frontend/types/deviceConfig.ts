@@ -0,0 +1,29 @@
+export class DeviceConfig {
+  device_id: number;
+  version: number;
+  description: string;
+  area: string;
+  speaker_volume: number | null;
+  motion_debounce_ms: number | null;
+  heartbeat_interval_s: number | null;
+  server_address: string | null;
+  updated_at: string;
+  applied_version: number | null;
+  applied_at: string | null;
+
+  constructor(device_id: number, version: number, description: string, area: string, speaker_volume: number | null,
+              motion_debounce_ms: number | null, heartbeat_interval_s: number | null, server_address: string | null,
+              updated_at: string, applied_version: number | null, applied_at: string | null) {
+    this.device_id = device_id;
+    this.version = version;
+    this.description = description;
+    this.area = area;
+    this.speaker_volume = speaker_volume;
+    this.motion_debounce_ms = motion_debounce_ms;
+    this.heartbeat_interval_s = heartbeat_interval_s;
+    this.server_address = server_address;
+    this.updated_at = updated_at;
+    this.applied_version = applied_version;
+    this.applied_at = applied_at;
+  }
+}
@@ -0,0 +1,57 @@
+export class Firmware {
+  id: number;
+  version: string;
+  board: string;
+  size: number;
+  sha256: string;
+  md5: string;
+  uploaded_at: string;
+
+  constructor(id: number, version: string, board: string, size: number, sha256: string, md5: string, uploaded_at: string) {
+    this.id = id;
+    this.version = version;
+    this.board = board;
+    this.size = size;
+    this.sha256 = sha256;
+    this.md5 = md5;
+    this.uploaded_at = uploaded_at;
+  }
+}
+
+export type UpdateStatus = "waiting" | "offered" | "downloading" | "succeeded" | "failed";
+
+export class FirmwareUpdate {
+  rollout_id: number;
+  device_id: number;
+  stage: number;
+  status: UpdateStatus;
+  error: string | null;
+  updated_at: string;
+
+  constructor(rollout_id: number, device_id: number, stage: number, status: UpdateStatus, error: string | null, updated_at: string) {
+    this.rollout_id = rollout_id;
+    this.device_id = device_id;
+    this.stage = stage;
+    this.status = status;
+    this.error = error;
+    this.updated_at = updated_at;
+  }
+}
+
+export class Rollout {
+  id: number;
+  firmware: Firmware;
+  batch_size: number;
+  stage: number;
+  created_at: string;
+  updates: FirmwareUpdate[];
+
+  constructor(id: number, firmware: Firmware, batch_size: number, stage: number, created_at: string, updates: FirmwareUpdate[]) {
+    this.id = id;
+    this.firmware = firmware;
+    this.batch_size = batch_size;
+    this.stage = stage;
+    this.created_at = created_at;
+    this.updates = updates;
+  }
+}
@@ -0,0 +1,17 @@
+export class PendingDevice {
+  id: number;
+  description: string;
+  area: string;
+  hardware_id: string | null;
+  request_id: string | null;
+  requested_at: string;
+
+  constructor(id: number, description: string, area: string, hardware_id: string | null, request_id: string | null, requested_at: string) {
+    this.id = id;
+    this.description = description;
+    this.area = area;
+    this.hardware_id = hardware_id;
+    this.request_id = request_id;
+    this.requested_at = requested_at;
+  }
+}
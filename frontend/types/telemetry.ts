@@ -0,0 +1,36 @@
+import {Device} from "~/types/device";
+
+export class Telemetry {
+  id: number;
+  device_id: number;
+  timestamp: string;
+  rssi: number | null;
+  free_heap: number | null;
+  uptime_s: number | null;
+  reset_reason: string | null;
+  firmware_version: string | null;
+  supply_voltage: number | null;
+
+  constructor(id: number, device_id: number, timestamp: string, rssi: number | null, free_heap: number | null, uptime_s: number | null,
+              reset_reason: string | null, firmware_version: string | null, supply_voltage: number | null) {
+    this.id = id;
+    this.device_id = device_id;
+    this.timestamp = timestamp;
+    this.rssi = rssi;
+    this.free_heap = free_heap;
+    this.uptime_s = uptime_s;
+    this.reset_reason = reset_reason;
+    this.firmware_version = firmware_version;
+    this.supply_voltage = supply_voltage;
+  }
+}
+
+// A device from /api/devices with the latest telemetry it sent.
+export class DeviceStatus extends Device {
+  telemetry: Telemetry | null;
+
+  constructor(id: number, uuid: string, description: string, area: string, area_id: number, telemetry: Telemetry | null) {
+    super(id, uuid, description, area, area_id);
+    this.telemetry = telemetry;
+  }
+}
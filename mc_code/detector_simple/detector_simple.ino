@@ -365,7 +365,7 @@ void setup() {
 	// server address, port and URL
   char uri [52];
   strcpy(uri, "/");
-  strcpy(uri, device_area);
+  strncat(uri, device_area, sizeof(uri) - 2);
 
 	webSocket.begin(server_ip, server_port, uri);
 
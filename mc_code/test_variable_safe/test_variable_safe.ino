@@ -21,6 +21,7 @@ void printAudioDetail(uint8_t type, int value);
 #include <ESP8266WiFi.h>
 #include <WiFiManager.h>
 #include <WebSocketsClient.h>
+#include <ESP8266httpUpdate.h>
 
 // Wifi:
 //WiFiManager wifi_manager;
@@ -35,6 +36,13 @@ unsigned long messageInterval = 5000;
 unsigned long lastUpdate = millis();
 bool connected = false;
 
+#define FIRMWARE_VERSION "1.0.0"
+
+// Telemetry:
+#define TELEMETRY_INTERVAL_MS 60000
+unsigned long lastTelemetry = 0;
+ADC_MODE(ADC_VCC); // ESP.getVcc() measures the supply voltage, A0 is not used
+
 // Json:
 JsonDocument doc;
 bool led = false;
@@ -44,6 +52,7 @@ bool reboot = false;
 // Motion:
 int motionPin = D1;
 int pirState = LOW;
+unsigned long lastMotion = 0;
 
 // Config:
 #define JSON_CONFIG_FILE "/config.json"
@@ -58,7 +67,12 @@ uint16_t device_id = 0;
 char device_uuid[50] = "to_register";
 char device_area[50] = "test";
 char device_description[50] = "Gerät 1";
+char device_token[80] = ""; // Issued by the server on approval, see DEVICE_APPROVAL
 uint16_t device_speaker_volume = 15; // Speaker volume. Set between 0 and 30.
+uint32_t device_motion_debounce_ms = 0; // Ignore motion this long after a detection.
+uint16_t device_heartbeat_interval_s = 15;
+long device_config_version = 0; // Version of the last config pushed by the server.
+long update_rollout_id = 0; // Rollout of the firmware being installed, reported as succeeded after the restart.
 
 void saveConfigFile() {
   Serial.println(F("Saving configuration..."));
@@ -70,7 +84,12 @@ void saveConfigFile() {
   config["device"]["uuid"] = device_uuid;
   config["device"]["area"] = device_area;
   config["device"]["description"] = device_description;
+  config["device"]["token"] = device_token;
   config["device"]["speaker_volume"] = device_speaker_volume;
+  config["device"]["motion_debounce_ms"] = device_motion_debounce_ms;
+  config["device"]["heartbeat_interval_s"] = device_heartbeat_interval_s;
+  config["device"]["config_version"] = device_config_version;
+  config["device"]["update_rollout_id"] = update_rollout_id;
 
   File configFile = SPIFFS.open(JSON_CONFIG_FILE, "w");
   if (!configFile) {
@@ -113,7 +132,12 @@ bool loadConfigFile() {
           strcpy(device_uuid, config["device"]["uuid"]);
           strcpy(device_area, config["device"]["area"]);
           strcpy(device_description, config["device"]["description"]);
+          strlcpy(device_token, config["device"]["token"] | "", sizeof(device_token));
           device_speaker_volume = config["device"]["speaker_volume"].as<uint16_t>();
+          device_motion_debounce_ms = config["device"]["motion_debounce_ms"] | 0;
+          device_heartbeat_interval_s = config["device"]["heartbeat_interval_s"] | 15;
+          device_config_version = config["device"]["config_version"] | 0;
+          update_rollout_id = config["device"]["update_rollout_id"] | 0;
 
           return true;
         } else {
@@ -246,6 +270,11 @@ void updateDeviceConfigFromServer(uint8_t * text) {
   DeserializationError error = deserializeJson(config, text);
 
   if (!error) {
+    if (config["status"] == "pending") {
+      Serial.print("Waiting for approval, pairing code: ");
+      Serial.println(config["pairing_code"].as<const char*>());
+    }
+
     if (config.containsKey("uuid")) {
       Serial.println("Updated device config:");
       serializeJsonPretty(config, Serial);
@@ -254,6 +283,10 @@ void updateDeviceConfigFromServer(uint8_t * text) {
       strcpy(device_uuid, config["uuid"]);
       strcpy(device_area, config["area"]);
       strcpy(device_description, config["description"]);
+      // Only sent once, later registrations keep the saved token
+      if (config.containsKey("token")) {
+        strlcpy(device_token, config["token"], sizeof(device_token));
+      }
 
       saveConfigFile();
     }
@@ -262,6 +295,196 @@ void updateDeviceConfigFromServer(uint8_t * text) {
   }
 }
 
+// Tells the server which config version runs, it pushes a newer one if there is one.
+void sendConfigAck() {
+  JsonDocument doc;
+
+  doc["device"]["id"] = device_id;
+  doc["device"]["uuid"] = device_uuid;
+  doc["device"]["description"] = device_description;
+  doc["device"]["area"] = device_area;
+  doc["config_version"] = device_config_version;
+  if (strlen(device_token) > 0) {
+    doc["token"] = device_token;
+  }
+
+  String output = "";
+  serializeJsonPretty(doc, output);
+
+  webSocket.sendTXT(output);
+}
+
+// Signal, memory, uptime and reset reason, the server warns if a value gets too low.
+void sendTelemetry() {
+  JsonDocument doc;
+
+  doc["device"]["id"] = device_id;
+  doc["device"]["uuid"] = device_uuid;
+  doc["device"]["description"] = device_description;
+  doc["device"]["area"] = device_area;
+  doc["telemetry"]["rssi"] = WiFi.RSSI();
+  doc["telemetry"]["free_heap"] = ESP.getFreeHeap();
+  doc["telemetry"]["uptime_s"] = millis() / 1000;
+  doc["telemetry"]["reset_reason"] = ESP.getResetReason();
+  doc["telemetry"]["firmware_version"] = FIRMWARE_VERSION;
+  doc["telemetry"]["supply_voltage"] = ESP.getVcc() / 1000.0;
+  if (strlen(device_token) > 0) {
+    doc["token"] = device_token;
+  }
+
+  String output = "";
+  serializeJson(doc, output);
+
+  webSocket.sendTXT(output);
+  lastTelemetry = millis();
+}
+
+void applyConfigUpdate(uint8_t * text) {
+  JsonDocument config;
+  DeserializationError error = deserializeJson(config, text);
+
+  if (error || config["type"] != "config_update") {
+    return;
+  }
+
+  Serial.println("Config update from server:");
+  serializeJsonPretty(config, Serial);
+
+  // The area is the path of the connection, a new one needs a new connection
+  if (strcmp(device_area, config["area"] | device_area) != 0) {
+    reboot = true;
+  }
+  strlcpy(device_area, config["area"] | device_area, sizeof(device_area));
+  strlcpy(device_description, config["description"] | device_description, sizeof(device_description));
+
+  if (config.containsKey("speaker_volume")) {
+    device_speaker_volume = config["speaker_volume"].as<uint16_t>();
+    myDFPlayer.volume(min((int)device_speaker_volume, 30));
+  }
+  if (config.containsKey("motion_debounce_ms")) {
+    device_motion_debounce_ms = config["motion_debounce_ms"].as<uint32_t>();
+  }
+  if (config.containsKey("heartbeat_interval_s")) {
+    device_heartbeat_interval_s = config["heartbeat_interval_s"].as<uint16_t>();
+    webSocket.enableHeartbeat(device_heartbeat_interval_s * 1000UL, 3000, 2);
+  }
+  if (config.containsKey("server_address")) {
+    String address = config["server_address"].as<String>();
+    int separator = address.lastIndexOf(':');
+    String ip = address.substring(0, separator);
+    uint16_t port = (uint16_t)address.substring(separator + 1).toInt();
+
+    if (ip != server_ip || port != server_port) {
+      strlcpy(server_ip, ip.c_str(), sizeof(server_ip));
+      server_port = port;
+      reboot = true;
+    }
+  }
+
+  device_config_version = config["version"].as<long>();
+  saveConfigFile();
+  sendConfigAck();
+}
+
+// Commands of the server, each is answered with its request id.
+void handleCommand(uint8_t * text) {
+  JsonDocument command;
+  DeserializationError error = deserializeJson(command, text);
+
+  if (error || command["type"] != "command") {
+    return;
+  }
+
+  JsonDocument response;
+  response["request_id"] = command["request_id"];
+  response["ok"] = true;
+
+  String name = command["command"].as<String>();
+  Serial.print("Command: ");
+  Serial.println(name);
+
+  if (name == "reboot") {
+    // Answered first, the restart happens after the message is handled
+    reboot = true;
+  } else if (name == "identify") {
+    for (int i = 0; i < 5; i++) {
+      digitalWrite(LED_BUILTIN, LOW);
+      delay(200);
+      digitalWrite(LED_BUILTIN, HIGH);
+      delay(200);
+    }
+    myDFPlayer.play(2);
+  } else if (name == "self_test") {
+    myDFPlayer.play(1);
+  } else if (name == "diagnostics") {
+    response["diagnostics"]["free_heap"] = ESP.getFreeHeap();
+    response["diagnostics"]["rssi"] = WiFi.RSSI();
+    response["diagnostics"]["uptime_s"] = millis() / 1000;
+    response["diagnostics"]["firmware_version"] = FIRMWARE_VERSION;
+  } else {
+    response["ok"] = false;
+    response["error"] = "Unbekannter Befehl";
+  }
+
+  String output = "";
+  serializeJson(response, output);
+
+  webSocket.sendTXT(output);
+}
+
+void sendUpdateStatus(long rollout_id, const char * status, String error = "") {
+  JsonDocument doc;
+
+  doc["device"]["id"] = device_id;
+  doc["device"]["uuid"] = device_uuid;
+  doc["device"]["description"] = device_description;
+  doc["device"]["area"] = device_area;
+  doc["rollout_id"] = rollout_id;
+  doc["status"] = status;
+  if (error.length() > 0) {
+    doc["error"] = error;
+  }
+  if (strlen(device_token) > 0) {
+    doc["token"] = device_token;
+  }
+
+  String output = "";
+  serializeJson(doc, output);
+
+  webSocket.sendTXT(output);
+}
+
+// Installs a firmware offered by the server, the image is checked against the MD5 the server sends.
+void handleUpdate(uint8_t * text) {
+  JsonDocument offer;
+  DeserializationError error = deserializeJson(offer, text);
+
+  if (error || offer["type"] != "update_available") {
+    return;
+  }
+
+  long rollout_id = offer["rollout_id"].as<long>();
+  Serial.print("Firmware update to ");
+  Serial.println(offer["version"].as<const char*>());
+
+  sendUpdateStatus(rollout_id, "downloading");
+  update_rollout_id = rollout_id;
+  saveConfigFile();
+
+  // Restarts right away on success
+  WiFiClient client;
+  t_httpUpdate_return result = ESPhttpUpdate.update(client, offer["url"].as<String>(), FIRMWARE_VERSION);
+
+  update_rollout_id = 0;
+  saveConfigFile();
+
+  if (result == HTTP_UPDATE_NO_UPDATES) {
+    sendUpdateStatus(rollout_id, "failed", "Kein Update erhalten");
+  } else {
+    sendUpdateStatus(rollout_id, "failed", ESPhttpUpdate.getLastErrorString());
+  }
+}
+
 void alert(uint8_t * text) {
   JsonDocument alert;
   DeserializationError error = deserializeJson(alert, text);
@@ -315,6 +538,8 @@ void webSocketEvent(WStype_t type, uint8_t * payload, size_t length) {
         doc["uuid"] = "39472baa-7bcf-4d56-9829-f17bb5b543cd";
         doc["description"] = device_description;
         doc["area"] = device_area;
+        // Lets the server return the same device if it never got to save the uuid
+        doc["hardware_id"] = WiFi.macAddress();
 
         String output = "";
 
@@ -323,6 +548,15 @@ void webSocketEvent(WStype_t type, uint8_t * payload, size_t length) {
         webSocket.sendTXT(output);
       } else {
         Serial.println("Device already registered.");
+        sendConfigAck();
+        sendTelemetry();
+
+        // Running the new firmware after an update
+        if (update_rollout_id != 0) {
+          sendUpdateStatus(update_rollout_id, "succeeded");
+          update_rollout_id = 0;
+          saveConfigFile();
+        }
       }
 			break;
 		case WStype_TEXT:
@@ -331,6 +565,9 @@ void webSocketEvent(WStype_t type, uint8_t * payload, size_t length) {
 
       // Check for json messages
       updateDeviceConfigFromServer(payload);
+      applyConfigUpdate(payload);
+      handleCommand(payload);
+      handleUpdate(payload);
       alert(payload);
 
 
@@ -385,6 +622,9 @@ void sendDetectionMessage(String source) {
   doc["device"]["description"] = device_description;
   doc["device"]["area"] = device_area;
   doc["source"] = source;
+  if (strlen(device_token) > 0) {
+    doc["token"] = device_token;
+  }
 
   String output = "";
   serializeJsonPretty(doc, output);
@@ -499,7 +739,7 @@ void setup() {
 	// server address, port and URL
   char uri [52];
   strcpy(uri, "/");
-  strcpy(uri, device_area);
+  strncat(uri, device_area, sizeof(uri) - 2);
 
 	webSocket.begin(server_ip, server_port, uri);
 
@@ -513,10 +753,10 @@ void setup() {
 	webSocket.setReconnectInterval(5000);
 
   // start heartbeat (optional)
-  // ping server every 15000 ms
+  // ping server every heartbeat interval (15 s unless the server configures it)
   // expect pong from server within 3000 ms
   // consider connection disconnected if pong is not received 2 times
-  webSocket.enableHeartbeat(15000, 3000, 2);
+  webSocket.enableHeartbeat(device_heartbeat_interval_s * 1000UL, 3000, 2);
 }
 
 void loop() {
@@ -524,12 +764,13 @@ void loop() {
 	webSocket.loop();
 
   if(digitalRead(motionPin) == HIGH) {
-    if(pirState == LOW) {
+    if(pirState == LOW && millis() - lastMotion >= device_motion_debounce_ms) {
       digitalWrite(LED_BUILTIN, LOW);
 
       Serial.println("Bewegung");
       sendDetectionMessage("Bewegung");
 
+      lastMotion = millis();
       pirState = HIGH;
     }
   } else {
@@ -541,6 +782,10 @@ void loop() {
     }
   }
 
+  if (connected && strcmp(device_uuid, "to_register") != 0 && millis() - lastTelemetry >= TELEMETRY_INTERVAL_MS) {
+    sendTelemetry();
+  }
+
   if (myDFPlayer.available()) {
     printAudioDetail(myDFPlayer.readType(), myDFPlayer.read()); //Print the detail message from DFPlayer to handle different errors and states.
   }
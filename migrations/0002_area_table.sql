@@ -0,0 +1,40 @@
+CREATE TABLE Area
+(
+    id bigint NOT NULL GENERATED ALWAYS AS IDENTITY ( INCREMENT 1 START 1 ),
+    slug varchar NOT NULL,
+    name varchar NOT NULL,
+    parent_id bigint,
+    armed boolean NOT NULL DEFAULT true,
+    notify boolean NOT NULL DEFAULT true,
+    notification_topic varchar,
+    notification_priority smallint NOT NULL DEFAULT 3,
+    PRIMARY KEY (id),
+    UNIQUE (slug),
+    FOREIGN KEY (parent_id)
+        REFERENCES area (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE SET NULL
+);
+
+-- Convert the free-form area strings of existing devices into area rows
+INSERT INTO area (slug, name)
+SELECT lower(trim(both '/' from area)), min(trim(both '/' from area))
+FROM device
+GROUP BY lower(trim(both '/' from area));
+
+ALTER TABLE device ADD COLUMN area_id bigint;
+
+UPDATE device
+SET area_id = area.id
+FROM area
+WHERE area.slug = lower(trim(both '/' from device.area));
+
+ALTER TABLE device ALTER COLUMN area_id SET NOT NULL;
+
+ALTER TABLE device
+    ADD FOREIGN KEY (area_id)
+        REFERENCES area (id) MATCH SIMPLE
+        ON UPDATE NO ACTION
+        ON DELETE NO ACTION;
+
+ALTER TABLE device DROP COLUMN area;
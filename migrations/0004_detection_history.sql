@@ -0,0 +1,5 @@
+-- Indices for the detection history: paging newest first, filtering by device, source and time range.
+CREATE INDEX detection_timestamp_idx ON detection (timestamp);
+CREATE INDEX detection_device_timestamp_idx ON detection (device_id, timestamp);
+CREATE INDEX detection_source_timestamp_idx ON detection (source, timestamp);
+CREATE INDEX device_area_id_idx ON device (area_id);
@@ -0,0 +1,20 @@
+-- Detections older than the retention period are moved here when archiving is enabled.
+CREATE TABLE detection_archive
+(
+    id bigint NOT NULL,
+    device_id bigint NOT NULL REFERENCES device (id),
+    source text NOT NULL,
+    timestamp timestamp with time zone NOT NULL,
+    archived_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id)
+);
+
+-- Daily counts of pruned detections, kept forever.
+CREATE TABLE detection_daily
+(
+    day timestamp with time zone NOT NULL,
+    device_id bigint NOT NULL REFERENCES device (id),
+    source text NOT NULL,
+    count bigint NOT NULL,
+    PRIMARY KEY (day, device_id, source)
+);
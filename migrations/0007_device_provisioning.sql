@@ -0,0 +1,16 @@
+-- With approval required, unknown devices wait here until an admin approves them.
+-- area is the name the device asked for, it is only resolved on approval.
+CREATE TABLE pending_device
+(
+    id bigint NOT NULL GENERATED ALWAYS AS IDENTITY ( INCREMENT 1 START 1 ),
+    description varchar NOT NULL,
+    area varchar NOT NULL,
+    hardware_id varchar UNIQUE,
+    request_id varchar UNIQUE,
+    pairing_code varchar NOT NULL,
+    requested_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id)
+);
+
+-- SHA-256 of the token issued to an approved device, detections have to carry the token.
+ALTER TABLE device ADD COLUMN token_hash varchar;
@@ -0,0 +1,15 @@
+-- Settings pushed to a device. A row is created on the first edit, NULL settings are left to the device.
+-- version grows with each edit, applied_version is the last version the device acknowledged.
+CREATE TABLE device_config
+(
+    device_id bigint NOT NULL REFERENCES device (id),
+    version bigint NOT NULL DEFAULT 1,
+    speaker_volume smallint,
+    motion_debounce_ms integer,
+    heartbeat_interval_s integer,
+    server_address varchar,
+    updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    applied_version bigint,
+    applied_at timestamp with time zone,
+    PRIMARY KEY (device_id)
+);
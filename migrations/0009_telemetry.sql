@@ -0,0 +1,16 @@
+-- Reports of the devices about their own state, every value is optional.
+CREATE TABLE telemetry
+(
+    id bigint NOT NULL GENERATED ALWAYS AS IDENTITY ( INCREMENT 1 START 1 ),
+    device_id bigint NOT NULL REFERENCES device (id),
+    timestamp timestamp with time zone NOT NULL,
+    rssi integer,
+    free_heap bigint,
+    uptime_s bigint,
+    reset_reason varchar,
+    firmware_version varchar,
+    supply_voltage double precision,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX telemetry_device_timestamp_idx ON telemetry (device_id, timestamp);
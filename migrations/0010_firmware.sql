@@ -0,0 +1,38 @@
+-- Firmware images for OTA updates, one per version and board.
+CREATE TABLE firmware
+(
+    id bigint NOT NULL GENERATED ALWAYS AS IDENTITY ( INCREMENT 1 START 1 ),
+    version varchar NOT NULL,
+    board varchar NOT NULL,
+    size bigint NOT NULL,
+    sha256 varchar NOT NULL,
+    md5 varchar NOT NULL,
+    image bytea NOT NULL,
+    uploaded_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id),
+    UNIQUE (board, version)
+);
+
+-- A firmware offered to a set of devices, one stage of batch_size devices after the other.
+CREATE TABLE firmware_rollout
+(
+    id bigint NOT NULL GENERATED ALWAYS AS IDENTITY ( INCREMENT 1 START 1 ),
+    firmware_id bigint NOT NULL REFERENCES firmware (id),
+    batch_size integer NOT NULL,
+    stage integer NOT NULL DEFAULT 1,
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (id)
+);
+
+CREATE TABLE firmware_update
+(
+    rollout_id bigint NOT NULL REFERENCES firmware_rollout (id),
+    device_id bigint NOT NULL REFERENCES device (id),
+    stage integer NOT NULL,
+    status text NOT NULL,
+    error varchar,
+    updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (rollout_id, device_id)
+);
+
+CREATE INDEX firmware_update_device_idx ON firmware_update (device_id, status);
@@ -0,0 +1,3 @@
+-- Repeated detections within the debounce window are counted on the detection that raised the alert.
+ALTER TABLE detection ADD COLUMN count integer NOT NULL DEFAULT 1;
+ALTER TABLE detection_archive ADD COLUMN count integer NOT NULL DEFAULT 1;
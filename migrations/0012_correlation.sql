@@ -0,0 +1,27 @@
+-- Optional correlation rule of an area. Detections short of it raise a pre-alarm instead of the full alarm.
+CREATE TABLE correlation_rule
+(
+    area_id bigint NOT NULL REFERENCES area (id),
+    min_detections integer,
+    min_devices integer NOT NULL DEFAULT 1,
+    window_s integer NOT NULL DEFAULT 60,
+    first_source text,
+    then_source text,
+    updated_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (area_id)
+);
+
+-- Detections that didn't satisfy the rule of their area, with how far the area got within the window.
+CREATE TABLE pre_alarm
+(
+    id bigint NOT NULL GENERATED ALWAYS AS IDENTITY ( INCREMENT 1 START 1 ),
+    area_id bigint NOT NULL REFERENCES area (id),
+    device_id bigint NOT NULL REFERENCES device (id),
+    source text NOT NULL,
+    timestamp timestamp with time zone NOT NULL,
+    detections integer NOT NULL,
+    devices integer NOT NULL,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX pre_alarm_area_timestamp_idx ON pre_alarm (area_id, timestamp);
@@ -0,0 +1,48 @@
+-- Sources are stored normalized to their sensor kind, contacts keep whether they opened or closed.
+ALTER TABLE detection ADD COLUMN state text;
+ALTER TABLE detection_archive ADD COLUMN state text;
+
+-- Aliases of the sensor kinds as of this migration, see SensorKind::of.
+CREATE TEMPORARY TABLE sensor_alias
+(
+    alias text NOT NULL PRIMARY KEY,
+    kind  text NOT NULL
+);
+
+INSERT INTO sensor_alias (alias, kind)
+VALUES ('motion', 'motion'), ('bewegung', 'motion'), ('pir', 'motion'), ('radar', 'motion'), ('presence', 'motion'), ('präsenz', 'motion'),
+       ('contact', 'contact'), ('kontakt', 'contact'), ('door', 'contact'), ('window', 'contact'), ('tür', 'contact'), ('tuer', 'contact'),
+       ('fenster', 'contact'), ('reed', 'contact'), ('door_contact', 'contact'), ('window_contact', 'contact'), ('türkontakt', 'contact'),
+       ('fensterkontakt', 'contact'),
+       ('glass_break', 'glass_break'), ('glassbreak', 'glass_break'), ('glass', 'glass_break'), ('glasbruch', 'glass_break'),
+       ('smoke', 'smoke'), ('rauch', 'smoke'), ('rauchmelder', 'smoke'), ('fire', 'smoke'), ('feuer', 'smoke'), ('brand', 'smoke'),
+       ('water_leak', 'water_leak'), ('water', 'water_leak'), ('leak', 'water_leak'), ('wasser', 'water_leak'), ('leck', 'water_leak'),
+       ('wassermelder', 'water_leak'),
+       ('panic_button', 'panic_button'), ('panic', 'panic_button'), ('panik', 'panic_button'), ('sos', 'panic_button'),
+       ('notruf', 'panic_button'), ('emergency', 'panic_button'),
+       ('tamper', 'tamper'), ('sabotage', 'tamper');
+
+-- Unknown sources are kept in lower case
+UPDATE detection SET source = replace(replace(lower(trim(source)), ' ', '_'), '-', '_');
+UPDATE detection SET source = sensor_alias.kind FROM sensor_alias WHERE sensor_alias.alias = detection.source;
+UPDATE detection_archive SET source = replace(replace(lower(trim(source)), ' ', '_'), '-', '_');
+UPDATE detection_archive SET source = sensor_alias.kind FROM sensor_alias WHERE sensor_alias.alias = detection_archive.source;
+UPDATE pre_alarm SET source = replace(replace(lower(trim(source)), ' ', '_'), '-', '_');
+UPDATE pre_alarm SET source = sensor_alias.kind FROM sensor_alias WHERE sensor_alias.alias = pre_alarm.source;
+UPDATE correlation_rule SET first_source = replace(replace(lower(trim(first_source)), ' ', '_'), '-', '_'),
+                            then_source = replace(replace(lower(trim(then_source)), ' ', '_'), '-', '_');
+UPDATE correlation_rule SET first_source = sensor_alias.kind FROM sensor_alias WHERE sensor_alias.alias = correlation_rule.first_source;
+UPDATE correlation_rule SET then_source = sensor_alias.kind FROM sensor_alias WHERE sensor_alias.alias = correlation_rule.then_source;
+
+-- Daily counts of sources that are now the same are added up
+CREATE TEMPORARY TABLE normalized_daily AS
+SELECT day, device_id, COALESCE(sensor_alias.kind, normalized.source) AS source, SUM(count) AS count
+FROM (SELECT day, device_id, replace(replace(lower(trim(source)), ' ', '_'), '-', '_') AS source, count FROM detection_daily) AS normalized
+         LEFT JOIN sensor_alias ON sensor_alias.alias = normalized.source
+GROUP BY 1, 2, 3;
+
+DELETE FROM detection_daily;
+INSERT INTO detection_daily (day, device_id, source, count) SELECT day, device_id, source, count FROM normalized_daily;
+
+DROP TABLE normalized_daily;
+DROP TABLE sensor_alias;
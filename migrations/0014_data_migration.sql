@@ -0,0 +1,8 @@
+-- One-off steps the server runs after the migrations, for data SQL can't normalize the way the server does.
+-- A step is recorded once it ran, so it doesn't run on every start.
+CREATE TABLE data_migration
+(
+    name text NOT NULL,
+    applied_at timestamp with time zone NOT NULL DEFAULT now(),
+    PRIMARY KEY (name)
+);
@@ -0,0 +1,16 @@
+-- Alarms the server raised, the alarm history. They are kept when the retention removes their detection,
+-- so detection_id has no foreign key.
+CREATE TABLE incident
+(
+    id bigint NOT NULL GENERATED ALWAYS AS IDENTITY ( INCREMENT 1 START 1 ),
+    area_id bigint NOT NULL REFERENCES area (id),
+    device_id bigint NOT NULL REFERENCES device (id),
+    detection_id bigint,
+    source text NOT NULL,
+    state text,
+    recipients integer NOT NULL,
+    timestamp timestamp with time zone NOT NULL,
+    PRIMARY KEY (id)
+);
+
+CREATE INDEX incident_timestamp_idx ON incident (timestamp);
@@ -0,0 +1,15 @@
+CREATE TABLE device
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid BLOB NOT NULL UNIQUE,
+    area TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+
+CREATE TABLE detection
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL REFERENCES device (id),
+    source TEXT NOT NULL,
+    timestamp TEXT NOT NULL
+);
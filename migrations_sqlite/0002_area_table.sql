@@ -0,0 +1,25 @@
+CREATE TABLE area
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    slug TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    parent_id INTEGER REFERENCES area (id) ON DELETE SET NULL,
+    armed BOOLEAN NOT NULL DEFAULT TRUE,
+    notify BOOLEAN NOT NULL DEFAULT TRUE,
+    notification_topic TEXT,
+    notification_priority INTEGER NOT NULL DEFAULT 3
+);
+
+-- Convert the free-form area strings of existing devices into area rows
+INSERT INTO area (slug, name)
+SELECT lower(trim(area, '/')), min(trim(area, '/'))
+FROM device
+GROUP BY lower(trim(area, '/'));
+
+-- SQLite can't add a NOT NULL column to an existing table, the server always sets it
+ALTER TABLE device ADD COLUMN area_id INTEGER REFERENCES area (id);
+
+UPDATE device
+SET area_id = (SELECT area.id FROM area WHERE area.slug = lower(trim(device.area, '/')));
+
+ALTER TABLE device DROP COLUMN area;
@@ -0,0 +1,4 @@
+-- Areas form a zone tree (building > floor > room) through parent_id.
+-- With propagate set, alerts of an area are also sent to the sirens of its parent zone.
+ALTER TABLE area ADD COLUMN kind TEXT NOT NULL DEFAULT 'room';
+ALTER TABLE area ADD COLUMN propagate BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,19 @@
+-- Detections older than the retention period are moved here when archiving is enabled.
+CREATE TABLE detection_archive
+(
+    id INTEGER PRIMARY KEY,
+    device_id INTEGER NOT NULL REFERENCES device (id),
+    source TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    archived_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'))
+);
+
+-- Daily counts of pruned detections, kept forever.
+CREATE TABLE detection_daily
+(
+    day TEXT NOT NULL,
+    device_id INTEGER NOT NULL REFERENCES device (id),
+    source TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (day, device_id, source)
+);
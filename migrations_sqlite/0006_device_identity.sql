@@ -0,0 +1,7 @@
+-- Registrations are idempotent: a device that registers again with the same hardware id (MAC or chip id)
+-- or the same request id gets its existing row back.
+ALTER TABLE device ADD COLUMN hardware_id TEXT;
+ALTER TABLE device ADD COLUMN request_id TEXT;
+
+CREATE UNIQUE INDEX device_hardware_id_idx ON device (hardware_id);
+CREATE UNIQUE INDEX device_request_id_idx ON device (request_id);
@@ -0,0 +1,15 @@
+-- With approval required, unknown devices wait here until an admin approves them.
+-- area is the name the device asked for, it is only resolved on approval.
+CREATE TABLE pending_device
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    description TEXT NOT NULL,
+    area TEXT NOT NULL,
+    hardware_id TEXT UNIQUE,
+    request_id TEXT UNIQUE,
+    pairing_code TEXT NOT NULL,
+    requested_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'))
+);
+
+-- SHA-256 of the token issued to an approved device, detections have to carry the token.
+ALTER TABLE device ADD COLUMN token_hash TEXT;
@@ -0,0 +1,14 @@
+-- Settings pushed to a device. A row is created on the first edit, NULL settings are left to the device.
+-- version grows with each edit, applied_version is the last version the device acknowledged.
+CREATE TABLE device_config
+(
+    device_id INTEGER PRIMARY KEY REFERENCES device (id),
+    version INTEGER NOT NULL DEFAULT 1,
+    speaker_volume INTEGER,
+    motion_debounce_ms INTEGER,
+    heartbeat_interval_s INTEGER,
+    server_address TEXT,
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')),
+    applied_version INTEGER,
+    applied_at TEXT
+);
@@ -0,0 +1,15 @@
+-- Reports of the devices about their own state, every value is optional.
+CREATE TABLE telemetry
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL REFERENCES device (id),
+    timestamp TEXT NOT NULL,
+    rssi INTEGER,
+    free_heap INTEGER,
+    uptime_s INTEGER,
+    reset_reason TEXT,
+    firmware_version TEXT,
+    supply_voltage REAL
+);
+
+CREATE INDEX telemetry_device_timestamp_idx ON telemetry (device_id, timestamp);
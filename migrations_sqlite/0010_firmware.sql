@@ -0,0 +1,36 @@
+-- Firmware images for OTA updates, one per version and board.
+CREATE TABLE firmware
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    version TEXT NOT NULL,
+    board TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    sha256 TEXT NOT NULL,
+    md5 TEXT NOT NULL,
+    image BLOB NOT NULL,
+    uploaded_at TEXT NOT NULL,
+    UNIQUE (board, version)
+);
+
+-- A firmware offered to a set of devices, one stage of batch_size devices after the other.
+CREATE TABLE firmware_rollout
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    firmware_id INTEGER NOT NULL REFERENCES firmware (id),
+    batch_size INTEGER NOT NULL,
+    stage INTEGER NOT NULL DEFAULT 1,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE firmware_update
+(
+    rollout_id INTEGER NOT NULL REFERENCES firmware_rollout (id),
+    device_id INTEGER NOT NULL REFERENCES device (id),
+    stage INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (rollout_id, device_id)
+);
+
+CREATE INDEX firmware_update_device_idx ON firmware_update (device_id, status);
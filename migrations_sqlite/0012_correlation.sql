@@ -0,0 +1,25 @@
+-- Optional correlation rule of an area. Detections short of it raise a pre-alarm instead of the full alarm.
+CREATE TABLE correlation_rule
+(
+    area_id INTEGER PRIMARY KEY REFERENCES area (id),
+    min_detections INTEGER,
+    min_devices INTEGER NOT NULL DEFAULT 1,
+    window_s INTEGER NOT NULL DEFAULT 60,
+    first_source TEXT,
+    then_source TEXT,
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'))
+);
+
+-- Detections that didn't satisfy the rule of their area, with how far the area got within the window.
+CREATE TABLE pre_alarm
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    area_id INTEGER NOT NULL REFERENCES area (id),
+    device_id INTEGER NOT NULL REFERENCES device (id),
+    source TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    detections INTEGER NOT NULL,
+    devices INTEGER NOT NULL
+);
+
+CREATE INDEX pre_alarm_area_timestamp_idx ON pre_alarm (area_id, timestamp);
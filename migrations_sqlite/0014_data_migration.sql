@@ -0,0 +1,7 @@
+-- One-off steps the server runs after the migrations, for data SQL can't normalize the way the server does.
+-- A step is recorded once it ran, so it doesn't run on every start.
+CREATE TABLE data_migration
+(
+    name TEXT PRIMARY KEY,
+    applied_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now'))
+);
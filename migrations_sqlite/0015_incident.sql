@@ -0,0 +1,15 @@
+-- Alarms the server raised, the alarm history. They are kept when the retention removes their detection,
+-- so detection_id has no foreign key.
+CREATE TABLE incident
+(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    area_id INTEGER NOT NULL REFERENCES area (id),
+    device_id INTEGER NOT NULL REFERENCES device (id),
+    detection_id INTEGER,
+    source TEXT NOT NULL,
+    state TEXT,
+    recipients INTEGER NOT NULL,
+    timestamp TEXT NOT NULL
+);
+
+CREATE INDEX incident_timestamp_idx ON incident (timestamp);